@@ -0,0 +1,23 @@
+CREATE TABLE movie_media (
+  movie_id INTEGER PRIMARY KEY NOT NULL REFERENCES movies(id),
+  container TEXT NOT NULL,
+  duration_ms BIGINT,
+  bit_rate BIGINT,
+  probed_date DATETIME NOT NULL
+);
+
+CREATE TABLE media_streams (
+  id INTEGER PRIMARY KEY NOT NULL,
+  movie_id INTEGER NOT NULL REFERENCES movies(id),
+  stream_index INTEGER NOT NULL,
+  kind TEXT NOT NULL,
+  codec TEXT NOT NULL,
+  language TEXT,
+  width INTEGER,
+  height INTEGER,
+  channels INTEGER,
+  sample_rate INTEGER,
+  bit_rate BIGINT
+);
+
+CREATE INDEX media_streams_movie_id_index ON media_streams (movie_id);
@@ -0,0 +1,10 @@
+CREATE TABLE media_chapters (
+  id INTEGER PRIMARY KEY NOT NULL,
+  movie_id INTEGER NOT NULL REFERENCES movies(id),
+  chapter_index INTEGER NOT NULL,
+  start_ms BIGINT NOT NULL,
+  end_ms BIGINT,
+  title TEXT
+);
+
+CREATE INDEX media_chapters_movie_id_index ON media_chapters (movie_id);
@@ -0,0 +1,12 @@
+-- SQLite can't drop columns, so rebuild the table without them.
+DROP INDEX movies_file_size_index;
+CREATE TABLE movies_backup (
+  id INTEGER PRIMARY KEY NOT NULL,
+  title TEXT NOT NULL,
+  file_path TEXT NOT NULL,
+  created_date DATETIME NOT NULL,
+  CONSTRAINT unique_file_path_constraint UNIQUE (file_path)
+);
+INSERT INTO movies_backup SELECT id, title, file_path, created_date FROM movies;
+DROP TABLE movies;
+ALTER TABLE movies_backup RENAME TO movies;
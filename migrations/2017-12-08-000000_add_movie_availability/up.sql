@@ -0,0 +1,4 @@
+ALTER TABLE movies ADD COLUMN available BOOLEAN NOT NULL DEFAULT 1;
+ALTER TABLE movies ADD COLUMN file_size BIGINT;
+
+CREATE INDEX movies_file_size_index ON movies (file_size);
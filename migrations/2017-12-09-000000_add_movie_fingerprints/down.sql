@@ -0,0 +1,15 @@
+-- SQLite can't drop columns, so rebuild the table without it.
+DROP INDEX movies_fingerprint_index;
+CREATE TABLE movies_backup (
+  id INTEGER PRIMARY KEY NOT NULL,
+  title TEXT NOT NULL,
+  file_path TEXT NOT NULL,
+  created_date DATETIME NOT NULL,
+  available BOOLEAN NOT NULL DEFAULT 1,
+  file_size BIGINT,
+  CONSTRAINT unique_file_path_constraint UNIQUE (file_path)
+);
+INSERT INTO movies_backup SELECT id, title, file_path, created_date, available, file_size FROM movies;
+DROP TABLE movies;
+ALTER TABLE movies_backup RENAME TO movies;
+CREATE INDEX movies_file_size_index ON movies (file_size);
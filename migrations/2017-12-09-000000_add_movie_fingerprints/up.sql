@@ -0,0 +1,4 @@
+ALTER TABLE movies ADD COLUMN fingerprint TEXT;
+
+DROP INDEX movies_file_size_index;
+CREATE INDEX movies_fingerprint_index ON movies (file_size, fingerprint);
@@ -0,0 +1,2 @@
+ALTER TABLE movies ADD COLUMN file_modified BIGINT;
+ALTER TABLE movies ADD COLUMN file_inode BIGINT;
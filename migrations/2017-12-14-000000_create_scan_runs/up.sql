@@ -0,0 +1,4 @@
+CREATE TABLE scan_runs (
+  library TEXT PRIMARY KEY NOT NULL,
+  last_run DATETIME NOT NULL
+);
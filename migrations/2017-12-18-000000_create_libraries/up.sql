@@ -0,0 +1,10 @@
+CREATE TABLE libraries (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  kind TEXT NOT NULL,
+  root_paths TEXT NOT NULL,
+  include_globs TEXT NOT NULL DEFAULT '[]',
+  exclude_globs TEXT NOT NULL DEFAULT '[]',
+  watch BOOLEAN NOT NULL DEFAULT 1,
+  scan_schedule TEXT
+);
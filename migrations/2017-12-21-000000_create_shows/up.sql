@@ -0,0 +1,31 @@
+CREATE TABLE shows (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  title TEXT NOT NULL,
+  year INTEGER,
+  created_date DATETIME NOT NULL
+);
+
+CREATE TABLE seasons (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  show_id INTEGER NOT NULL REFERENCES shows (id),
+  season_number INTEGER NOT NULL,
+  UNIQUE (show_id, season_number)
+);
+
+CREATE TABLE episodes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  season_id INTEGER NOT NULL REFERENCES seasons (id),
+  episode_number INTEGER,
+  last_episode_number INTEGER,
+  absolute_number INTEGER,
+  air_date DATE,
+  title TEXT,
+  file_path TEXT NOT NULL UNIQUE,
+  created_date DATETIME NOT NULL,
+  available BOOLEAN NOT NULL DEFAULT 1,
+  file_size BIGINT,
+  file_modified BIGINT,
+  file_inode BIGINT
+);
+
+CREATE INDEX episodes_season_index ON episodes (season_id);
@@ -0,0 +1,9 @@
+CREATE TABLE movie_metadata (
+  movie_id INTEGER PRIMARY KEY NOT NULL REFERENCES movies(id),
+  tmdb_id INTEGER NOT NULL,
+  overview TEXT,
+  release_date DATE,
+  poster_path TEXT,
+  backdrop_path TEXT,
+  fetched_date DATETIME NOT NULL
+);
@@ -0,0 +1,32 @@
+-- SQLite can't drop columns, so rebuild the tables without them.
+CREATE TABLE movie_metadata_tmdb (
+  movie_id INTEGER PRIMARY KEY NOT NULL REFERENCES movies(id),
+  tmdb_id INTEGER NOT NULL,
+  overview TEXT,
+  release_date DATE,
+  poster_path TEXT,
+  backdrop_path TEXT,
+  fetched_date DATETIME NOT NULL
+);
+INSERT INTO movie_metadata_tmdb
+  SELECT movie_id, tmdb_id, overview, release_date,
+    replace(poster, 'https://image.tmdb.org/t/p/original', ''),
+    replace(backdrop, 'https://image.tmdb.org/t/p/original', ''),
+    fetched_date
+  FROM movie_metadata WHERE tmdb_id IS NOT NULL;
+DROP TABLE movie_metadata;
+ALTER TABLE movie_metadata_tmdb RENAME TO movie_metadata;
+
+CREATE TABLE libraries_backup (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  name TEXT NOT NULL UNIQUE,
+  kind TEXT NOT NULL,
+  root_paths TEXT NOT NULL,
+  include_globs TEXT NOT NULL DEFAULT '[]',
+  exclude_globs TEXT NOT NULL DEFAULT '[]',
+  watch BOOLEAN NOT NULL DEFAULT 1,
+  scan_schedule TEXT
+);
+INSERT INTO libraries_backup SELECT id, name, kind, root_paths, include_globs, exclude_globs, watch, scan_schedule FROM libraries;
+DROP TABLE libraries;
+ALTER TABLE libraries_backup RENAME TO libraries;
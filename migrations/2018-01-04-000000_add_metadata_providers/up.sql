@@ -0,0 +1,25 @@
+ALTER TABLE libraries ADD COLUMN metadata_providers TEXT NOT NULL DEFAULT '["nfo","tmdb","omdb"]';
+
+-- Metadata is now merged from several providers, so any field may be
+-- missing and artwork is stored as a URL or local path.
+CREATE TABLE movie_metadata_merged (
+  movie_id INTEGER PRIMARY KEY NOT NULL REFERENCES movies(id),
+  title TEXT,
+  year INTEGER,
+  overview TEXT,
+  release_date DATE,
+  poster TEXT,
+  backdrop TEXT,
+  tmdb_id INTEGER,
+  imdb_id TEXT,
+  sources TEXT NOT NULL,
+  fetched_date DATETIME NOT NULL
+);
+INSERT INTO movie_metadata_merged
+  SELECT movie_id, NULL, NULL, overview, release_date,
+    'https://image.tmdb.org/t/p/original' || poster_path,
+    'https://image.tmdb.org/t/p/original' || backdrop_path,
+    tmdb_id, NULL, 'tmdb', fetched_date
+  FROM movie_metadata;
+DROP TABLE movie_metadata;
+ALTER TABLE movie_metadata_merged RENAME TO movie_metadata;
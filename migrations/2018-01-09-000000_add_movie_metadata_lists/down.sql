@@ -0,0 +1,19 @@
+-- SQLite can't drop columns, so rebuild the table without them.
+CREATE TABLE movie_metadata_backup (
+  movie_id INTEGER PRIMARY KEY NOT NULL REFERENCES movies(id),
+  title TEXT,
+  year INTEGER,
+  overview TEXT,
+  release_date DATE,
+  poster TEXT,
+  backdrop TEXT,
+  tmdb_id INTEGER,
+  imdb_id TEXT,
+  sources TEXT NOT NULL,
+  fetched_date DATETIME NOT NULL
+);
+INSERT INTO movie_metadata_backup
+  SELECT movie_id, title, year, overview, release_date, poster, backdrop, tmdb_id, imdb_id, sources, fetched_date
+  FROM movie_metadata;
+DROP TABLE movie_metadata;
+ALTER TABLE movie_metadata_backup RENAME TO movie_metadata;
@@ -0,0 +1,3 @@
+ALTER TABLE movie_metadata ADD COLUMN genres TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE movie_metadata ADD COLUMN actors TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE movie_metadata ADD COLUMN ratings TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,6 @@
+CREATE TABLE provider_responses (
+  url TEXT PRIMARY KEY NOT NULL,
+  body TEXT NOT NULL,
+  fetched_date DATETIME NOT NULL,
+  expires_date DATETIME NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE scan_runs_backup (
+  library TEXT PRIMARY KEY NOT NULL,
+  last_run DATETIME NOT NULL
+);
+INSERT INTO scan_runs_backup
+  SELECT libraries.name, scan_runs.last_run
+  FROM scan_runs JOIN libraries ON libraries.id = scan_runs.library_id;
+DROP TABLE scan_runs;
+ALTER TABLE scan_runs_backup RENAME TO scan_runs;
@@ -0,0 +1,10 @@
+-- Libraries can be renamed, so key the last runs on the library id.
+CREATE TABLE scan_runs_backup (
+  library_id INTEGER PRIMARY KEY NOT NULL REFERENCES libraries(id),
+  last_run DATETIME NOT NULL
+);
+INSERT INTO scan_runs_backup
+  SELECT libraries.id, scan_runs.last_run
+  FROM scan_runs JOIN libraries ON libraries.name = scan_runs.library;
+DROP TABLE scan_runs;
+ALTER TABLE scan_runs_backup RENAME TO scan_runs;
@@ -0,0 +1,93 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use rocket::Route;
+use rocket_contrib::JsonValue;
+
+use data::init::establish_connection;
+use data::models;
+use data::movies::fingerprinted_movies;
+
+#[derive(Serialize)]
+pub struct DuplicateFile {
+    pub id: i32,
+    pub title: String,
+    pub file_path: String,
+}
+
+#[derive(Serialize)]
+pub struct Duplicate {
+    pub fingerprint: String,
+    pub file_size: i64,
+    pub files: Vec<DuplicateFile>,
+}
+
+/// Groups movies with the same size and fingerprint, keeping only groups
+/// with more than one file. Expects copies to be next to each other.
+pub fn group_duplicates(movies: Vec<models::Movie>) -> Vec<Duplicate> {
+    let mut groups : Vec<Duplicate> = Vec::new();
+    for movie in movies {
+        let (file_size, fingerprint) = match (movie.file_size, movie.fingerprint) {
+            (Some(file_size), Some(fingerprint)) => (file_size, fingerprint),
+            _ => continue,
+        };
+        let file = DuplicateFile { id: movie.id, title: movie.title, file_path: movie.file_path };
+        let same = match groups.last() {
+            Some(group) => group.file_size == file_size && group.fingerprint == fingerprint,
+            None => false,
+        };
+        if same {
+            groups.last_mut().unwrap().files.push(file);
+        } else {
+            groups.push(Duplicate { fingerprint: fingerprint, file_size: file_size, files: vec![file] });
+        }
+    }
+    groups.into_iter().filter(|group| group.files.len() > 1).collect()
+}
+
+#[get("/duplicates")]
+pub fn duplicates() -> JsonValue {
+    let conn = establish_connection();
+    json!({
+        "duplicates": group_duplicates(fingerprinted_movies(&conn)),
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![duplicates]
+}
+
+#[cfg(test)]
+fn movie(id: i32, file_size: i64, fingerprint: &str) -> models::Movie {
+    use chrono::NaiveDate;
+    models::Movie {
+        id: id,
+        title: format!("Movie {}", id),
+        file_path: format!("/movies/{}.mkv", id),
+        created_date: NaiveDate::from_ymd(2017, 12, 1).and_hms(0, 0, 0),
+        available: true,
+        file_size: Some(file_size),
+        fingerprint: Some(fingerprint.to_string()),
+        file_modified: None,
+        file_inode: None,
+    }
+}
+
+#[test]
+fn only_repeated_content_is_reported() {
+    let duplicates = group_duplicates(vec![
+        movie(1, 100, "a"),
+        movie(2, 100, "a"),
+        movie(3, 100, "b"),
+        movie(4, 200, "b"),
+        movie(5, 300, "c"),
+        movie(6, 300, "c"),
+        movie(7, 300, "c"),
+    ]);
+    let ids : Vec<Vec<i32>> = duplicates.iter().map(|d| d.files.iter().map(|f| f.id).collect()).collect();
+    assert_eq!(vec![vec![1, 2], vec![5, 6, 7]], ids);
+    assert_eq!("a", duplicates[0].fingerprint);
+}
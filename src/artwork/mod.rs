@@ -0,0 +1,124 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+pub mod store;
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rocket::{Route, State};
+use rocket::request::Request;
+use rocket::response::{Response, Responder};
+use rocket::http::Status;
+
+use artwork::store::{local_artwork, local_source, ArtworkStore, ImageKind};
+use data::init::establish_connection;
+use data::metadata::get_movie_metadata;
+use data::movies::find_movie;
+use partial_file::{serve_partial, PartialFile};
+
+/// The URL stays the same when a movie's artwork changes, so clients keep
+/// images but check them with the validators before each use.
+const CACHE_CONTROL: &'static str = "no-cache";
+
+/// Downloads movie artwork on first request and keeps it, with resized
+/// variants, in a cache on disk.
+pub struct ArtworkCache {
+    store: ArtworkStore,
+}
+
+impl ArtworkCache {
+    pub fn new(cache_dir: PathBuf) -> ArtworkCache {
+        ArtworkCache { store: ArtworkStore::new(cache_dir) }
+    }
+
+    /// Reads `CAROLUS_ARTWORK_CACHE` (default a directory under the system
+    /// temp dir).
+    pub fn from_env() -> ArtworkCache {
+        let cache_dir = env::var_os("CAROLUS_ARTWORK_CACHE").map(PathBuf::from)
+            .unwrap_or_else(|| env::temp_dir().join("carolus-artwork"));
+        ArtworkCache::new(cache_dir)
+    }
+
+    /// The cached image for a source, fetching it if this is the first time
+    /// it is asked for.
+    pub fn image(&self, source: &str, kind: ImageKind, width: Option<u32>) -> io::Result<Option<PathBuf>> {
+        let key = self.store.fetch(source, kind)?;
+        Ok(self.store.path(&key, kind, width))
+    }
+}
+
+/// Artwork next to the movie wins over what the metadata providers found.
+fn artwork_source(movie_id: i32, kind: ImageKind) -> Option<String> {
+    let conn = establish_connection();
+    let movie = match find_movie(&conn, movie_id as i64) {
+        Some(movie) => movie,
+        None => return None,
+    };
+    let movie_path = Path::new(&movie.file_path);
+    if let Some(local) = local_artwork(movie_path, kind) {
+        return local.to_str().map(String::from);
+    }
+    let source = get_movie_metadata(&conn, movie.id).and_then(|metadata| match kind {
+        ImageKind::Poster => metadata.poster,
+        ImageKind::Backdrop => metadata.backdrop,
+    });
+    match source {
+        Some(source) => if source.starts_with("http://") || source.starts_with("https://") {
+            Some(source)
+        } else {
+            local_source(movie_path, &source).and_then(|path| path.to_str().map(String::from))
+        },
+        None => None,
+    }
+}
+
+/// A cached image, served with the usual validators plus a
+/// `Cache-Control` asking clients to revalidate.
+pub struct Artwork(PartialFile);
+
+impl Responder<'static> for Artwork {
+    fn respond_to(self, req: &Request) -> Result<Response<'static>, Status> {
+        let mut response = self.0.respond_to(req)?;
+        response.set_raw_header("Cache-Control", CACHE_CONTROL);
+        Ok(response)
+    }
+}
+
+#[derive(FromForm)]
+pub struct ImageRequest {
+    w: Option<u32>,
+}
+
+fn movie_image(movie_id: i32, kind: &str, width: Option<u32>, cache: &ArtworkCache) -> io::Result<Option<Artwork>> {
+    let kind = match ImageKind::parse(kind) {
+        Some(kind) => kind,
+        None => return Ok(None),
+    };
+    let source = match artwork_source(movie_id, kind) {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+    match cache.image(&source, kind, width)? {
+        Some(path) => serve_partial(&path).map(|file| Some(Artwork(file))),
+        None => Ok(None),
+    }
+}
+
+#[get("/<movie_id>/images/<kind>")]
+pub fn movie_image_original(movie_id: i32, kind: String, cache: State<ArtworkCache>) -> io::Result<Option<Artwork>> {
+    movie_image(movie_id, &kind, None, cache.inner())
+}
+
+#[get("/<movie_id>/images/<kind>?<image_request>")]
+pub fn movie_image_sized(movie_id: i32, kind: String, image_request: ImageRequest, cache: State<ArtworkCache>) -> io::Result<Option<Artwork>> {
+    movie_image(movie_id, &kind, image_request.w, cache.inner())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![movie_image_original, movie_image_sized]
+}
@@ -0,0 +1,363 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use blake2::{Blake2b, Digest};
+use image::{self, ColorType, DynamicImage, FilterType, GenericImage, ImageDecoder, ImageFormat};
+use image::jpeg::JPEGEncoder;
+use reqwest::Client;
+use reqwest::header::ContentLength;
+
+/// Quality of the resized variants, which are always JPEG.
+const JPEG_QUALITY: u8 = 85;
+
+/// Artwork is downloaded from URLs found in NFO files as well as from
+/// providers, so what is read and decoded is limited. The largest original
+/// posters and backdrops are well within both.
+const MAX_ARTWORK_BYTES: u64 = 20 * 1024 * 1024;
+const MAX_ARTWORK_DIMENSION: u32 = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageKind {
+    Poster,
+    Backdrop,
+}
+
+impl ImageKind {
+    pub fn parse(kind: &str) -> Option<ImageKind> {
+        match kind {
+            "poster" => Some(ImageKind::Poster),
+            "backdrop" => Some(ImageKind::Backdrop),
+            _ => None,
+        }
+    }
+
+    /// Widths of the resized variants made of each image, much like the
+    /// sizes TheMovieDB offers.
+    pub fn widths(&self) -> &'static [u32] {
+        match *self {
+            ImageKind::Poster => &[185, 342, 500],
+            ImageKind::Backdrop => &[300, 780, 1280],
+        }
+    }
+
+    /// Names of artwork kept next to movies, without the extension, with
+    /// `{}` standing for the movie's file name.
+    fn local_names(&self) -> &'static [&'static str] {
+        match *self {
+            ImageKind::Poster => &["{}-poster", "poster", "folder", "cover"],
+            ImageKind::Backdrop => &["{}-fanart", "fanart", "backdrop", "background"],
+        }
+    }
+}
+
+const LOCAL_EXTENSIONS: &'static [&'static str] = &["jpg", "jpeg", "png"];
+
+/// Artwork in the movie's folder, `poster.jpg`, `fanart.jpg` and the like,
+/// which is preferred to anything a provider found.
+pub fn local_artwork(movie_path: &Path, kind: ImageKind) -> Option<PathBuf> {
+    let folder = match movie_path.parent() {
+        Some(folder) => folder,
+        None => return None,
+    };
+    let stem = movie_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    for name in kind.local_names() {
+        let name = name.replace("{}", stem);
+        for extension in LOCAL_EXTENSIONS {
+            let path = folder.join(format!("{}.{}", name, extension));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// A non-URL source from a movie's metadata, such as an NFO `<thumb>`,
+/// resolved against the movie's folder. Anything outside it isn't served.
+pub fn local_source(movie_path: &Path, source: &str) -> Option<PathBuf> {
+    let folder = match movie_path.parent().map(|folder| folder.canonicalize()) {
+        Some(Ok(folder)) => folder,
+        _ => return None,
+    };
+    match folder.join(source).canonicalize() {
+        Ok(path) => if path.starts_with(&folder) && path.is_file() { Some(path) } else { None },
+        Err(_) => None,
+    }
+}
+
+/// The smallest variant at least as wide as asked for, or None for the
+/// original when none is wide enough or no width was asked for.
+pub fn variant_width(kind: ImageKind, requested: Option<u32>) -> Option<u32> {
+    match requested {
+        Some(requested) => kind.widths().iter().cloned().find(|&width| width >= requested),
+        None => None,
+    }
+}
+
+/// Where artwork comes from: a URL, or a local file that is keyed by its
+/// size and modification time too so that replacing it is noticed.
+fn source_key(source: &str) -> String {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return source.to_string();
+    }
+    match fs::metadata(source) {
+        Ok(metadata) => {
+            let modified = metadata.modified().ok()
+                .and_then(|modified| modified.duration_since(::std::time::UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs());
+            format!("{}:{}:{}", source, metadata.len(), modified)
+        },
+        Err(_) => source.to_string(),
+    }
+}
+
+/// A BLAKE2b hash of the source, which names its files in the cache.
+pub fn cache_key(source: &str) -> String {
+    let mut hasher = Blake2b::default();
+    hasher.input(source_key(source).as_bytes());
+    hasher.result().iter().take(16).map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::PNG => "png",
+        ImageFormat::GIF => "gif",
+        ImageFormat::WEBP => "webp",
+        _ => "jpg",
+    }
+}
+
+static PARTIAL_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A temporary name next to `path`, unique so requests fetching the same
+/// image at once don't write over each other.
+fn partial_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64 ^ d.as_secs())
+        .unwrap_or(0);
+    let count = PARTIAL_COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("image");
+    path.with_file_name(format!("{}.{:016x}{:08x}.partial", name, nanos, count))
+}
+
+/// Writes to a temporary file first, so a request never sees half an
+/// image.
+fn write_atomically(path: &Path, write: &Fn(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let partial = partial_path(path);
+    let result = File::create(&partial)
+        .and_then(|mut file| write(&mut file))
+        .and_then(|()| fs::rename(&partial, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn invalid_image(err: image::ImageError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "artwork too large")
+}
+
+/// Reads up to `max` bytes, failing rather than truncating anything longer.
+fn read_limited<R: Read>(reader: R, max: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(max + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max {
+        return Err(too_large());
+    }
+    Ok(bytes)
+}
+
+/// The width and height from the image's header, read before decoding it
+/// so a small file claiming to be a huge image isn't decoded.
+fn dimensions(bytes: &[u8], format: ImageFormat) -> io::Result<(u32, u32)> {
+    let cursor = io::Cursor::new(bytes);
+    let dimensions = match format {
+        ImageFormat::JPEG => image::jpeg::JPEGDecoder::new(cursor).dimensions(),
+        ImageFormat::PNG => image::png::PNGDecoder::new(cursor).dimensions(),
+        ImageFormat::GIF => image::gif::Decoder::new(cursor).dimensions(),
+        ImageFormat::WEBP => image::webp::WebpDecoder::new(cursor).dimensions(),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported artwork format")),
+    };
+    dimensions.map_err(invalid_image)
+}
+
+/// Originals and resized variants of artwork, stored in a directory per
+/// image named after its cache key.
+pub struct ArtworkStore {
+    dir: PathBuf,
+    client: Client,
+}
+
+impl ArtworkStore {
+    pub fn new(dir: PathBuf) -> ArtworkStore {
+        ArtworkStore { dir: dir, client: Client::new() }
+    }
+
+    fn image_dir(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(key)
+    }
+
+    fn find(&self, key: &str, name: &str) -> Option<PathBuf> {
+        let entries = match fs::read_dir(self.image_dir(key)) {
+            Ok(entries) => entries,
+            Err(_) => return None,
+        };
+        entries.filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|path| path.file_stem().and_then(|stem| stem.to_str()) == Some(name)
+                && path.extension().and_then(|extension| extension.to_str()) != Some("partial"))
+    }
+
+    fn read_source(&self, source: &str) -> io::Result<Vec<u8>> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let response = self.client.get(source).send()
+                .and_then(|response| response.error_for_status())
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+            if response.headers().get::<ContentLength>().map_or(false, |length| length.0 > MAX_ARTWORK_BYTES) {
+                return Err(too_large());
+            }
+            read_limited(response, MAX_ARTWORK_BYTES)
+        } else {
+            read_limited(File::open(source)?, MAX_ARTWORK_BYTES)
+        }
+    }
+
+    /// Downloads or copies the artwork into the cache, making its resized
+    /// variants, unless it is there already.
+    pub fn fetch(&self, source: &str, kind: ImageKind) -> io::Result<String> {
+        let key = cache_key(source);
+        if self.find(&key, "original").is_some() {
+            return Ok(key);
+        }
+        let bytes = self.read_source(source)?;
+        let format = image::guess_format(&bytes).map_err(invalid_image)?;
+        let (width, height) = dimensions(&bytes, format)?;
+        if width > MAX_ARTWORK_DIMENSION || height > MAX_ARTWORK_DIMENSION {
+            return Err(too_large());
+        }
+        let image = image::load_from_memory_with_format(&bytes, format).map_err(invalid_image)?;
+        let dir = self.image_dir(&key);
+        fs::create_dir_all(&dir)?;
+        for &width in kind.widths() {
+            if width < image.width() {
+                write_atomically(&dir.join(format!("w{}.jpg", width)), &|file| resize(&image, width, file))?;
+            }
+        }
+        let original = dir.join(format!("original.{}", extension(format)));
+        write_atomically(&original, &|file| file.write_all(&bytes))?;
+        Ok(key)
+    }
+
+    /// The cached file to serve for a width, the original when there is no
+    /// variant as wide or the image is narrower than the variant.
+    pub fn path(&self, key: &str, kind: ImageKind, width: Option<u32>) -> Option<PathBuf> {
+        match variant_width(kind, width) {
+            Some(width) => self.find(key, &format!("w{}", width)).or_else(|| self.find(key, "original")),
+            None => self.find(key, "original"),
+        }
+    }
+}
+
+fn resize(image: &DynamicImage, width: u32, file: &mut File) -> io::Result<()> {
+    let height = ((image.height() as u64 * width as u64) / image.width() as u64).max(1) as u32;
+    let resized = image.resize_exact(width, height, FilterType::Lanczos3).to_rgb();
+    JPEGEncoder::new_with_quality(file, JPEG_QUALITY)
+        .encode(&resized, width, height, ColorType::RGB(8))
+}
+
+#[test]
+fn variants_are_the_next_size_up() {
+    assert_eq!(Some(185), variant_width(ImageKind::Poster, Some(100)));
+    assert_eq!(Some(342), variant_width(ImageKind::Poster, Some(185 + 1)));
+    assert_eq!(None, variant_width(ImageKind::Poster, Some(2000)));
+    assert_eq!(None, variant_width(ImageKind::Backdrop, None));
+    assert_eq!(Some(1280), variant_width(ImageKind::Backdrop, Some(1280)));
+}
+
+#[test]
+fn partial_files_are_named_apart() {
+    let path = Path::new("/cache/ab/abcd/original.jpg");
+    let (first, second) = (partial_path(path), partial_path(path));
+    assert!(first != second);
+    assert_eq!(path.parent(), first.parent());
+    assert_eq!(Some("partial"), first.extension().and_then(|extension| extension.to_str()));
+}
+
+#[test]
+fn local_artwork_next_to_movies() {
+    let dir = ::tempdir::TempDir::new("carolus_artwork").unwrap();
+    let movie = dir.path().join("Heat (1995).mkv");
+    assert_eq!(None, local_artwork(&movie, ImageKind::Poster));
+    File::create(dir.path().join("folder.jpg")).unwrap();
+    assert_eq!(Some(dir.path().join("folder.jpg")), local_artwork(&movie, ImageKind::Poster));
+    File::create(dir.path().join("Heat (1995)-poster.png")).unwrap();
+    assert_eq!(Some(dir.path().join("Heat (1995)-poster.png")), local_artwork(&movie, ImageKind::Poster));
+    File::create(dir.path().join("fanart.jpg")).unwrap();
+    assert_eq!(Some(dir.path().join("fanart.jpg")), local_artwork(&movie, ImageKind::Backdrop));
+}
+
+#[test]
+fn local_images_are_cached_with_resized_variants() {
+    use image::{ImageBuffer, Rgb};
+    let dir = ::tempdir::TempDir::new("carolus_artwork").unwrap();
+    let poster = dir.path().join("poster.png");
+    let buffer = ImageBuffer::from_fn(400, 600, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+    buffer.save(&poster).unwrap();
+
+    let store = ArtworkStore::new(dir.path().join("cache"));
+    let source = poster.to_str().unwrap();
+    let key = store.fetch(source, ImageKind::Poster).unwrap();
+    assert_eq!(key, store.fetch(source, ImageKind::Poster).unwrap());
+
+    let original = store.path(&key, ImageKind::Poster, None).unwrap();
+    assert_eq!(Some("original.png"), original.file_name().and_then(|name| name.to_str()));
+    let small = store.path(&key, ImageKind::Poster, Some(150)).unwrap();
+    assert_eq!(Some("w185.jpg"), small.file_name().and_then(|name| name.to_str()));
+    assert_eq!((185, 277), image::open(&small).unwrap().dimensions());
+    // Wider than the image, so there's no 500 wide variant
+    assert_eq!(original, store.path(&key, ImageKind::Poster, Some(450)).unwrap());
+}
+
+#[test]
+fn oversized_artwork_is_rejected() {
+    use image::{ImageBuffer, Rgb};
+    assert_eq!(b"poster".to_vec(), read_limited(&b"poster"[..], 6).unwrap());
+    assert!(read_limited(&b"poster"[..], 5).is_err());
+
+    let dir = ::tempdir::TempDir::new("carolus_artwork").unwrap();
+    let banner = dir.path().join("banner.png");
+    ImageBuffer::from_pixel(MAX_ARTWORK_DIMENSION + 1, 1, Rgb([0u8, 0, 0])).save(&banner).unwrap();
+    let store = ArtworkStore::new(dir.path().join("cache"));
+    assert!(store.fetch(banner.to_str().unwrap(), ImageKind::Backdrop).is_err());
+}
+
+#[test]
+fn local_sources_stay_in_the_movie_folder() {
+    let dir = ::tempdir::TempDir::new("carolus_artwork").unwrap();
+    let folder = dir.path().join("Alien (1979)");
+    fs::create_dir(&folder).unwrap();
+    File::create(folder.join("cover.jpg")).unwrap();
+    File::create(dir.path().join("outside.jpg")).unwrap();
+    let movie = folder.join("Alien (1979).mkv");
+
+    let cover = folder.canonicalize().unwrap().join("cover.jpg");
+    assert_eq!(Some(cover.clone()), local_source(&movie, "cover.jpg"));
+    assert_eq!(Some(cover.clone()), local_source(&movie, cover.to_str().unwrap()));
+    assert_eq!(None, local_source(&movie, "../outside.jpg"));
+    assert_eq!(None, local_source(&movie, dir.path().join("outside.jpg").to_str().unwrap()));
+    assert_eq!(None, local_source(&movie, "/etc/passwd"));
+    assert_eq!(None, local_source(&movie, "missing.jpg"));
+}
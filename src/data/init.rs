@@ -7,11 +7,26 @@
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use std::env;
+use std::error::Error;
+
+/// How long a connection waits for another one's write to finish before
+/// giving up with "database is locked".
+const BUSY_TIMEOUT_MS: u32 = 30000;
 
 pub fn establish_connection() -> SqliteConnection {
     let database_url = env::var("DATABASE_URL")
         .expect("DATABASE_URL must be set");
-        
-    SqliteConnection::establish(&database_url)
-        .expect(&format!("Error connecting to {}", database_url))
+
+    connect(&database_url)
+        .unwrap_or_else(|err| panic!("Error connecting to {}: {}", database_url, err))
+}
+
+/// Scans, the watcher and requests each write through their own
+/// connection, so they wait for each other rather than fail, and readers
+/// aren't blocked by a write in progress.
+pub fn connect(database_url: &str) -> Result<SqliteConnection, Box<Error>> {
+    let conn = SqliteConnection::establish(database_url)?;
+    conn.execute("PRAGMA journal_mode = WAL")?;
+    conn.execute(&format!("PRAGMA busy_timeout = {}", BUSY_TIMEOUT_MS))?;
+    Ok(conn)
 }
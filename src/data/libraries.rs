@@ -0,0 +1,79 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use data::models::{Library, NewLibrary};
+use data::schema;
+use diesel::prelude::*;
+use diesel;
+
+pub fn all_libraries(conn: &SqliteConnection) -> Vec<Library> {
+    use data::schema::libraries::dsl::*;
+
+    libraries.order(id)
+        .load::<Library>(conn)
+        .expect("Error loading libraries")
+}
+
+pub fn find_library(conn: &SqliteConnection, library_id: i32) -> Option<Library> {
+    use data::schema::libraries::dsl::*;
+
+    libraries.find(library_id)
+        .first::<Library>(conn)
+        .optional()
+        .expect("Error loading library")
+}
+
+pub fn find_library_by_name(conn: &SqliteConnection, library_name: &str) -> Option<Library> {
+    use data::schema::libraries::dsl::*;
+
+    libraries.filter(name.eq(library_name))
+        .first::<Library>(conn)
+        .optional()
+        .expect("Error loading library")
+}
+
+pub fn create_library(conn: &SqliteConnection, new_library: &NewLibrary) -> Library {
+    use data::schema::libraries::dsl::*;
+
+    diesel::insert(new_library)
+        .into(schema::libraries::table)
+        .execute(conn)
+        .expect("Error saving new library");
+    libraries.filter(name.eq(new_library.name))
+        .first(conn)
+        .expect("Error loading new library")
+}
+
+pub fn update_library(conn: &SqliteConnection, library_id: i32, library: &NewLibrary) -> Option<Library> {
+    use data::schema::libraries::dsl::*;
+
+    diesel::update(libraries.find(library_id))
+        .set((
+            name.eq(library.name),
+            kind.eq(library.kind),
+            root_paths.eq(library.root_paths),
+            include_globs.eq(library.include_globs),
+            exclude_globs.eq(library.exclude_globs),
+            watch.eq(library.watch),
+            scan_schedule.eq(library.scan_schedule),
+            metadata_providers.eq(library.metadata_providers),
+        ))
+        .execute(conn)
+        .expect("Error updating library");
+    libraries.find(library_id)
+        .first(conn)
+        .optional()
+        .expect("Error loading library")
+}
+
+pub fn delete_library(conn: &SqliteConnection, library_id: i32) -> bool {
+    use data::schema::libraries::dsl::*;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(schema::scan_runs::table.find(library_id)).execute(conn)?;
+        diesel::delete(libraries.find(library_id)).execute(conn)
+    }).expect("Error deleting library") > 0
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use data::models::{MediaChapter, MediaStream, MovieMedia, NewMediaChapter, NewMediaStream, NewMovieMedia};
+use data::schema;
+use diesel::prelude::*;
+use chrono::prelude::*;
+use diesel;
+
+use media::probe::MediaInfo;
+
+/// Replaces whatever was probed for the movie before.
+pub fn save_media_info(conn: &SqliteConnection, movie: i32, info: &MediaInfo) -> QueryResult<()> {
+    use data::schema::media_chapters::dsl::{media_chapters, movie_id as chapter_movie_id};
+    use data::schema::media_streams::dsl::{media_streams, movie_id as stream_movie_id};
+    use data::schema::movie_media::dsl::{movie_media, movie_id as media_movie_id};
+
+    let new_media = NewMovieMedia {
+        movie_id: movie,
+        container: info.container,
+        duration_ms: info.duration_ms.map(|d| d as i64),
+        bit_rate: info.bit_rate.map(|b| b as i64),
+        probed_date: Utc::now().naive_utc(),
+    };
+
+    conn.transaction(|| {
+        diesel::delete(media_chapters.filter(chapter_movie_id.eq(movie))).execute(conn)?;
+        diesel::delete(media_streams.filter(stream_movie_id.eq(movie))).execute(conn)?;
+        diesel::delete(movie_media.filter(media_movie_id.eq(movie))).execute(conn)?;
+        diesel::insert(&new_media)
+            .into(schema::movie_media::table)
+            .execute(conn)?;
+        for stream in &info.streams {
+            let new_stream = NewMediaStream {
+                movie_id: movie,
+                stream_index: stream.index as i32,
+                kind: stream.kind.as_str(),
+                codec: &stream.codec,
+                language: stream.language.as_ref().map(String::as_str),
+                width: stream.width.map(|w| w as i32),
+                height: stream.height.map(|h| h as i32),
+                channels: stream.channels.map(|c| c as i32),
+                sample_rate: stream.sample_rate.map(|r| r as i32),
+                bit_rate: stream.bit_rate.map(|b| b as i64),
+            };
+            diesel::insert(&new_stream)
+                .into(schema::media_streams::table)
+                .execute(conn)?;
+        }
+        for (index, chapter) in info.chapters.iter().enumerate() {
+            let new_chapter = NewMediaChapter {
+                movie_id: movie,
+                chapter_index: index as i32,
+                start_ms: chapter.start_ms as i64,
+                end_ms: chapter.end_ms.map(|e| e as i64),
+                title: chapter.title.as_ref().map(String::as_str),
+            };
+            diesel::insert(&new_chapter)
+                .into(schema::media_chapters::table)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn get_media_info(conn: &SqliteConnection, movie: i32) -> Option<(MovieMedia, Vec<MediaStream>)> {
+    use data::schema::media_streams::dsl::{media_streams, movie_id as stream_movie_id, stream_index};
+    use data::schema::movie_media::dsl::movie_media;
+
+    let media = movie_media.find(movie)
+        .first::<MovieMedia>(conn)
+        .optional()
+        .expect("Error loading media info");
+    media.map(|media| {
+        let streams = media_streams.filter(stream_movie_id.eq(movie))
+            .order(stream_index)
+            .load::<MediaStream>(conn)
+            .expect("Error loading media streams");
+        (media, streams)
+    })
+}
+
+pub fn get_chapters(conn: &SqliteConnection, movie: i32) -> Vec<MediaChapter> {
+    use data::schema::media_chapters::dsl::*;
+
+    media_chapters.filter(movie_id.eq(movie))
+        .order(chapter_index)
+        .load::<MediaChapter>(conn)
+        .expect("Error loading chapters")
+}
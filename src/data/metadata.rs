@@ -0,0 +1,32 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use data::models::{MovieMetadata, NewMovieMetadata};
+use data::schema;
+use diesel::prelude::*;
+use diesel;
+
+/// Replaces whatever was looked up for the movie before.
+pub fn save_movie_metadata(conn: &SqliteConnection, metadata: &NewMovieMetadata) -> QueryResult<()> {
+    use data::schema::movie_metadata::dsl::*;
+
+    conn.transaction(|| {
+        diesel::delete(movie_metadata.filter(movie_id.eq(metadata.movie_id))).execute(conn)?;
+        diesel::insert(metadata)
+            .into(schema::movie_metadata::table)
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+pub fn get_movie_metadata(conn: &SqliteConnection, movie: i32) -> Option<MovieMetadata> {
+    use data::schema::movie_metadata::dsl::*;
+
+    movie_metadata.find(movie)
+        .first::<MovieMetadata>(conn)
+        .optional()
+        .expect("Error loading movie metadata")
+}
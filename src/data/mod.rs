@@ -8,3 +8,9 @@ pub mod init;
 pub mod schema;
 pub mod models;
 pub mod movies;
+pub mod media;
+pub mod libraries;
+pub mod scan_runs;
+pub mod shows;
+pub mod metadata;
+pub mod provider_responses;
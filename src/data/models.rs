@@ -4,7 +4,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use data::schema::movies;
+use data::schema::{episodes, libraries, media_chapters, media_streams, movie_media, movie_metadata, movies, provider_responses, scan_runs, seasons, shows};
 use chrono::prelude::*;
 
 #[derive(Queryable)]
@@ -13,6 +13,11 @@ pub struct Movie {
     pub title: String,
     pub file_path: String,
     pub created_date: NaiveDateTime,
+    pub available: bool,
+    pub file_size: Option<i64>,
+    pub fingerprint: Option<String>,
+    pub file_modified: Option<i64>,
+    pub file_inode: Option<i64>,
 }
 
 #[derive(Insertable)]
@@ -21,4 +26,228 @@ pub struct NewMovie<'a> {
     pub title: &'a str,
     pub file_path: &'a str,
     pub created_date: NaiveDateTime,
-}
\ No newline at end of file
+    pub file_size: Option<i64>,
+    pub fingerprint: Option<&'a str>,
+    pub file_modified: Option<i64>,
+    pub file_inode: Option<i64>,
+}
+
+#[derive(Queryable)]
+pub struct MovieMedia {
+    pub movie_id: i32,
+    pub container: String,
+    pub duration_ms: Option<i64>,
+    pub bit_rate: Option<i64>,
+    pub probed_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="movie_media"]
+pub struct NewMovieMedia<'a> {
+    pub movie_id: i32,
+    pub container: &'a str,
+    pub duration_ms: Option<i64>,
+    pub bit_rate: Option<i64>,
+    pub probed_date: NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct MovieMetadata {
+    pub movie_id: i32,
+    pub title: Option<String>,
+    pub year: Option<i32>,
+    pub overview: Option<String>,
+    pub release_date: Option<NaiveDate>,
+    pub poster: Option<String>,
+    pub backdrop: Option<String>,
+    pub tmdb_id: Option<i32>,
+    pub imdb_id: Option<String>,
+    pub sources: String,
+    pub fetched_date: NaiveDateTime,
+    pub genres: String,
+    pub actors: String,
+    pub ratings: String,
+}
+
+#[derive(Insertable)]
+#[table_name="movie_metadata"]
+pub struct NewMovieMetadata<'a> {
+    pub movie_id: i32,
+    pub title: Option<&'a str>,
+    pub year: Option<i32>,
+    pub overview: Option<&'a str>,
+    pub release_date: Option<NaiveDate>,
+    pub poster: Option<&'a str>,
+    pub backdrop: Option<&'a str>,
+    pub tmdb_id: Option<i32>,
+    pub imdb_id: Option<&'a str>,
+    pub sources: &'a str,
+    pub fetched_date: NaiveDateTime,
+    pub genres: &'a str,
+    pub actors: &'a str,
+    pub ratings: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct ProviderResponse {
+    pub url: String,
+    pub body: String,
+    pub fetched_date: NaiveDateTime,
+    pub expires_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="provider_responses"]
+pub struct NewProviderResponse<'a> {
+    pub url: &'a str,
+    pub body: &'a str,
+    pub fetched_date: NaiveDateTime,
+    pub expires_date: NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct MediaStream {
+    pub id: i32,
+    pub movie_id: i32,
+    pub stream_index: i32,
+    pub kind: String,
+    pub codec: String,
+    pub language: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub channels: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub bit_rate: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[table_name="media_streams"]
+pub struct NewMediaStream<'a> {
+    pub movie_id: i32,
+    pub stream_index: i32,
+    pub kind: &'a str,
+    pub codec: &'a str,
+    pub language: Option<&'a str>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub channels: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub bit_rate: Option<i64>,
+}
+
+#[derive(Queryable)]
+pub struct MediaChapter {
+    pub id: i32,
+    pub movie_id: i32,
+    pub chapter_index: i32,
+    pub start_ms: i64,
+    pub end_ms: Option<i64>,
+    pub title: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="media_chapters"]
+pub struct NewMediaChapter<'a> {
+    pub movie_id: i32,
+    pub chapter_index: i32,
+    pub start_ms: i64,
+    pub end_ms: Option<i64>,
+    pub title: Option<&'a str>,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name="scan_runs"]
+pub struct ScanRun {
+    pub library_id: i32,
+    pub last_run: NaiveDateTime,
+}
+
+/// Root paths and globs are stored as JSON arrays.
+#[derive(Queryable)]
+pub struct Library {
+    pub id: i32,
+    pub name: String,
+    pub kind: String,
+    pub root_paths: String,
+    pub include_globs: String,
+    pub exclude_globs: String,
+    pub watch: bool,
+    pub scan_schedule: Option<String>,
+    pub metadata_providers: String,
+}
+
+#[derive(Insertable)]
+#[table_name="libraries"]
+pub struct NewLibrary<'a> {
+    pub name: &'a str,
+    pub kind: &'a str,
+    pub root_paths: &'a str,
+    pub include_globs: &'a str,
+    pub exclude_globs: &'a str,
+    pub watch: bool,
+    pub scan_schedule: Option<&'a str>,
+    pub metadata_providers: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct Show {
+    pub id: i32,
+    pub title: String,
+    pub year: Option<i32>,
+    pub created_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name="shows"]
+pub struct NewShow<'a> {
+    pub title: &'a str,
+    pub year: Option<i32>,
+    pub created_date: NaiveDateTime,
+}
+
+#[derive(Queryable)]
+pub struct Season {
+    pub id: i32,
+    pub show_id: i32,
+    pub season_number: i32,
+}
+
+#[derive(Insertable)]
+#[table_name="seasons"]
+pub struct NewSeason {
+    pub show_id: i32,
+    pub season_number: i32,
+}
+
+#[derive(Queryable)]
+pub struct Episode {
+    pub id: i32,
+    pub season_id: i32,
+    pub episode_number: Option<i32>,
+    pub last_episode_number: Option<i32>,
+    pub absolute_number: Option<i32>,
+    pub air_date: Option<NaiveDate>,
+    pub title: Option<String>,
+    pub file_path: String,
+    pub created_date: NaiveDateTime,
+    pub available: bool,
+    pub file_size: Option<i64>,
+    pub file_modified: Option<i64>,
+    pub file_inode: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[table_name="episodes"]
+pub struct NewEpisode<'a> {
+    pub season_id: i32,
+    pub episode_number: Option<i32>,
+    pub last_episode_number: Option<i32>,
+    pub absolute_number: Option<i32>,
+    pub air_date: Option<NaiveDate>,
+    pub title: Option<&'a str>,
+    pub file_path: &'a str,
+    pub created_date: NaiveDateTime,
+    pub file_size: Option<i64>,
+    pub file_modified: Option<i64>,
+    pub file_inode: Option<i64>,
+}
@@ -9,38 +9,55 @@ use data::schema;
 use diesel::prelude::*;
 use chrono::prelude::*;
 use diesel;
+use std::path::{Path, MAIN_SEPARATOR};
 
-pub fn create_movie<'a>(conn: &SqliteConnection, movie_title: &'a str, movie_file_path: &'a str) -> Movie {
+/// What the indexer knows about a file on disk, used to tell whether it
+/// has changed since the last scan.
+pub struct MovieFile<'a> {
+    pub size: i64,
+    pub modified: Option<i64>,
+    pub inode: Option<i64>,
+    pub fingerprint: &'a str,
+}
+
+pub fn create_movie<'a>(conn: &SqliteConnection, movie_title: &'a str, movie_file_path: &'a str, movie_file: &MovieFile<'a>) -> Movie {
     use data::schema::movies::dsl::*;
 
     let new_movie = NewMovie {
         title: movie_title,
         file_path: movie_file_path,
         created_date: Utc::now().naive_utc(),
+        file_size: Some(movie_file.size),
+        fingerprint: Some(movie_file.fingerprint),
+        file_modified: movie_file.modified,
+        file_inode: movie_file.inode,
     };
 
-    let movie_id : Result<i32, _> =
+    let existing : Option<Movie> =
         movies.filter(file_path.eq(movie_file_path))
-            .select(id)
-            .first(conn);
-
-    let movie_id =
-        match movie_id {
-            Ok(movie_id) => movie_id as usize,
-            Err(_) => {
-                diesel::insert(&new_movie)
-                    .into(schema::movies::table)
-                    .execute(conn)
-                    .expect("Error saving new movie")
-            }
-        };
-    get_movie(conn, movie_id as i64)
+            .first(conn)
+            .optional()
+            .expect("Error loading movie");
+
+    match existing {
+        Some(movie) => movie,
+        None => {
+            diesel::insert(&new_movie)
+                .into(schema::movies::table)
+                .execute(conn)
+                .expect("Error saving new movie");
+            movies.filter(file_path.eq(movie_file_path))
+                .first(conn)
+                .expect("Error loading new movie")
+        }
+    }
 }
 
 pub fn page_movies(conn: &SqliteConnection, page: i64, count: i64) -> Vec<Movie> {
     use data::schema::movies::dsl::*;
 
-    movies.offset(page * count)
+    movies.filter(available.eq(true))
+        .offset(page * count)
         .limit(count)
         .load::<Movie>(conn)
         .expect("Error loading movies")
@@ -53,3 +70,107 @@ pub fn get_movie(conn: &SqliteConnection, movie_id: i64) -> Movie {
         .first::<Movie>(conn)
         .expect("Error loading movie")
 }
+
+pub fn find_movie(conn: &SqliteConnection, movie_id: i64) -> Option<Movie> {
+    use data::schema::movies::dsl::*;
+
+    movies.find(movie_id as i32)
+        .first::<Movie>(conn)
+        .optional()
+        .expect("Error loading movie")
+}
+
+pub fn find_movie_by_path(conn: &SqliteConnection, movie_file_path: &str) -> Option<Movie> {
+    use data::schema::movies::dsl::*;
+
+    movies.filter(file_path.eq(movie_file_path))
+        .first::<Movie>(conn)
+        .optional()
+        .expect("Error loading movie")
+}
+
+/// Every movie whose file is somewhere below `directory`.
+pub fn movies_under(conn: &SqliteConnection, directory: &str) -> Vec<Movie> {
+    use data::schema::movies::dsl::*;
+
+    let prefix = format!("{}{}", directory.trim_right_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    movies.filter(file_path.like(format!("{}%", prefix)))
+        .load::<Movie>(conn)
+        .expect("Error loading movies")
+        .into_iter()
+        .filter(|movie| movie.file_path.starts_with(&prefix))
+        .collect()
+}
+
+pub fn update_movie_path(conn: &SqliteConnection, movie_id: i32, movie_title: &str, movie_file_path: &str) {
+    use data::schema::movies::dsl::*;
+
+    diesel::update(movies.find(movie_id))
+        .set((title.eq(movie_title), file_path.eq(movie_file_path)))
+        .execute(conn)
+        .expect("Error updating movie");
+}
+
+pub fn update_movie_file(conn: &SqliteConnection, movie_id: i32, movie_file: &MovieFile) {
+    use data::schema::movies::dsl::*;
+
+    diesel::update(movies.find(movie_id))
+        .set((
+            file_size.eq(movie_file.size),
+            fingerprint.eq(movie_file.fingerprint),
+            file_modified.eq(movie_file.modified),
+            file_inode.eq(movie_file.inode),
+            available.eq(true),
+        ))
+        .execute(conn)
+        .expect("Error updating movie");
+}
+
+pub fn set_available(conn: &SqliteConnection, movie_id: i32, is_available: bool) {
+    use data::schema::movies::dsl::*;
+
+    diesel::update(movies.find(movie_id))
+        .set(available.eq(is_available))
+        .execute(conn)
+        .expect("Error updating movie");
+}
+
+/// A movie with the same content whose file is no longer where it was,
+/// which is most likely the file that has turned up at a new path.
+pub fn find_moved_movie(conn: &SqliteConnection, movie_file_size: i64, movie_fingerprint: &str) -> Option<Movie> {
+    use data::schema::movies::dsl::*;
+
+    movies.filter(file_size.eq(movie_file_size))
+        .filter(fingerprint.eq(movie_fingerprint))
+        .load::<Movie>(conn)
+        .expect("Error loading movies")
+        .into_iter()
+        .find(|movie| !Path::new(&movie.file_path).exists())
+}
+
+pub fn all_movies(conn: &SqliteConnection) -> Vec<Movie> {
+    use data::schema::movies::dsl::*;
+
+    movies.load::<Movie>(conn)
+        .expect("Error loading movies")
+}
+
+pub fn available_movies(conn: &SqliteConnection) -> Vec<Movie> {
+    use data::schema::movies::dsl::*;
+
+    movies.filter(available.eq(true))
+        .load::<Movie>(conn)
+        .expect("Error loading movies")
+}
+
+/// Available movies that have been fingerprinted, ordered so that copies
+/// of the same content are next to each other.
+pub fn fingerprinted_movies(conn: &SqliteConnection) -> Vec<Movie> {
+    use data::schema::movies::dsl::*;
+
+    movies.filter(available.eq(true))
+        .filter(fingerprint.is_not_null())
+        .order((file_size, fingerprint, id))
+        .load::<Movie>(conn)
+        .expect("Error loading movies")
+}
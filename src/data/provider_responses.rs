@@ -0,0 +1,37 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use chrono::prelude::*;
+use data::models::{NewProviderResponse, ProviderResponse};
+use data::schema;
+use diesel::prelude::*;
+use diesel;
+
+/// Replaces the response cached for the URL, dropping any others that have
+/// expired on the way.
+pub fn save_provider_response(conn: &SqliteConnection, response: &NewProviderResponse) -> QueryResult<()> {
+    use data::schema::provider_responses::dsl::*;
+
+    conn.transaction(|| {
+        diesel::delete(provider_responses.filter(url.eq(response.url).or(expires_date.le(response.fetched_date))))
+            .execute(conn)?;
+        diesel::insert(response)
+            .into(schema::provider_responses::table)
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// The response cached for the URL, unless it expired before `now`.
+pub fn find_provider_response(conn: &SqliteConnection, response_url: &str, now: NaiveDateTime) -> Option<ProviderResponse> {
+    use data::schema::provider_responses::dsl::*;
+
+    provider_responses.filter(url.eq(response_url))
+        .filter(expires_date.gt(now))
+        .first::<ProviderResponse>(conn)
+        .optional()
+        .expect("Error loading provider response")
+}
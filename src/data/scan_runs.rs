@@ -0,0 +1,34 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use data::models::ScanRun;
+use data::schema;
+use diesel::prelude::*;
+use chrono::prelude::*;
+use diesel;
+
+pub fn last_scan_run(conn: &SqliteConnection, library: i32) -> Option<NaiveDateTime> {
+    use data::schema::scan_runs::dsl::*;
+
+    scan_runs.find(library)
+        .select(last_run)
+        .first::<NaiveDateTime>(conn)
+        .optional()
+        .expect("Error loading last scan run")
+}
+
+pub fn record_scan_run(conn: &SqliteConnection, library: i32, run: NaiveDateTime) {
+    use data::schema::scan_runs::dsl::*;
+
+    let scan_run = ScanRun { library_id: library, last_run: run };
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(scan_runs.find(library)).execute(conn)?;
+        diesel::insert(&scan_run)
+            .into(schema::scan_runs::table)
+            .execute(conn)?;
+        Ok(())
+    }).expect("Error saving scan run");
+}
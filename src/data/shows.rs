@@ -0,0 +1,202 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use data::models::{Episode, NewEpisode, NewSeason, NewShow, Season, Show};
+use data::schema;
+use diesel::prelude::*;
+use chrono::prelude::*;
+use diesel;
+use std::path::MAIN_SEPARATOR;
+
+fn find_show_by_title(conn: &SqliteConnection, show_title: &str, show_year: Option<i32>) -> Option<Show> {
+    use data::schema::shows::dsl::*;
+
+    let show = match show_year {
+        Some(show_year) => shows.filter(title.eq(show_title))
+            .filter(year.eq(show_year))
+            .first::<Show>(conn)
+            .optional(),
+        None => shows.filter(title.eq(show_title))
+            .filter(year.is_null())
+            .first::<Show>(conn)
+            .optional(),
+    };
+    show.expect("Error loading show")
+}
+
+/// The show with this title and year, created the first time one of its
+/// episodes is indexed.
+pub fn find_or_create_show(conn: &SqliteConnection, show_title: &str, show_year: Option<i32>) -> Show {
+    if let Some(show) = find_show_by_title(conn, show_title, show_year) {
+        return show;
+    }
+    let new_show = NewShow {
+        title: show_title,
+        year: show_year,
+        created_date: Utc::now().naive_utc(),
+    };
+    diesel::insert(&new_show)
+        .into(schema::shows::table)
+        .execute(conn)
+        .expect("Error saving new show");
+    find_show_by_title(conn, show_title, show_year).expect("Error loading new show")
+}
+
+pub fn find_or_create_season(conn: &SqliteConnection, season_show_id: i32, number: i32) -> Season {
+    use data::schema::seasons::dsl::*;
+
+    let existing = seasons.filter(show_id.eq(season_show_id))
+        .filter(season_number.eq(number))
+        .first::<Season>(conn)
+        .optional()
+        .expect("Error loading season");
+    if let Some(season) = existing {
+        return season;
+    }
+    diesel::insert(&NewSeason { show_id: season_show_id, season_number: number })
+        .into(schema::seasons::table)
+        .execute(conn)
+        .expect("Error saving new season");
+    seasons.filter(show_id.eq(season_show_id))
+        .filter(season_number.eq(number))
+        .first(conn)
+        .expect("Error loading new season")
+}
+
+pub fn page_shows(conn: &SqliteConnection, page: i64, count: i64) -> Vec<Show> {
+    use data::schema::shows::dsl::*;
+
+    shows.order((title, id))
+        .offset(page * count)
+        .limit(count)
+        .load::<Show>(conn)
+        .expect("Error loading shows")
+}
+
+pub fn find_show(conn: &SqliteConnection, show_id: i32) -> Option<Show> {
+    use data::schema::shows::dsl::*;
+
+    shows.find(show_id)
+        .first::<Show>(conn)
+        .optional()
+        .expect("Error loading show")
+}
+
+pub fn find_season(conn: &SqliteConnection, season_id: i32) -> Option<Season> {
+    use data::schema::seasons::dsl::*;
+
+    seasons.find(season_id)
+        .first::<Season>(conn)
+        .optional()
+        .expect("Error loading season")
+}
+
+pub fn seasons_of(conn: &SqliteConnection, season_show_id: i32) -> Vec<Season> {
+    use data::schema::seasons::dsl::*;
+
+    seasons.filter(show_id.eq(season_show_id))
+        .order(season_number)
+        .load::<Season>(conn)
+        .expect("Error loading seasons")
+}
+
+/// The available episodes of the given seasons, in the order they aired.
+pub fn episodes_of(conn: &SqliteConnection, season_ids: &[i32]) -> Vec<Episode> {
+    use data::schema::episodes::dsl::*;
+
+    episodes.filter(season_id.eq_any(season_ids.to_vec()))
+        .filter(available.eq(true))
+        .order((season_id, episode_number, air_date, id))
+        .load::<Episode>(conn)
+        .expect("Error loading episodes")
+}
+
+pub fn find_episode(conn: &SqliteConnection, episode_id: i32) -> Option<Episode> {
+    use data::schema::episodes::dsl::*;
+
+    episodes.find(episode_id)
+        .first::<Episode>(conn)
+        .optional()
+        .expect("Error loading episode")
+}
+
+pub fn find_episode_by_path(conn: &SqliteConnection, episode_file_path: &str) -> Option<Episode> {
+    use data::schema::episodes::dsl::*;
+
+    episodes.filter(file_path.eq(episode_file_path))
+        .first::<Episode>(conn)
+        .optional()
+        .expect("Error loading episode")
+}
+
+/// Adds the episode, or updates the one already indexed from its file.
+pub fn save_episode(conn: &SqliteConnection, new_episode: &NewEpisode) -> Episode {
+    use data::schema::episodes::dsl::*;
+
+    match find_episode_by_path(conn, new_episode.file_path) {
+        Some(episode) => {
+            diesel::update(episodes.find(episode.id))
+                .set((
+                    season_id.eq(new_episode.season_id),
+                    episode_number.eq(new_episode.episode_number),
+                    last_episode_number.eq(new_episode.last_episode_number),
+                    absolute_number.eq(new_episode.absolute_number),
+                    air_date.eq(new_episode.air_date),
+                    title.eq(new_episode.title),
+                    file_size.eq(new_episode.file_size),
+                    file_modified.eq(new_episode.file_modified),
+                    file_inode.eq(new_episode.file_inode),
+                    available.eq(true),
+                ))
+                .execute(conn)
+                .expect("Error updating episode");
+        },
+        None => {
+            diesel::insert(new_episode)
+                .into(schema::episodes::table)
+                .execute(conn)
+                .expect("Error saving new episode");
+        },
+    }
+    find_episode_by_path(conn, new_episode.file_path).expect("Error loading episode")
+}
+
+pub fn all_episodes(conn: &SqliteConnection) -> Vec<Episode> {
+    use data::schema::episodes::dsl::*;
+
+    episodes.load::<Episode>(conn)
+        .expect("Error loading episodes")
+}
+
+/// Every episode whose file is somewhere below `directory`.
+pub fn episodes_under(conn: &SqliteConnection, directory: &str) -> Vec<Episode> {
+    use data::schema::episodes::dsl::*;
+
+    let prefix = format!("{}{}", directory.trim_right_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    episodes.filter(file_path.like(format!("{}%", prefix)))
+        .load::<Episode>(conn)
+        .expect("Error loading episodes")
+        .into_iter()
+        .filter(|episode| episode.file_path.starts_with(&prefix))
+        .collect()
+}
+
+pub fn available_episodes(conn: &SqliteConnection) -> Vec<Episode> {
+    use data::schema::episodes::dsl::*;
+
+    episodes.filter(available.eq(true))
+        .load::<Episode>(conn)
+        .expect("Error loading episodes")
+}
+
+pub fn set_episode_available(conn: &SqliteConnection, episode_id: i32, is_available: bool) {
+    use data::schema::episodes::dsl::*;
+
+    diesel::update(episodes.find(episode_id))
+        .set(available.eq(is_available))
+        .execute(conn)
+        .expect("Error updating episode");
+}
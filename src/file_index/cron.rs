@@ -0,0 +1,211 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::str::FromStr;
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+/// Give up looking for the next run after this many years, an expression
+/// like `0 0 30 2 *` never matches.
+const MAX_YEARS_AHEAD: i32 = 5;
+
+/// A set of allowed values for one field, as bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Field {
+    bits: u64,
+    restricted: bool,
+}
+
+impl Field {
+    fn contains(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+
+    fn parse(field: &str, min: u32, max: u32) -> Result<Field, String> {
+        let mut bits = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.find('/') {
+                Some(slash) => (&part[..slash], parse_number(&part[slash + 1..])?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err(format!("step of zero in '{}'", part));
+            }
+            let (from, to) = if range == "*" {
+                (min, max)
+            } else {
+                match range.find('-') {
+                    Some(dash) => (parse_number(&range[..dash])?, parse_number(&range[dash + 1..])?),
+                    None => {
+                        let value = parse_number(range)?;
+                        // `5/15` means from 5 to the end, every 15
+                        (value, if step > 1 { max } else { value })
+                    },
+                }
+            };
+            if from < min || to > max || from > to {
+                return Err(format!("'{}' is outside {}-{}", part, min, max));
+            }
+            let mut value = from;
+            while value <= to {
+                bits |= 1 << value;
+                value += step;
+            }
+        }
+        Ok(Field { bits: bits, restricted: !field.starts_with('*') })
+    }
+}
+
+fn parse_number(value: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| format!("'{}' is not a number", value))
+}
+
+/// A standard five field cron expression: minute, hour, day of month,
+/// month and day of week (0 or 7 is Sunday).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minutes: Field,
+    hours: Field,
+    days_of_month: Field,
+    months: Field,
+    days_of_week: Field,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Schedule, String> {
+        let fields : Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields in '{}'", expression));
+        }
+        let mut days_of_week = Field::parse(fields[4], 0, 7)?;
+        if days_of_week.contains(7) {
+            days_of_week.bits |= 1;
+        }
+        Ok(Schedule {
+            minutes: Field::parse(fields[0], 0, 59)?,
+            hours: Field::parse(fields[1], 0, 23)?,
+            days_of_month: Field::parse(fields[2], 1, 31)?,
+            months: Field::parse(fields[3], 1, 12)?,
+            days_of_week: days_of_week,
+        })
+    }
+}
+
+impl Schedule {
+    /// Like cron, when both day fields are restricted a day matching
+    /// either of them will do.
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day_of_month = self.days_of_month.contains(date.day());
+        let day_of_week = self.days_of_week.contains(date.weekday().num_days_from_sunday());
+        match (self.days_of_month.restricted, self.days_of_week.restricted) {
+            (true, true) => day_of_month || day_of_week,
+            (true, false) => day_of_month,
+            (false, true) => day_of_week,
+            (false, false) => true,
+        }
+    }
+
+    /// The first time strictly after `after` that the schedule matches.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.date().and_hms(after.hour(), after.minute(), 0) + Duration::minutes(1);
+        let mut date = start.date();
+        let mut first_day = true;
+        while date.year() <= after.year() + MAX_YEARS_AHEAD {
+            if !self.months.contains(date.month()) {
+                date = first_of_next_month(date);
+                first_day = false;
+                continue;
+            }
+            if self.day_matches(date) {
+                let from = if first_day { start.time() } else { NaiveTime::from_hms(0, 0, 0) };
+                if let Some(time) = self.first_time_from(from) {
+                    return Some(date.and_time(time));
+                }
+            }
+            date = date.succ();
+            first_day = false;
+        }
+        None
+    }
+
+    fn first_time_from(&self, from: NaiveTime) -> Option<NaiveTime> {
+        for hour in from.hour()..24 {
+            if !self.hours.contains(hour) {
+                continue;
+            }
+            let first_minute = if hour == from.hour() { from.minute() } else { 0 };
+            for minute in first_minute..60 {
+                if self.minutes.contains(minute) {
+                    return Some(NaiveTime::from_hms(hour, minute, 0));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn first_of_next_month(date: NaiveDate) -> NaiveDate {
+    if date.month() == 12 {
+        NaiveDate::from_ymd(date.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(date.year(), date.month() + 1, 1)
+    }
+}
+
+#[cfg(test)]
+fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, 0)
+}
+
+#[cfg(test)]
+fn next(expression: &str, after: NaiveDateTime) -> Option<NaiveDateTime> {
+    expression.parse::<Schedule>().unwrap().next_after(after)
+}
+
+#[test]
+fn nightly() {
+    assert_eq!(Some(at(2017, 12, 2, 3, 0)), next("0 3 * * *", at(2017, 12, 1, 3, 0)));
+    assert_eq!(Some(at(2017, 12, 1, 3, 0)), next("0 3 * * *", at(2017, 12, 1, 2, 59)));
+}
+
+#[test]
+fn steps_and_lists() {
+    assert_eq!(Some(at(2017, 12, 1, 10, 45)), next("*/15 * * * *", at(2017, 12, 1, 10, 30)));
+    assert_eq!(Some(at(2017, 12, 1, 11, 5)), next("5/30 * * * *", at(2017, 12, 1, 10, 40)));
+    assert_eq!(Some(at(2017, 12, 1, 18, 0)), next("0 6,18 * * *", at(2017, 12, 1, 7, 0)));
+    assert_eq!(Some(at(2017, 12, 1, 9, 0)), next("0 9-17/4 * * *", at(2017, 12, 1, 8, 0)));
+    assert_eq!(Some(at(2017, 12, 1, 13, 0)), next("0 9-17/4 * * *", at(2017, 12, 1, 9, 0)));
+}
+
+#[test]
+fn days_of_week() {
+    // 1 December 2017 is a Friday
+    assert_eq!(Some(at(2017, 12, 4, 3, 0)), next("0 3 * * 1-5", at(2017, 12, 1, 4, 0)));
+    assert_eq!(Some(at(2017, 12, 3, 3, 0)), next("0 3 * * 7", at(2017, 12, 1, 4, 0)));
+    // Either the 15th or a Sunday
+    assert_eq!(Some(at(2017, 12, 3, 0, 0)), next("0 0 15 * 0", at(2017, 12, 1, 0, 0)));
+}
+
+#[test]
+fn rolls_over_months_and_years() {
+    assert_eq!(Some(at(2018, 1, 1, 0, 0)), next("0 0 1 * *", at(2017, 12, 1, 0, 0)));
+    assert_eq!(Some(at(2018, 2, 28, 0, 0)), next("0 0 28 2 *", at(2017, 12, 1, 0, 0)));
+    assert_eq!(Some(at(2020, 2, 29, 0, 0)), next("0 0 29 2 *", at(2017, 12, 1, 0, 0)));
+    assert_eq!(None, next("0 0 30 2 *", at(2017, 12, 1, 0, 0)));
+}
+
+#[test]
+fn invalid_expressions() {
+    assert!("0 3 * *".parse::<Schedule>().is_err());
+    assert!("60 * * * *".parse::<Schedule>().is_err());
+    assert!("* * 0 * *".parse::<Schedule>().is_err());
+    assert!("*/0 * * * *".parse::<Schedule>().is_err());
+    assert!("a * * * *".parse::<Schedule>().is_err());
+    assert!("5-1 * * * *".parse::<Schedule>().is_err());
+}
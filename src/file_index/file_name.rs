@@ -4,34 +4,534 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::fmt;
 use std::io;
 use std::path::Path;
 
+use chrono::NaiveDate;
 use regex::Regex;
 
+use media::bytes::invalid;
+
+/// What a scene style release name says about the file besides its title.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Release {
+    pub resolution: Option<&'static str>,
+    pub source: Option<&'static str>,
+    pub codec: Option<&'static str>,
+    pub audio: Option<&'static str>,
+    pub edition: Option<&'static str>,
+    pub group: Option<String>,
+    pub three_d: bool,
+    pub hdr: bool,
+}
+
+impl Release {
+    /// The first tag of each kind wins, `TrueHD.7.1.Atmos` is TrueHD.
+    fn record(&mut self, tag: Tag) {
+        fn first(field: &mut Option<&'static str>, value: &'static str) {
+            if field.is_none() {
+                *field = Some(value);
+            }
+        }
+        match tag {
+            Tag::Resolution(value) => first(&mut self.resolution, value),
+            Tag::Source(value) => first(&mut self.source, value),
+            Tag::Codec(value) => first(&mut self.codec, value),
+            Tag::Audio(value) => first(&mut self.audio, value),
+            Tag::Edition(value) => first(&mut self.edition, value),
+            Tag::ThreeD => self.three_d = true,
+            Tag::Hdr => self.hdr = true,
+            Tag::Other => (),
+        }
+    }
+}
+
+impl fmt::Display for Release {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts : Vec<String> = vec![self.resolution, self.source, self.codec, self.audio, self.edition]
+            .into_iter()
+            .filter_map(|part| part.map(String::from))
+            .collect();
+        if self.three_d {
+            parts.push("3D".to_string());
+        }
+        if self.hdr {
+            parts.push("HDR".to_string());
+        }
+        if let Some(ref group) = self.group {
+            parts.push(format!("-{}", group));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EpisodeNumber {
+    /// `S01E02` or `1x02`, with more than one episode for `S01E01-E03`.
+    Season { season: i32, episodes: Vec<i32> },
+    /// Daily shows named by air date, `2017.12.14`.
+    Date(NaiveDate),
+    /// Numbered across seasons, `Show - 123`.
+    Absolute(Vec<i32>),
+}
+
+#[derive(Debug, PartialEq)]
 pub enum ParseResult {
-    Movie { title: String, year: Option<i32> }
+    Movie { title: String, year: Option<i32>, release: Release },
+    Episode { show: String, year: Option<i32>, number: EpisodeNumber, title: Option<String>, release: Release },
 }
 
-pub fn parse(path: &Path) -> io::Result<ParseResult> {
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Resolution(&'static str),
+    Source(&'static str),
+    Codec(&'static str),
+    Audio(&'static str),
+    Edition(&'static str),
+    ThreeD,
+    Hdr,
+    /// Part of a release name, but nothing worth keeping.
+    Other,
+}
+
+fn classify(word: &str) -> Option<Tag> {
+    let tag = match word.to_lowercase().as_str() {
+        "2160p" | "4k" | "uhd" => Tag::Resolution("2160p"),
+        "1080p" => Tag::Resolution("1080p"),
+        "1080i" => Tag::Resolution("1080i"),
+        "720p" => Tag::Resolution("720p"),
+        "576p" => Tag::Resolution("576p"),
+        "480p" => Tag::Resolution("480p"),
+        "bluray" | "blu-ray" | "bdrip" | "brrip" | "bdremux" | "remux" => Tag::Source("BluRay"),
+        "web-dl" | "webdl" => Tag::Source("WEB-DL"),
+        "webrip" | "web" => Tag::Source("WEBRip"),
+        "hdtv" => Tag::Source("HDTV"),
+        "dvdrip" | "dvd" | "dvd5" | "dvd9" | "dvdscr" => Tag::Source("DVD"),
+        "hdrip" => Tag::Source("HDRip"),
+        "cam" | "hdcam" | "telesync" => Tag::Source("CAM"),
+        "x264" | "h264" | "h.264" | "avc" => Tag::Codec("H.264"),
+        "x265" | "h265" | "h.265" | "hevc" => Tag::Codec("H.265"),
+        "xvid" => Tag::Codec("XviD"),
+        "divx" => Tag::Codec("DivX"),
+        "vp9" => Tag::Codec("VP9"),
+        "av1" => Tag::Codec("AV1"),
+        "dts-hd" | "dtshd" | "dts-hdma" => Tag::Audio("DTS-HD MA"),
+        "dts-x" | "dtsx" => Tag::Audio("DTS:X"),
+        "dts" => Tag::Audio("DTS"),
+        "truehd" => Tag::Audio("TrueHD"),
+        "atmos" => Tag::Audio("Atmos"),
+        "ac3" | "dd" | "dd5.1" | "dd2.0" => Tag::Audio("Dolby Digital"),
+        "eac3" | "ddp" | "ddp5.1" | "ddp7.1" | "dd+" => Tag::Audio("Dolby Digital Plus"),
+        "aac" | "aac2.0" | "aac5.1" => Tag::Audio("AAC"),
+        "flac" => Tag::Audio("FLAC"),
+        "mp3" => Tag::Audio("MP3"),
+        "extended" => Tag::Edition("Extended"),
+        "unrated" => Tag::Edition("Unrated"),
+        "uncut" => Tag::Edition("Uncut"),
+        "theatrical" => Tag::Edition("Theatrical"),
+        "remastered" => Tag::Edition("Remastered"),
+        "imax" => Tag::Edition("IMAX"),
+        "criterion" => Tag::Edition("Criterion"),
+        "3d" | "sbs" | "hsbs" | "half-sbs" | "ou" | "hou" | "half-ou" => Tag::ThreeD,
+        "hdr" | "hdr10" | "hdr10+" | "hdr10plus" | "dv" | "dovi" => Tag::Hdr,
+        "proper" | "repack" | "rerip" | "internal" | "multi" | "multisubs" | "subbed"
+            | "10bit" | "8bit" | "5.1" | "7.1" | "2.0" | "6ch" | "8ch" => Tag::Other,
+        _ => return None,
+    };
+    Some(tag)
+}
+
+/// Tags written as two words.
+fn classify_pair(first: &str, second: &str) -> Option<Tag> {
+    let tag = match (first.to_lowercase().as_str(), second.to_lowercase().as_str()) {
+        ("director's", "cut") | ("directors", "cut") => Tag::Edition("Director's Cut"),
+        ("extended", "cut") | ("extended", "edition") => Tag::Edition("Extended"),
+        ("special", "edition") => Tag::Edition("Special Edition"),
+        ("final", "cut") => Tag::Edition("Final Cut"),
+        ("dts-hd", "ma") => Tag::Audio("DTS-HD MA"),
+        ("dolby", "vision") => Tag::Hdr,
+        _ => return None,
+    };
+    Some(tag)
+}
+
+fn parse_year(word: &str) -> Option<i32> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"([^']+)\s+\((\d{4})\)").unwrap();
+        static ref YEAR: Regex = Regex::new(r"^(19|20)\d{2}$").unwrap();
+    }
+    if YEAR.is_match(word) { word.parse().ok() } else { None }
+}
+
+/// A dot stays in `5.1` style audio channels and in `H.264`.
+fn keeps_dot(chars: &[char], i: usize) -> bool {
+    let at = |j: Option<usize>| j.and_then(|j| chars.get(j).cloned());
+    let is_digit = |c: Option<char>| c.map_or(false, |c| c.is_digit(10));
+    let (before, before2) = (at(i.checked_sub(1)), at(i.checked_sub(2)));
+    let (after, after2) = (at(Some(i + 1)), at(Some(i + 2)));
+    let channels = is_digit(before) && !is_digit(before2) && is_digit(after) && !is_digit(after2);
+    let codec = (before == Some('h') || before == Some('H'))
+        && !before2.map_or(false, |c| c.is_alphanumeric())
+        && after == Some('2');
+    channels || codec
+}
+
+/// Underscores always separate words, dots only in names without spaces
+/// so `Mr. & Mrs. Smith` keeps them.
+fn normalise(name: &str) -> String {
+    let spaced = name.contains(' ');
+    let chars : Vec<char> = name.chars().collect();
+    chars.iter().enumerate()
+        .map(|(i, &c)| match c {
+            '_' => ' ',
+            '.' if !spaced && !keeps_dot(&chars, i) => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+struct Word {
+    text: String,
+    bracketed: bool,
+}
+
+fn push_word(words: &mut Vec<Word>, word: &mut String) {
+    if !word.is_empty() {
+        words.push(Word { text: word.clone(), bracketed: false });
+        word.clear();
     }
-    let filename = path.file_stem().unwrap().to_str().unwrap();
-    match RE.captures(filename) {
-        Some(caps) => {
-            let title = caps.get(1).unwrap().as_str();
-            let year = caps.get(2).map(|x|x.as_str().parse::<i32>().ok()).unwrap_or_default();
-            Ok(ParseResult::Movie { title: title.to_owned(), year: year })
+}
+
+/// Brackets hold a year, tags like `[1080p BluRay]` or `[WEBDL-1080p]`,
+/// or something else such as a release group kept as one word.
+fn push_bracketed(words: &mut Vec<Word>, inner: &str) {
+    let mut parts = Vec::new();
+    for part in inner.split_whitespace() {
+        let split : Vec<&str> = part.split('-').collect();
+        if classify(part).is_none() && split.len() > 1 && split.iter().all(|part| classify(part).is_some()) {
+            parts.extend(split);
+        } else {
+            parts.push(part);
+        }
+    }
+    if parts.len() > 1 && parts.iter().any(|part| classify(part).is_some()) {
+        words.extend(parts.into_iter().map(|part| Word { text: part.to_string(), bracketed: true }));
+    } else if !inner.is_empty() {
+        words.push(Word { text: inner.to_string(), bracketed: true });
+    }
+}
+
+fn split_words(name: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        let close = match c {
+            '(' => Some(')'),
+            '[' => Some(']'),
+            '{' => Some('}'),
+            _ => None,
+        };
+        if let Some(close) = close {
+            push_word(&mut words, &mut word);
+            let inner : String = chars.by_ref().take_while(|&c| c != close).collect();
+            push_bracketed(&mut words, inner.trim());
+        } else if c.is_whitespace() {
+            push_word(&mut words, &mut word);
+        } else {
+            word.push(c);
+        }
+    }
+    push_word(&mut words, &mut word);
+    words
+}
+
+fn is_group(word: &Word) -> bool {
+    word.bracketed && parse_year(&word.text).is_none() && classify(&word.text).is_none()
+}
+
+/// Splits words into a title, year and release tags. Tags are looked for
+/// from word `first` on, so a title is at least that long. The title runs
+/// up to the last year before the first tag, or the first tag if there is
+/// no year, so `2001.A.Space.Odyssey.1968` and `Blade.Runner.2049.2017`
+/// both work. A show name keeps a trailing bracket, as in `The Office (US)`,
+/// rather than taking it for a release group.
+fn parse_words(mut words: Vec<Word>, first: usize, trailing_group: bool) -> (String, Option<i32>, Release) {
+    let mut release = Release::default();
+
+    if words.len() > 1 && is_group(&words[0]) {
+        release.group = Some(words.remove(0).text);
+    }
+    let site = if trailing_group && words.len() > 1 && is_group(&words[words.len() - 1]) {
+        words.pop().map(|word| word.text)
+    } else {
+        None
+    };
+    let tagged = words.iter().skip(first).any(|word| classify(&word.text).is_some() || parse_year(&word.text).is_some());
+    if let Some(last) = words.last_mut() {
+        let dash = if !trailing_group || last.bracketed || classify(&last.text).is_some() { None } else { last.text.rfind('-') };
+        if let Some(dash) = dash {
+            let group = last.text[dash + 1..].to_string();
+            let tag = classify(&last.text[..dash]).is_some();
+            if !group.is_empty() && (tag || tagged) {
+                last.text.truncate(dash);
+                release.group = Some(group);
+            }
+        }
+    }
+    if release.group.is_none() {
+        release.group = site;
+    }
+
+    let is_tag = |i: usize| classify(&words[i].text).is_some()
+        || (i + 1 < words.len() && classify_pair(&words[i].text, &words[i + 1].text).is_some());
+    let first_tag = (first..words.len()).find(|&i| is_tag(i)).unwrap_or(words.len());
+    let years : Vec<usize> = (first..words.len()).filter(|&i| parse_year(&words[i].text).is_some()).collect();
+    let year_index = years.iter().cloned().filter(|&i| i < first_tag).last().or_else(|| years.last().cloned());
+    let title_end = year_index.unwrap_or(first_tag);
+
+    let mut i = title_end;
+    while i < words.len() {
+        match words.get(i + 1).and_then(|next| classify_pair(&words[i].text, &next.text)) {
+            Some(tag) => {
+                release.record(tag);
+                i += 2;
+            },
+            None => {
+                if let Some(tag) = classify(&words[i].text) {
+                    release.record(tag);
+                }
+                i += 1;
+            },
+        }
+    }
+
+    let title : Vec<String> = words[..title_end].iter()
+        .map(|word| if word.bracketed { format!("({})", word.text) } else { word.text.clone() })
+        .collect();
+    let title = title.join(" ").trim_matches(&['-', ' '][..]).to_owned();
+    (title, year_index.and_then(|i| parse_year(&words[i].text)), release)
+}
+
+/// The number at the start of `text`, how many digits it has and the rest.
+fn leading_number(text: &str) -> Option<(i32, usize, &str)> {
+    let end = text.find(|c: char| !c.is_digit(10)).unwrap_or(text.len());
+    if end == 0 || end > 4 {
+        return None;
+    }
+    text[..end].parse().ok().map(|number| (number, end, &text[end..]))
+}
+
+/// A range of episodes in one file is never longer than this.
+const MAX_EPISODES_PER_FILE: i32 = 50;
+
+/// The episodes after the first in `S01E01E02`, `S01E01-E03`, `S01E01-03`,
+/// `1x01x02` or `1x01-1x02`.
+fn episode_list(first: i32, rest: &str, marker: char) -> Option<Vec<i32>> {
+    let mut episodes = vec![first];
+    let mut rest = rest;
+    while !rest.is_empty() {
+        let range = rest.starts_with('-');
+        if range {
+            rest = &rest[1..];
+        }
+        if rest.starts_with(marker) {
+            rest = &rest[1..];
+        } else if let Some((_, _, after)) = leading_number(rest) {
+            // The season repeated, `-1x02`
+            if after.starts_with(marker) {
+                rest = &after[1..];
+            }
         }
-        _ => Ok(ParseResult::Movie { title: filename.to_owned(), year: None }),
+        let (episode, _, after) = match leading_number(rest) {
+            Some(number) => number,
+            None => return None,
+        };
+        let last = *episodes.last().unwrap();
+        if range && episode > last && episode - last <= MAX_EPISODES_PER_FILE {
+            episodes.extend(last + 1..episode + 1);
+        } else {
+            episodes.push(episode);
+        }
+        rest = after;
     }
+    Some(episodes)
+}
+
+/// `S01E02` or `1x02`, possibly with more episodes.
+fn season_episode(word: &str) -> Option<EpisodeNumber> {
+    let word = word.to_lowercase();
+    let (marker, numbers) = if word.starts_with('s') {
+        ('e', &word[1..])
+    } else {
+        ('x', &word[..])
+    };
+    let (season, digits, rest) = match leading_number(numbers) {
+        Some(number) => number,
+        None => return None,
+    };
+    if !rest.starts_with(marker) || (marker == 'x' && digits > 2) {
+        return None;
+    }
+    let (episode, digits, rest) = match leading_number(&rest[1..]) {
+        Some(number) => number,
+        None => return None,
+    };
+    if marker == 'x' && (digits < 2 || digits > 3) {
+        return None;
+    }
+    episode_list(episode, rest, marker).map(|episodes| EpisodeNumber::Season { season: season, episodes: episodes })
+}
+
+fn air_date(year: &str, month: &str, day: &str) -> Option<NaiveDate> {
+    let short = |part: &str| !part.is_empty() && part.len() <= 2 && part.chars().all(|c| c.is_digit(10));
+    if !short(month) || !short(day) {
+        return None;
+    }
+    match (parse_year(year), month.parse(), day.parse()) {
+        (Some(year), Ok(month), Ok(day)) => NaiveDate::from_ymd_opt(year, month, day),
+        _ => None,
+    }
+}
+
+/// `123` or `01-02` after a ` - `, as anime releases are numbered.
+fn absolute_numbers(word: &str) -> Option<Vec<i32>> {
+    let parts : Vec<&str> = word.split('-').collect();
+    if parts.len() > 2 || parse_year(parts[0]).is_some() {
+        return None;
+    }
+    let mut numbers = Vec::new();
+    for part in parts {
+        match leading_number(part) {
+            Some((number, digits, "")) if digits >= 2 => numbers.push(number),
+            _ => return None,
+        }
+    }
+    if numbers.len() == 2 && numbers[1] > numbers[0] && numbers[1] - numbers[0] <= MAX_EPISODES_PER_FILE {
+        let (first, last) = (numbers[0], numbers[1]);
+        numbers = (first..last + 1).collect();
+    }
+    Some(numbers)
+}
+
+/// Where the episode number is in the words and how many words it takes.
+fn find_episode(words: &[Word]) -> Option<(usize, usize, EpisodeNumber)> {
+    for i in 0..words.len() {
+        if words[i].bracketed {
+            continue;
+        }
+        let word = words[i].text.as_str();
+        let next = words.get(i + 1).map(|word| word.text.as_str());
+        if let Some(number) = season_episode(word) {
+            return Some((i, 1, number));
+        }
+        // `S01 E02`
+        if let Some(next) = next {
+            if word.len() > 1 && (word.starts_with('s') || word.starts_with('S')) {
+                if let Some(number) = season_episode(&format!("{}{}", word, next)) {
+                    return Some((i, 2, number));
+                }
+            }
+        }
+        let parts : Vec<&str> = word.split('-').collect();
+        if parts.len() == 3 {
+            if let Some(date) = air_date(parts[0], parts[1], parts[2]) {
+                return Some((i, 1, EpisodeNumber::Date(date)));
+            }
+        }
+        if i + 2 < words.len() && !words[i + 1].bracketed && !words[i + 2].bracketed {
+            if let Some(date) = air_date(word, &words[i + 1].text, &words[i + 2].text) {
+                return Some((i, 3, EpisodeNumber::Date(date)));
+            }
+        }
+        if i > 0 && word == "-" {
+            if let Some(numbers) = next.and_then(absolute_numbers) {
+                return Some((i, 2, EpisodeNumber::Absolute(numbers)));
+            }
+        }
+        if i > 0 && (word.starts_with('e') || word.starts_with('E')) {
+            match leading_number(&word[1..]) {
+                Some((number, digits, "")) if digits >= 2 => return Some((i, 1, EpisodeNumber::Absolute(vec![number]))),
+                _ => (),
+            }
+        }
+    }
+    None
+}
+
+fn parse_episode_name(name: &str) -> Option<ParseResult> {
+    let mut words = split_words(&normalise(name));
+    let (start, length, number) = match find_episode(&words) {
+        Some(found) => found,
+        None => return None,
+    };
+    let after = words.split_off(start + length);
+    words.truncate(start);
+    let (show, year, show_release) = parse_words(words, 1, false);
+    let (title, _, mut release) = parse_words(after, 0, true);
+    if release.group.is_none() {
+        release.group = show_release.group;
+    }
+    Some(ParseResult::Episode {
+        show: show,
+        year: year,
+        number: number,
+        title: if title.is_empty() { None } else { Some(title) },
+        release: release,
+    })
+}
+
+fn parse_movie_name(name: &str) -> ParseResult {
+    let (title, year, release) = parse_words(split_words(&normalise(name)), 1, true);
+    ParseResult::Movie {
+        title: if title.is_empty() { name.trim().to_owned() } else { title },
+        year: year,
+        release: release,
+    }
+}
+
+/// Parses names like `Title (Year)` as well as scene releases such as
+/// `The.Matrix.1999.1080p.BluRay.x264-GRP`, and episodes numbered
+/// `S01E02`, `1x02`, by air date or absolutely as in `Show - 123`.
+pub fn parse_name(name: &str) -> ParseResult {
+    match parse_episode_name(name) {
+        Some(episode) => episode,
+        None => parse_movie_name(name),
+    }
+}
+
+fn file_name(path: &Path) -> io::Result<&str> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| invalid("file name is not valid unicode"))
+}
+
+pub fn parse(path: &Path) -> io::Result<ParseResult> {
+    Ok(parse_name(file_name(path)?))
+}
+
+/// The title and year in a name that isn't an episode, like a show's
+/// folder `Doctor Who (2005)`.
+pub fn title_and_year(name: &str) -> (String, Option<i32>) {
+    match parse_movie_name(name) {
+        ParseResult::Movie { title, year, .. } | ParseResult::Episode { show: title, year, .. } => (title, year),
+    }
+}
+
+/// The title and year of a file in a movie library, where a name like
+/// `Fahrenheit - 451` isn't an episode.
+pub fn movie_title_and_year(path: &Path) -> io::Result<(String, Option<i32>)> {
+    Ok(title_and_year(file_name(path)?))
+}
+
+pub fn movie_title(path: &Path) -> io::Result<String> {
+    movie_title_and_year(path).map(|(title, _)| title)
 }
 
 #[test]
 fn a_clockwork_orange(){
     match parse(Path::new("A Clockwork Orange (1971).mkv")) {
-        Ok(ParseResult::Movie { title, year: Some (year) }) => {
+        Ok(ParseResult::Movie { title, year: Some (year), .. }) => {
             assert_eq!("A Clockwork Orange", title);
             assert_eq!(1971, year);
         }
@@ -42,7 +542,7 @@ fn a_clockwork_orange(){
 #[test]
 fn american_history_x(){
     match parse(Path::new("American History X.mp4")) {
-        Ok(ParseResult::Movie { title, year: None }) => {
+        Ok(ParseResult::Movie { title, year: None, .. }) => {
             assert_eq!("American History X", title);
         }
         result => assert!(false, result)
@@ -52,7 +552,7 @@ fn american_history_x(){
 #[test]
 fn great_escape(){
     match parse(Path::new("Great Escape.m4v")) {
-        Ok(ParseResult::Movie { title, year: None }) => {
+        Ok(ParseResult::Movie { title, year: None, .. }) => {
             assert_eq!("Great Escape", title);
         }
         result => assert!(false, result)
@@ -62,9 +562,121 @@ fn great_escape(){
 #[test]
 fn die_hard(){
     match parse(Path::new("/storage/movies/Die Hard.m4v")) {
-        Ok(ParseResult::Movie { title, year: None }) => {
+        Ok(ParseResult::Movie { title, year: None, .. }) => {
             assert_eq!("Die Hard", title);
         }
         result => assert!(false, result)
     }
 }
+
+/// File name, title, year and the release as formatted by `Display`.
+#[cfg(test)]
+const RELEASES: &'static [(&'static str, &'static str, Option<i32>, &'static str)] = &[
+    ("The.Matrix.1999.1080p.BluRay.x264-GRP.mkv", "The Matrix", Some(1999), "1080p BluRay H.264 -GRP"),
+    ("The_Dark_Knight_2008_720p.mkv", "The Dark Knight", Some(2008), "720p"),
+    ("Casablanca.1942.mkv", "Casablanca", Some(1942), ""),
+    ("Up (2009).mkv", "Up", Some(2009), ""),
+    ("Paddington 2 (2017).mkv", "Paddington 2", Some(2017), ""),
+    ("Se7en.1995.mkv", "Se7en", Some(1995), ""),
+    ("Spider-Man.mkv", "Spider-Man", None, ""),
+    ("Old Movie Title.avi", "Old Movie Title", None, ""),
+    ("Spider-Man.2002.1080p.WEB-DL.x264-GRP.mkv", "Spider-Man", Some(2002), "1080p WEB-DL H.264 -GRP"),
+    ("Movie.Title.1080p.BluRay.x264-GRP.mkv", "Movie Title", None, "1080p BluRay H.264 -GRP"),
+    ("Jaws.1975.720p.BluRay.x264.mkv", "Jaws", Some(1975), "720p BluRay H.264"),
+    ("Apollo.13.1995.720p.HDTV.x264.mkv", "Apollo 13", Some(1995), "720p HDTV H.264"),
+    ("Kill.Bill.Vol.1.2003.1080p.BluRay.x264-GRP.mkv", "Kill Bill Vol 1", Some(2003), "1080p BluRay H.264 -GRP"),
+    ("Ghostbusters.1984.REPACK.1080p.BluRay.x264-GRP.mkv", "Ghostbusters", Some(1984), "1080p BluRay H.264 -GRP"),
+    ("Amelie.2001.FRENCH.DVDRip.XviD-GRP.avi", "Amelie", Some(2001), "DVD XviD -GRP"),
+    ("Akira.1988.H.264.AAC.720p.mkv", "Akira", Some(1988), "720p H.264 AAC"),
+    // Years in titles
+    ("Blade.Runner.2049.2017.2160p.UHD.BluRay.REMUX.HDR.HEVC.Atmos-EPSiLON.mkv", "Blade Runner 2049", Some(2017), "2160p BluRay H.265 Atmos HDR -EPSiLON"),
+    ("2001.A.Space.Odyssey.1968.REMASTERED.1080p.BluRay.x264-AMIABLE.mkv", "2001 A Space Odyssey", Some(1968), "1080p BluRay H.264 Remastered -AMIABLE"),
+    ("1917.2019.2160p.WEB-DL.DDP5.1.HDR.HEVC-GRP.mkv", "1917", Some(2019), "2160p WEB-DL H.265 Dolby Digital Plus HDR -GRP"),
+    // Audio
+    ("Mad.Max.Fury.Road.2015.UNRATED.720p.WEBRip.AAC2.0.x264-GRP.mkv", "Mad Max Fury Road", Some(2015), "720p WEBRip H.264 AAC Unrated -GRP"),
+    ("Coco.2017.1080p.BluRay.x264.DTS-HD.MA.7.1-GRP.mkv", "Coco", Some(2017), "1080p BluRay H.264 DTS-HD MA -GRP"),
+    ("Dunkirk.2017.IMAX.2160p.UHD.BluRay.x265.10bit.HDR.TrueHD.7.1.Atmos-TERMiNAL.mkv", "Dunkirk", Some(2017), "2160p BluRay H.265 TrueHD IMAX HDR -TERMiNAL"),
+    ("Interstellar 2014 2160p UHD BluRay REMUX HDR DV HEVC TrueHD Atmos-FGT.mkv", "Interstellar", Some(2014), "2160p BluRay H.265 TrueHD HDR -FGT"),
+    // Editions
+    ("Alien.1979.Directors.Cut.720p.BRRip.XviD.AC3-ViSiON.avi", "Alien", Some(1979), "720p BluRay XviD Dolby Digital Director's Cut -ViSiON"),
+    ("Heat (1995) Extended Edition 1080p BluRay DTS-HD MA 5.1 x264.mkv", "Heat", Some(1995), "1080p BluRay H.264 DTS-HD MA Extended"),
+    ("Blade.Runner.1982.The.Final.Cut.1080p.BluRay.DTS.x264-GRP.mkv", "Blade Runner", Some(1982), "1080p BluRay H.264 DTS Final Cut -GRP"),
+    ("Titanic.1997.Special.Edition.DVD9.mkv", "Titanic", Some(1997), "DVD Special Edition"),
+    ("The.Lord.of.the.Rings.The.Return.of.the.King.2003.EXTENDED.1080p.BluRay.x264-GRP.mkv", "The Lord of the Rings The Return of the King", Some(2003), "1080p BluRay H.264 Extended -GRP"),
+    // 3D
+    ("Avatar.2009.3D.HSBS.1080p.BluRay.x264-GRP.mkv", "Avatar", Some(2009), "1080p BluRay H.264 3D -GRP"),
+    ("Gravity.2013.3D.Half-OU.1080p.BluRay.x264.DTS-GRP.mkv", "Gravity", Some(2013), "1080p BluRay H.264 DTS 3D -GRP"),
+    ("Step Up 3D (2010).mkv", "Step Up 3D", Some(2010), ""),
+    // Brackets
+    ("Inception (2010) [1080p] [BluRay] [5.1] [YTS.MX].mp4", "Inception", Some(2010), "1080p BluRay -YTS.MX"),
+    ("[HorribleSubs] Your Name (2016) [1080p].mkv", "Your Name", Some(2016), "1080p -HorribleSubs"),
+    ("Tenet (2020) {imdb-tt6723592} [WEBDL-1080p].mkv", "Tenet", Some(2020), "1080p WEB-DL"),
+    ("Arrival.2016.1080p.BluRay.x264-GRP[rarbg].mkv", "Arrival", Some(2016), "1080p BluRay H.264 -GRP"),
+];
+
+#[test]
+fn scene_releases() {
+    for &(file, title, year, release) in RELEASES {
+        match parse(Path::new(file)) {
+            Ok(ParseResult::Movie { title: parsed_title, year: parsed_year, release: parsed_release }) => {
+                assert_eq!((title, year, release), (parsed_title.as_str(), parsed_year, parsed_release.to_string().as_str()), "{}", file);
+            }
+            result => assert!(false, result)
+        }
+    }
+}
+
+#[test]
+fn release_flags() {
+    let release = match parse_name("Avengers.Endgame.2019.3D.2160p.UHD.BluRay.DV.HEVC-GRP") {
+        ParseResult::Movie { release, .. } | ParseResult::Episode { release, .. } => release,
+    };
+    assert!(release.three_d);
+    assert!(release.hdr);
+    assert_eq!(Some("GRP".to_string()), release.group);
+    assert_eq!(Release::default(), match parse_name("Casablanca (1942)") {
+        ParseResult::Movie { release, .. } | ParseResult::Episode { release, .. } => release,
+    });
+}
+
+#[cfg(test)]
+fn season(season: i32, episodes: &[i32]) -> EpisodeNumber {
+    EpisodeNumber::Season { season: season, episodes: episodes.to_vec() }
+}
+
+#[test]
+fn episodes() {
+    let cases = vec![
+        ("Doctor.Who.2005.S01E01.Rose.720p.WEB-DL.x264-GRP.mkv", "Doctor Who", Some(2005), season(1, &[1]), Some("Rose"), "720p WEB-DL H.264 -GRP"),
+        ("Doctor Who (2005) - 1x01 - Rose.mkv", "Doctor Who", Some(2005), season(1, &[1]), Some("Rose"), ""),
+        ("The Office (US) - S02E01-E02 - The Dundies.mkv", "The Office (US)", None, season(2, &[1, 2]), Some("The Dundies"), ""),
+        ("Friends.1x02.The.One.with.the.Sonogram.at.the.End.mkv", "Friends", None, season(1, &[2]), Some("The One with the Sonogram at the End"), ""),
+        ("Friends 3x01-3x02 The One with the Princess Leia Fantasy.avi", "Friends", None, season(3, &[1, 2]), Some("The One with the Princess Leia Fantasy"), ""),
+        ("Lost.S01E01-03.Pilot.mkv", "Lost", None, season(1, &[1, 2, 3]), Some("Pilot"), ""),
+        ("Sherlock.S02E01E02.mkv", "Sherlock", None, season(2, &[1, 2]), None, ""),
+        ("Game.of.Thrones.S08E06.1080p.WEB.H264-GRP.mkv", "Game of Thrones", None, season(8, &[6]), None, "1080p WEBRip H.264 -GRP"),
+        ("Show Name S01 E02.mkv", "Show Name", None, season(1, &[2]), None, ""),
+        ("S03E07.mkv", "", None, season(3, &[7]), None, ""),
+        ("The.Daily.Show.2017.12.14.Guest.Name.720p.WEB.x264-GRP.mkv", "The Daily Show", None, EpisodeNumber::Date(NaiveDate::from_ymd(2017, 12, 14)), Some("Guest Name"), "720p WEBRip H.264 -GRP"),
+        ("Last Week Tonight 2017-12-10.mkv", "Last Week Tonight", None, EpisodeNumber::Date(NaiveDate::from_ymd(2017, 12, 10)), None, ""),
+        ("[HorribleSubs] One Piece - 890 [1080p].mkv", "One Piece", None, EpisodeNumber::Absolute(vec![890]), None, "1080p -HorribleSubs"),
+        ("[SubsPlease] Jujutsu Kaisen - 01-02 (1080p).mkv", "Jujutsu Kaisen", None, EpisodeNumber::Absolute(vec![1, 2]), None, "1080p -SubsPlease"),
+        ("Naruto.Shippuden.E123.720p.mkv", "Naruto Shippuden", None, EpisodeNumber::Absolute(vec![123]), None, "720p"),
+    ];
+    for (file, show, year, number, title, release) in cases {
+        match parse(Path::new(file)) {
+            Ok(ParseResult::Episode { show: parsed_show, year: parsed_year, number: parsed_number, title: parsed_title, release: parsed_release }) => {
+                assert_eq!((show, year, number, title, release),
+                    (parsed_show.as_str(), parsed_year, parsed_number, parsed_title.as_ref().map(|title| title.as_str()), parsed_release.to_string().as_str()),
+                    "{}", file);
+            }
+            result => assert!(false, "{}: {:?}", file, result)
+        }
+    }
+}
+
+#[test]
+fn movie_libraries_never_have_episodes() {
+    assert_eq!("Fahrenheit - 451", movie_title(Path::new("Fahrenheit - 451.mkv")).unwrap());
+    assert_eq!("The Matrix", movie_title(Path::new("The.Matrix.1999.1080p.BluRay.x264-GRP.mkv")).unwrap());
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use base64;
+use blake2::{Blake2b, Digest};
+
+use media::bytes::put_u64;
+
+/// Bytes hashed from each end of the file. Enough to tell different
+/// encodes apart without reading whole movies.
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len as usize);
+    file.take(len).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// A BLAKE2b hash of the file size and its first and last 64KiB, which
+/// stays the same when a file is renamed or moved.
+pub fn fingerprint(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hasher = Blake2b::default();
+    let mut size_bytes = Vec::new();
+    put_u64(&mut size_bytes, size);
+    hasher.input(&size_bytes);
+    if size <= SAMPLE_SIZE * 2 {
+        hasher.input(&read_at(&mut file, 0, size)?);
+    } else {
+        hasher.input(&read_at(&mut file, 0, SAMPLE_SIZE)?);
+        hasher.input(&read_at(&mut file, size - SAMPLE_SIZE, SAMPLE_SIZE)?);
+    }
+    Ok(base64::encode(&hasher.result()))
+}
+
+#[cfg(test)]
+fn write_file(path: &Path, contents: &[u8]) {
+    use std::io::Write;
+    File::create(path).unwrap().write_all(contents).unwrap();
+}
+
+#[test]
+fn same_content_same_fingerprint() {
+    let dir = ::tempdir::TempDir::new("carolus_fingerprint").unwrap();
+    let contents : Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+    write_file(&dir.path().join("a.mkv"), &contents);
+    write_file(&dir.path().join("b.mkv"), &contents);
+    assert_eq!(fingerprint(&dir.path().join("a.mkv")).unwrap(), fingerprint(&dir.path().join("b.mkv")).unwrap());
+}
+
+#[test]
+fn different_ends_different_fingerprint() {
+    let dir = ::tempdir::TempDir::new("carolus_fingerprint").unwrap();
+    let mut contents : Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+    write_file(&dir.path().join("a.mkv"), &contents);
+    let last = contents.len() - 1;
+    contents[last] ^= 0xff;
+    write_file(&dir.path().join("b.mkv"), &contents);
+    assert!(fingerprint(&dir.path().join("a.mkv")).unwrap() != fingerprint(&dir.path().join("b.mkv")).unwrap());
+}
@@ -0,0 +1,323 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+pub const IGNORE_FILE: &'static str = ".carolusignore";
+
+/// Directories that hold extra material rather than the movie itself.
+const SKIPPED_DIRECTORIES: &'static [&'static str] = &["sample", "samples", "trailers", "extras"];
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// One line of an ignore file.
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    /// Reads a line in gitignore syntax: `#` comments, `!` to re-include,
+    /// a trailing `/` to only match directories, and patterns with a `/`
+    /// matched from the ignore file's directory rather than at any depth.
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_right();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = if line.starts_with('!') {
+            (true, &line[1..])
+        } else if line.starts_with("\\!") || line.starts_with("\\#") {
+            (false, &line[1..])
+        } else {
+            (false, line)
+        };
+        let directory_only = line.ends_with('/');
+        let line = line.trim_right_matches('/');
+        let anchored = line.contains('/');
+        let line = line.trim_left_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        Pattern::new(line).ok().map(|pattern| Rule {
+            pattern: pattern,
+            negated: negated,
+            directory_only: directory_only,
+            anchored: anchored,
+        })
+    }
+
+    fn matches(&self, relative: &str, name: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let subject = if self.anchored { relative } else { name };
+        self.pattern.matches_with(subject, &MATCH_OPTIONS)
+    }
+}
+
+/// The rules of a `.carolusignore` file, which apply to everything below
+/// the directory it is in.
+pub struct IgnoreFile {
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    pub fn parse(base: &Path, contents: &str) -> IgnoreFile {
+        IgnoreFile {
+            base: base.to_path_buf(),
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    /// The ignore file in `directory`, if there is one that can be read.
+    pub fn read(directory: &Path) -> Option<IgnoreFile> {
+        let path = directory.join(IGNORE_FILE);
+        let mut contents = String::new();
+        match File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+            Ok(_) => Some(IgnoreFile::parse(directory, &contents)),
+            Err(_) => None,
+        }
+    }
+
+    /// Whether the last rule matching the path ignores it, or None if no
+    /// rule matches.
+    pub fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = match path.strip_prefix(&self.base).ok().and_then(|relative| relative.to_str()) {
+            Some(relative) => relative.replace('\\', "/"),
+            None => return None,
+        };
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return None,
+        };
+        self.rules.iter().rev()
+            .find(|rule| rule.matches(&relative, name, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Hidden files and directories, and directories of samples, trailers
+/// and extras, unless an ignore file says otherwise.
+pub fn skipped_by_default(path: &Path, is_dir: bool) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_lowercase(),
+        None => return false,
+    };
+    name.starts_with('.') || (is_dir && SKIPPED_DIRECTORIES.contains(&name.as_str()))
+}
+
+/// The closest ignore file with a rule for the path decides, as deeper
+/// ignore files override the ones above them.
+fn is_skipped(ignore_files: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    for ignore_file in ignore_files.iter().rev() {
+        if let Some(ignored) = ignore_file.matches(path, is_dir) {
+            return ignored;
+        }
+    }
+    skipped_by_default(path, is_dir)
+}
+
+/// Ignore files from `root` down to `directory`, or None if `directory`
+/// or one of the directories above it up to `root` is itself ignored.
+fn ignore_files_to(root: &Path, directory: &Path) -> Option<Vec<IgnoreFile>> {
+    let relative = match directory.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => return None,
+    };
+    let mut ignore_files : Vec<IgnoreFile> = IgnoreFile::read(root).into_iter().collect();
+    let mut current = root.to_path_buf();
+    for component in relative.components() {
+        current.push(component.as_os_str());
+        if is_skipped(&ignore_files, &current, true) {
+            return None;
+        }
+        ignore_files.extend(IgnoreFile::read(&current));
+    }
+    Some(ignore_files)
+}
+
+/// Whether a path below `root` is ignored by an ignore file or the
+/// default rules, either itself or through one of its parent directories.
+pub fn is_ignored(root: &Path, path: &Path) -> bool {
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return false,
+    };
+    match ignore_files_to(root, parent) {
+        Some(ignore_files) => is_skipped(&ignore_files, path, path.is_dir()),
+        None => true,
+    }
+}
+
+/// Symlinked directories are followed, but each directory is only walked
+/// once so a link back up the tree doesn't loop forever.
+fn walk(directory: &Path, ignore_files: &mut Vec<IgnoreFile>, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    let real_path = match fs::canonicalize(directory) {
+        Ok(real_path) => real_path,
+        Err(err) => {
+            warn!("Could not read {}: {}", directory.display(), err);
+            return;
+        },
+    };
+    if !visited.insert(real_path) {
+        warn!("Skipping {}, it links to a directory already scanned", directory.display());
+        return;
+    }
+    let mut entries : Vec<PathBuf> = match fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(Result::ok).map(|entry| entry.path()).collect(),
+        Err(err) => {
+            warn!("Could not read {}: {}", directory.display(), err);
+            return;
+        },
+    };
+    entries.sort();
+    for entry in entries {
+        let is_dir = entry.is_dir();
+        if is_skipped(ignore_files, &entry, is_dir) {
+            continue;
+        }
+        if is_dir {
+            let pushed = match IgnoreFile::read(&entry) {
+                Some(ignore_file) => {
+                    ignore_files.push(ignore_file);
+                    true
+                },
+                None => false,
+            };
+            walk(&entry, ignore_files, visited, files);
+            if pushed {
+                ignore_files.pop();
+            }
+        } else if entry.is_file() {
+            files.push(entry);
+        }
+    }
+}
+
+/// Every file below `directory` that isn't ignored, taking into account
+/// ignore files between `root` and `directory` as well as those below.
+pub fn files_in(root: &Path, directory: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Some(mut ignore_files) = ignore_files_to(root, directory) {
+        walk(directory, &mut ignore_files, &mut HashSet::new(), &mut files);
+    }
+    files
+}
+
+#[cfg(test)]
+fn create_tree(root: &Path, files: &[&str]) {
+    use std::io::Write;
+    for file in files {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(b"movie").unwrap();
+    }
+}
+
+#[cfg(test)]
+fn write_ignore_file(directory: &Path, contents: &str) {
+    use std::io::Write;
+    File::create(directory.join(IGNORE_FILE)).unwrap().write_all(contents.as_bytes()).unwrap();
+}
+
+#[cfg(test)]
+fn relative_files(root: &Path, directory: &Path) -> Vec<String> {
+    files_in(root, directory).iter()
+        .map(|file| file.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn gitignore_syntax() {
+    let ignore_file = IgnoreFile::parse(Path::new("/movies"), "
+# Not finished downloading
+*.part
+!keep.part
+/Unsorted/
+Boxsets/*/Bonus
+\\#hash.mkv
+");
+    assert_eq!(Some(true), ignore_file.matches(Path::new("/movies/Heat/Heat.part"), false));
+    assert_eq!(Some(false), ignore_file.matches(Path::new("/movies/Heat/keep.part"), false));
+    assert_eq!(Some(true), ignore_file.matches(Path::new("/movies/Unsorted"), true));
+    assert_eq!(None, ignore_file.matches(Path::new("/movies/Unsorted"), false));
+    assert_eq!(None, ignore_file.matches(Path::new("/movies/Heat/Unsorted"), true));
+    assert_eq!(Some(true), ignore_file.matches(Path::new("/movies/Boxsets/Alien/Bonus"), true));
+    assert_eq!(None, ignore_file.matches(Path::new("/movies/Boxsets/Alien/Aliens/Bonus"), true));
+    assert_eq!(Some(true), ignore_file.matches(Path::new("/movies/#hash.mkv"), false));
+    assert_eq!(None, ignore_file.matches(Path::new("/shows/Heat.part"), false));
+}
+
+#[test]
+fn samples_trailers_extras_and_hidden_files_are_skipped() {
+    let dir = ::tempdir::TempDir::new("carolus_ignore").unwrap();
+    let root = dir.path();
+    create_tree(root, &[
+        "Heat (1995)/Heat (1995).mkv",
+        "Heat (1995)/Sample/heat-sample.mkv",
+        "Heat (1995)/Trailers/Heat trailer.mkv",
+        "Heat (1995)/Extras/Making of.mkv",
+        "Heat (1995)/.Heat (1995).mkv",
+        ".recycle/Alien (1979).mkv",
+        "Alien (1979).mkv",
+    ]);
+    assert_eq!(vec!["Alien (1979).mkv", "Heat (1995)/Heat (1995).mkv"], relative_files(root, root));
+    assert!(is_ignored(root, &root.join("Heat (1995)/Extras/Making of.mkv")));
+    assert!(!is_ignored(root, &root.join("Heat (1995)/Heat (1995).mkv")));
+}
+
+#[test]
+fn ignore_files_apply_below_their_directory() {
+    let dir = ::tempdir::TempDir::new("carolus_ignore").unwrap();
+    let root = dir.path();
+    create_tree(root, &[
+        "Alien (1979).mkv",
+        "Alien (1979).part.mkv",
+        "Unsorted/Heat (1995).mkv",
+        "Documentaries/Extras/Jiro Dreams of Sushi (2011).mkv",
+        "Documentaries/Making of Heat.mkv",
+        "Documentaries/Heat (1995).part.mkv",
+    ]);
+    write_ignore_file(root, "*.part.mkv\nUnsorted/\n");
+    // A deeper ignore file overrides the defaults and the root's rules
+    write_ignore_file(&root.join("Documentaries"), "!Extras/\n!*.part.mkv\nMaking of*\n");
+    assert_eq!(vec![
+        "Alien (1979).mkv",
+        "Documentaries/Extras/Jiro Dreams of Sushi (2011).mkv",
+        "Documentaries/Heat (1995).part.mkv",
+    ], relative_files(root, root));
+    assert!(relative_files(root, &root.join("Unsorted")).is_empty());
+    assert!(is_ignored(root, &root.join("Unsorted/Heat (1995).mkv")));
+    assert!(is_ignored(root, &root.join("Documentaries/Making of Heat.mkv")));
+    assert!(!is_ignored(root, &root.join("Documentaries/Extras/Jiro Dreams of Sushi (2011).mkv")));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_directories_are_walked_once() {
+    use std::os::unix::fs::symlink;
+    let dir = ::tempdir::TempDir::new("carolus_ignore").unwrap();
+    let root = dir.path().join("movies");
+    let elsewhere = dir.path().join("elsewhere");
+    create_tree(&root, &["Heat (1995)/Heat (1995).mkv"]);
+    create_tree(&elsewhere, &["Alien (1979).mkv"]);
+    symlink(&elsewhere, root.join("Linked")).unwrap();
+    symlink(&root, root.join("Heat (1995)/Loop")).unwrap();
+    assert_eq!(vec!["Heat (1995)/Heat (1995).mkv", "Linked/Alien (1979).mkv"], relative_files(&root, &root));
+}
@@ -1,37 +1,324 @@
 
-use std::env;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
-use glob::glob;
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
 
-use data::init::establish_connection;
-use data::movies::create_movie;
+use data::media::save_media_info;
+use data::models::{Episode, Movie};
+use data::movies::{all_movies, available_movies, create_movie, find_moved_movie, find_movie_by_path, set_available, update_movie_file, update_movie_path, MovieFile};
+use media::bytes::invalid;
+use media::probe;
+use provider::MetadataLookup;
 
-use file_index::file_name::{self, ParseResult};
+use file_index::file_name;
+use file_index::fingerprint;
+use file_index::ignore;
+use file_index::library::{libraries, LibraryKind, LibraryFilter};
+use file_index::scan::ScanJob;
+use file_index::shows;
 
-fn index_movie_directory(add_movie: &Fn(&PathBuf)) {
-    match env::var("CAROLUS_MOVIES_PATH") {
-        Ok (directories) => {
-            for directory in directories.split(",") {
-                for file in glob(&format!("{}/**/*.mp4", &directory)).unwrap().filter_map(Result::ok) {
-                    add_movie(&file);
-                }
-            }
-        },
-        Err(_) => (),
+/// Files indexed per transaction during a scan.
+pub const BATCH_SIZE: usize = 50;
+
+pub const MOVIE_EXTENSIONS: &'static [&'static str] = &["mp4", "m4v", "mkv", "webm", "avi", "ts"];
+
+pub fn is_movie_file(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => MOVIE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Movie files below `directory`, leaving out anything ignored between it
+/// and the library `root`.
+pub fn movie_files_in(root: &Path, directory: &Path) -> Vec<PathBuf> {
+    ignore::files_in(root, directory).into_iter().filter(|p| is_movie_file(p)).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileOutcome {
+    New,
+    Changed,
+    Moved,
+    Unchanged,
+}
+
+/// Counts of what a scan found, logged when it finishes.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ScanStats {
+    pub new: usize,
+    pub changed: usize,
+    pub moved: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub errors: usize,
+}
+
+impl ScanStats {
+    pub fn record(&mut self, outcome: io::Result<FileOutcome>) {
+        match outcome {
+            Ok(FileOutcome::New) => self.new += 1,
+            Ok(FileOutcome::Changed) => self.changed += 1,
+            Ok(FileOutcome::Moved) => self.moved += 1,
+            Ok(FileOutcome::Unchanged) => self.unchanged += 1,
+            Err(_) => self.errors += 1,
+        }
+    }
+}
+
+impl fmt::Display for ScanStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} new, {} changed, {} moved, {} unchanged, {} removed, {} errors",
+            self.new, self.changed, self.moved, self.unchanged, self.removed, self.errors)
+    }
+}
+
+/// The size, modification time and inode of a file, which are cheap to
+/// read and together change whenever the content is likely to have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileStamp {
+    pub size: i64,
+    pub modified: Option<i64>,
+    pub inode: Option<i64>,
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<i64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino() as i64)
+}
+
+#[cfg(not(unix))]
+fn inode(_: &fs::Metadata) -> Option<i64> {
+    None
+}
+
+impl FileStamp {
+    pub fn read(path: &Path) -> io::Result<FileStamp> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs() as i64);
+        Ok(FileStamp { size: metadata.len() as i64, modified: modified, inode: inode(&metadata) })
+    }
+
+    /// Whether the movie was indexed from a file with this stamp.
+    pub fn matches(&self, movie: &Movie) -> bool {
+        movie.fingerprint.is_some()
+            && movie.file_size == Some(self.size)
+            && movie.file_modified == self.modified
+            && movie.file_inode == self.inode
+    }
+
+    /// Whether the episode was indexed from a file with this stamp.
+    pub fn matches_episode(&self, episode: &Episode) -> bool {
+        episode.file_size == Some(self.size)
+            && episode.file_modified == self.modified
+            && episode.file_inode == self.inode
+    }
+}
+
+/// Adds or refreshes a single movie file, then looks up its metadata with
+/// the providers of the library it is in.
+pub fn index_file(conn: &SqliteConnection, movie_path: &Path) -> io::Result<FileOutcome> {
+    let existing = match movie_path.to_str() {
+        Some(file_path) => find_movie_by_path(conn, file_path),
+        None => None,
+    };
+    let providers = libraries(conn).into_iter()
+        .find(|library| library.filter().contains(movie_path))
+        .map_or_else(Vec::new, |library| library.providers());
+    let mut lookups = Vec::new();
+    let outcome = index_known_file(conn, movie_path, existing, &mut lookups);
+    for lookup in lookups {
+        lookup.run(conn, &providers);
     }
+    outcome
+}
+
+fn metadata_lookup(movie_id: i32, movie_path: &Path, title: String, year: Option<i32>, unchanged: bool) -> MetadataLookup {
+    MetadataLookup { movie_id: movie_id, path: movie_path.to_path_buf(), title: title, year: year, unchanged: unchanged }
 }
 
-pub fn index() {
-    let conn = establish_connection();
-    index_movie_directory(&|movie_path|{
-        match file_name::parse(movie_path) {
-            Ok(ParseResult::Movie{ title, ..}) => {
-                let file_path = movie_path.to_str().unwrap();
+/// Unchanged files are skipped without reading them. A new path with the
+/// same size and fingerprint as a movie whose file has gone is taken to be
+/// that movie moved, and the existing row is re-pointed so its history
+/// survives. Metadata lookups are queued on `lookups` rather than made
+/// here, as this runs inside the scan's transaction.
+fn index_known_file(conn: &SqliteConnection, movie_path: &Path, existing: Option<Movie>, lookups: &mut Vec<MetadataLookup>) -> io::Result<FileOutcome> {
+    let file_path = movie_path.to_str().ok_or_else(|| invalid("file path is not valid unicode"))?;
+    let stamp = FileStamp::read(movie_path)?;
+    if let Some(ref movie) = existing {
+        if stamp.matches(movie) {
+            if !movie.available {
+                info!("Movie available again: {}", movie.title);
+                set_available(conn, movie.id, true);
+            }
+            let (title, year) = file_name::movie_title_and_year(movie_path)?;
+            lookups.push(metadata_lookup(movie.id, movie_path, title, year, true));
+            return Ok(FileOutcome::Unchanged);
+        }
+    }
+    let (title, year) = file_name::movie_title_and_year(movie_path)?;
+    let fingerprint = fingerprint::fingerprint(movie_path)?;
+    let movie_file = MovieFile {
+        size: stamp.size,
+        modified: stamp.modified,
+        inode: stamp.inode,
+        fingerprint: &fingerprint,
+    };
+    let (movie, outcome) = match existing {
+        Some(movie) => {
+            info!("Update movie: {}", movie.title);
+            update_movie_file(conn, movie.id, &movie_file);
+            (movie, FileOutcome::Changed)
+        },
+        None => match find_moved_movie(conn, stamp.size, &fingerprint) {
+            Some(movie) => {
+                info!("Move movie: {} to {}", movie.title, file_path);
+                update_movie_path(conn, movie.id, &title, file_path);
+                update_movie_file(conn, movie.id, &movie_file);
+                return Ok(FileOutcome::Moved);
+            },
+            None => {
                 info!("Add movie: {}", title);
-                create_movie(&conn, &title, &file_path);
+                (create_movie(conn, &title, file_path, &movie_file), FileOutcome::New)
             },
-            Err(err) => error!("Unexpected error parsing file: {}", err)
-        };
-    });
+        },
+    };
+    match probe::probe(movie_path) {
+        Ok(info) => {
+            if let Err(err) = save_media_info(conn, movie.id, &info) {
+                error!("Error saving media info for {}: {}", file_path, err);
+            }
+        },
+        Err(err) => warn!("Could not probe {}: {}", file_path, err),
+    }
+    lookups.push(metadata_lookup(movie.id, movie_path, title, year, false));
+    Ok(outcome)
+}
+
+/// Marks movies whose files have gone missing as unavailable, keeping
+/// their rows for when the file comes back or turns up somewhere else.
+pub fn reconcile(conn: &SqliteConnection) -> usize {
+    let mut removed = 0;
+    for movie in available_movies(conn) {
+        if !Path::new(&movie.file_path).exists() {
+            info!("Movie unavailable: {}", movie.title);
+            set_available(conn, movie.id, false);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// Indexes a batch of files in one transaction, scans commit a batch at a
+/// time so the watcher and requests aren't locked out for a whole scan.
+/// False if the job was cancelled part way, keeping what was done so far.
+pub fn index_batch<F>(conn: &SqliteConnection, job: &ScanJob, batch: &[PathBuf], mut index: F) -> bool
+    where F: FnMut(&Path) -> io::Result<FileOutcome>
+{
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        for file in batch {
+            if job.is_cancelled() {
+                return Ok(());
+            }
+            job.started_file(file);
+            job.finished_file(file, index(file));
+        }
+        Ok(())
+    }).expect("Error indexing files");
+    !job.is_cancelled()
+}
+
+/// Indexes the job's library, looking up what is already known about all
+/// files in a single query. Metadata for each batch is looked up once it
+/// has been committed.
+fn scan_movies(conn: &SqliteConnection, job: &ScanJob, filter: &LibraryFilter) {
+    let providers = job.library.providers();
+    let mut known : HashMap<String, Movie> = all_movies(conn).into_iter()
+        .map(|movie| (movie.file_path.clone(), movie))
+        .collect();
+    for directory in job.library.directories() {
+        let files = filter.movie_files_in(&directory);
+        for batch in files.chunks(BATCH_SIZE) {
+            let mut lookups = Vec::new();
+            let finished = index_batch(conn, job, batch, |file| {
+                let existing = file.to_str().and_then(|file_path| known.remove(file_path));
+                index_known_file(conn, file, existing, &mut lookups)
+            });
+            for lookup in lookups {
+                lookup.run(conn, &providers);
+            }
+            if !finished {
+                return;
+            }
+        }
+    }
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        // Movies indexed before an ignore file or exclude glob left them out
+        let excluded = known.values()
+            .filter(|movie| movie.available && filter.contains(Path::new(&movie.file_path)))
+            .filter(|movie| Path::new(&movie.file_path).exists());
+        let mut removed = 0;
+        for movie in excluded {
+            info!("Movie excluded: {}", movie.title);
+            set_available(conn, movie.id, false);
+            removed += 1;
+        }
+        job.removed(removed + reconcile(conn));
+        Ok(())
+    }).expect("Error indexing movies");
+}
+
+/// Indexes the job's library as movies or, for show libraries, episodes.
+pub fn scan(conn: &SqliteConnection, job: &ScanJob) {
+    let filter = job.library.filter();
+    match job.library.kind {
+        LibraryKind::Shows => shows::scan(conn, job, &filter),
+        LibraryKind::Movies | LibraryKind::HomeVideos => scan_movies(conn, job, &filter),
+    }
+}
+
+#[test]
+fn movie_extensions() {
+    assert!(is_movie_file(Path::new("/movies/Alien (1979).mkv")));
+    assert!(is_movie_file(Path::new("Heat.MP4")));
+    assert!(is_movie_file(Path::new("recording.ts")));
+    assert!(!is_movie_file(Path::new("Alien (1979).srt")));
+    assert!(!is_movie_file(Path::new("README")));
+}
+
+#[test]
+fn unchanged_files_match_their_stamp() {
+    use std::io::Write;
+    use chrono::NaiveDate;
+    let dir = ::tempdir::TempDir::new("carolus_index").unwrap();
+    let path = dir.path().join("Heat (1995).mkv");
+    fs::File::create(&path).unwrap().write_all(b"movie").unwrap();
+    let stamp = FileStamp::read(&path).unwrap();
+    let mut movie = Movie {
+        id: 1,
+        title: "Heat".to_string(),
+        file_path: path.to_str().unwrap().to_string(),
+        created_date: NaiveDate::from_ymd(2017, 12, 1).and_hms(0, 0, 0),
+        available: true,
+        file_size: Some(5),
+        fingerprint: Some("fingerprint".to_string()),
+        file_modified: stamp.modified,
+        file_inode: stamp.inode,
+    };
+    assert!(stamp.matches(&movie));
+    movie.file_size = Some(6);
+    assert!(!stamp.matches(&movie));
+    movie.file_size = Some(5);
+    movie.fingerprint = None;
+    assert!(!stamp.matches(&movie));
 }
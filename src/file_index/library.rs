@@ -0,0 +1,343 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use glob::Pattern;
+use serde_json;
+
+use data::libraries::{all_libraries, create_library, find_library, update_library};
+use data::models::{self, NewLibrary};
+use file_index::cron::Schedule;
+use file_index::ignore::is_ignored;
+use file_index::index::movie_files_in;
+use provider::{self, MetadataProvider, PROVIDERS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LibraryKind {
+    Movies,
+    Shows,
+    HomeVideos,
+}
+
+impl LibraryKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LibraryKind::Movies => "movies",
+            LibraryKind::Shows => "shows",
+            LibraryKind::HomeVideos => "home_videos",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<LibraryKind> {
+        match kind {
+            "movies" => Some(LibraryKind::Movies),
+            "shows" => Some(LibraryKind::Shows),
+            "home_videos" => Some(LibraryKind::HomeVideos),
+            _ => None,
+        }
+    }
+}
+
+fn watch_by_default() -> bool {
+    true
+}
+
+fn default_providers() -> Vec<String> {
+    PROVIDERS.iter().map(|name| name.to_string()).collect()
+}
+
+/// A named set of directories indexed together. The id is assigned by the
+/// database and never read from a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Library {
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    pub name: String,
+    pub kind: LibraryKind,
+    pub root_paths: Vec<String>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default = "watch_by_default")]
+    pub watch: bool,
+    #[serde(default)]
+    pub scan_schedule: Option<String>,
+    /// Metadata providers in priority order, each field is taken from the
+    /// first one that has it.
+    #[serde(default = "default_providers")]
+    pub metadata_providers: Vec<String>,
+}
+
+fn string_list(json: &str) -> Vec<String> {
+    serde_json::from_str(json).unwrap_or_else(|_| Vec::new())
+}
+
+fn compile(globs: &[String]) -> Vec<Pattern> {
+    globs.iter().filter_map(|glob| Pattern::new(glob).ok()).collect()
+}
+
+impl Library {
+    pub fn from_model(library: models::Library) -> Library {
+        let kind = LibraryKind::parse(&library.kind).unwrap_or(LibraryKind::Movies);
+        Library {
+            id: library.id,
+            // Show libraries saved before watching was refused for them
+            watch: library.watch && kind != LibraryKind::Shows,
+            kind: kind,
+            root_paths: string_list(&library.root_paths),
+            include: string_list(&library.include_globs),
+            exclude: string_list(&library.exclude_globs),
+            name: library.name,
+            scan_schedule: library.scan_schedule,
+            metadata_providers: string_list(&library.metadata_providers),
+        }
+    }
+
+    /// Checks what the database can't: that there is somewhere to scan and
+    /// that the globs and schedule parse.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if self.root_paths.is_empty() {
+            return Err("root_paths must not be empty".to_string());
+        }
+        if let Some(root) = self.root_paths.iter().find(|root| !Path::new(root).is_absolute()) {
+            return Err(format!("root path '{}' is not absolute", root));
+        }
+        for glob in self.include.iter().chain(self.exclude.iter()) {
+            if let Err(err) = Pattern::new(glob) {
+                return Err(format!("invalid glob '{}': {}", glob, err.msg));
+            }
+        }
+        if let Some(name) = self.metadata_providers.iter().find(|name| !PROVIDERS.contains(&name.as_str())) {
+            return Err(format!("unknown metadata provider '{}', expected one of {}", name, PROVIDERS.join(", ")));
+        }
+        if self.watch && !self.has_movies() {
+            return Err("show libraries can't be watched yet, set watch to false".to_string());
+        }
+        if let Some(ref expression) = self.scan_schedule {
+            let schedule = expression.parse::<Schedule>()
+                .map_err(|err| format!("invalid scan_schedule: {}", err))?;
+            if schedule.next_after(Local::now().naive_local()).is_none() {
+                return Err(format!("scan_schedule '{}' never matches", expression));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn directories(&self) -> Vec<PathBuf> {
+        self.root_paths.iter().map(PathBuf::from).collect()
+    }
+
+    pub fn filter(&self) -> LibraryFilter {
+        LibraryFilter {
+            roots: self.directories(),
+            include: compile(&self.include),
+            exclude: compile(&self.exclude),
+        }
+    }
+
+    /// The library's metadata providers that are configured.
+    pub fn providers(&self) -> Vec<Box<MetadataProvider>> {
+        provider::providers(&self.metadata_providers)
+    }
+
+    /// Show libraries are indexed as episodes rather than movies.
+    pub fn has_movies(&self) -> bool {
+        self.kind != LibraryKind::Shows
+    }
+
+    fn with_new_library<F, T>(&self, f: F) -> T
+        where F: FnOnce(&NewLibrary) -> T
+    {
+        let root_paths = serde_json::to_string(&self.root_paths).unwrap();
+        let include_globs = serde_json::to_string(&self.include).unwrap();
+        let exclude_globs = serde_json::to_string(&self.exclude).unwrap();
+        let metadata_providers = serde_json::to_string(&self.metadata_providers).unwrap();
+        f(&NewLibrary {
+            name: &self.name,
+            kind: self.kind.as_str(),
+            root_paths: &root_paths,
+            include_globs: &include_globs,
+            exclude_globs: &exclude_globs,
+            watch: self.watch,
+            scan_schedule: self.scan_schedule.as_ref().map(|schedule| schedule.as_str()),
+            metadata_providers: &metadata_providers,
+        })
+    }
+}
+
+/// Which files below a library's roots belong to it. Globs are matched
+/// against the path relative to its root, so `Extras/**` or `**/*sample*`
+/// work wherever the library lives, and files left out by `.carolusignore`
+/// files or the default rules never are.
+pub struct LibraryFilter {
+    roots: Vec<PathBuf>,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl LibraryFilter {
+    pub fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.roots.iter().find(|root| path.starts_with(root)).map(|root| root.as_path())
+    }
+
+    fn matches_globs(&self, root: &Path, path: &Path) -> bool {
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        let included = self.include.is_empty() || self.include.iter().any(|glob| glob.matches_path(relative));
+        included && !self.exclude.iter().any(|glob| glob.matches_path(relative))
+    }
+
+    pub fn includes(&self, path: &Path) -> bool {
+        match self.root_of(path) {
+            Some(root) => self.matches_globs(root, path) && !is_ignored(root, path),
+            None => false,
+        }
+    }
+
+    pub fn movie_files_in(&self, directory: &Path) -> Vec<PathBuf> {
+        let root = match self.root_of(directory) {
+            Some(root) => root,
+            None => return Vec::new(),
+        };
+        movie_files_in(root, directory).into_iter().filter(|file| self.matches_globs(root, file)).collect()
+    }
+}
+
+pub fn libraries(conn: &SqliteConnection) -> Vec<Library> {
+    all_libraries(conn).into_iter().map(Library::from_model).collect()
+}
+
+pub fn find(conn: &SqliteConnection, library_id: i32) -> Option<Library> {
+    find_library(conn, library_id).map(Library::from_model)
+}
+
+pub fn create(conn: &SqliteConnection, library: &Library) -> Library {
+    Library::from_model(library.with_new_library(|new_library| create_library(conn, new_library)))
+}
+
+pub fn update(conn: &SqliteConnection, library_id: i32, library: &Library) -> Option<Library> {
+    library.with_new_library(|new_library| update_library(conn, library_id, new_library))
+        .map(Library::from_model)
+}
+
+fn env_movie_directories() -> Vec<String> {
+    match env::var("CAROLUS_MOVIES_PATH") {
+        Ok (directories) => directories.split(",").map(String::from).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Creates a movies library from `CAROLUS_MOVIES_PATH` the first time the
+/// server starts without any libraries, so existing setups keep working.
+pub fn seed_from_env(conn: &SqliteConnection) {
+    let directories = env_movie_directories();
+    if directories.is_empty() || !all_libraries(conn).is_empty() {
+        return;
+    }
+    let library = Library {
+        id: 0,
+        name: "Movies".to_string(),
+        kind: LibraryKind::Movies,
+        root_paths: directories,
+        include: Vec::new(),
+        exclude: Vec::new(),
+        watch: true,
+        scan_schedule: None,
+        metadata_providers: default_providers(),
+    };
+    info!("Creating library {} from CAROLUS_MOVIES_PATH", library.name);
+    create(conn, &library);
+}
+
+#[cfg(test)]
+pub fn test_library(name: &str, root_paths: &[&str]) -> Library {
+    Library {
+        id: 0,
+        name: name.to_string(),
+        kind: LibraryKind::Movies,
+        root_paths: root_paths.iter().map(|root| root.to_string()).collect(),
+        include: Vec::new(),
+        exclude: Vec::new(),
+        watch: true,
+        scan_schedule: None,
+        metadata_providers: default_providers(),
+    }
+}
+
+#[test]
+fn requests_fill_in_defaults() {
+    let library : Library = serde_json::from_str(r#"{
+        "id": 12,
+        "name": "Home videos",
+        "kind": "home_videos",
+        "root_paths": ["/media/camera"]
+    }"#).unwrap();
+    assert_eq!(0, library.id);
+    assert_eq!(LibraryKind::HomeVideos, library.kind);
+    assert!(library.include.is_empty() && library.exclude.is_empty());
+    assert!(library.watch);
+    assert_eq!(None, library.scan_schedule);
+    assert_eq!(vec!["nfo", "tmdb", "omdb"], library.metadata_providers);
+}
+
+#[test]
+fn validation() {
+    assert_eq!(Ok(()), test_library("Movies", &["/movies"]).validate());
+    assert!(test_library("", &["/movies"]).validate().is_err());
+    assert!(test_library("Movies", &[]).validate().is_err());
+    assert!(test_library("Movies", &["movies"]).validate().is_err());
+
+    let mut library = test_library("Movies", &["/movies"]);
+    library.exclude = vec!["[unclosed".to_string()];
+    assert!(library.validate().is_err());
+
+    let mut library = test_library("Movies", &["/movies"]);
+    library.scan_schedule = Some("0 3 * *".to_string());
+    assert!(library.validate().is_err());
+    library.scan_schedule = Some("0 3 * * *".to_string());
+    assert_eq!(Ok(()), library.validate());
+
+    library.metadata_providers = vec!["tmdb".to_string(), "imdb".to_string()];
+    assert!(library.validate().is_err());
+
+    let mut library = test_library("Shows", &["/shows"]);
+    library.kind = LibraryKind::Shows;
+    assert!(library.validate().is_err());
+    library.watch = false;
+    assert_eq!(Ok(()), library.validate());
+}
+
+#[test]
+fn globs_match_below_the_root() {
+    let mut library = test_library("Movies", &["/movies", "/archive/films"]);
+    library.exclude = vec!["Extras/**".to_string(), "**/*sample*".to_string()];
+    let filter = library.filter();
+    assert!(filter.includes(Path::new("/movies/Heat (1995).mkv")));
+    assert!(filter.includes(Path::new("/archive/films/Alien (1979)/Alien (1979).mkv")));
+    assert!(!filter.includes(Path::new("/movies/Extras/Heat - Making of.mkv")));
+    assert!(!filter.includes(Path::new("/movies/Heat (1995)/heat-sample.mkv")));
+    assert!(!filter.includes(Path::new("/shows/Heat (1995).mkv")));
+
+    library.include = vec!["*.mkv".to_string()];
+    let filter = library.filter();
+    assert!(filter.includes(Path::new("/movies/Heat (1995).mkv")));
+    assert!(!filter.includes(Path::new("/movies/Heat (1995).avi")));
+}
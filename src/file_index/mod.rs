@@ -5,5 +5,13 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 
+pub mod cron;
 mod file_name;
+pub mod fingerprint;
+pub mod ignore;
 pub mod index;
+pub mod library;
+pub mod scan;
+pub mod schedule;
+pub mod shows;
+pub mod watcher;
@@ -0,0 +1,292 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+
+use chrono::prelude::*;
+
+use data::init::establish_connection;
+use file_index::index::{self, FileOutcome, ScanStats};
+use file_index::library::Library;
+
+/// Finished scans kept around for their progress to be looked up.
+const MAX_FINISHED_SCANS: usize = 32;
+
+/// Only the first few errors are kept, a broken mount can fail every file.
+const MAX_ERRORS: usize = 100;
+
+static NEXT_SCAN_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanState {
+    Running,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanProgress {
+    pub id: usize,
+    pub library: String,
+    pub state: ScanState,
+    pub files_seen: usize,
+    pub current_path: Option<String>,
+    pub errors: Vec<String>,
+    pub stats: ScanStats,
+    pub started: String,
+    pub finished: Option<String>,
+}
+
+/// One scan of a library, updated by the indexer as it goes.
+pub struct ScanJob {
+    pub library: Library,
+    cancelled: AtomicBool,
+    progress: Mutex<ScanProgress>,
+}
+
+impl ScanJob {
+    pub fn new(library: Library) -> ScanJob {
+        ScanJob {
+            cancelled: AtomicBool::new(false),
+            progress: Mutex::new(ScanProgress {
+                id: NEXT_SCAN_ID.fetch_add(1, Ordering::SeqCst) + 1,
+                library: library.name.clone(),
+                state: ScanState::Running,
+                files_seen: 0,
+                current_path: None,
+                errors: Vec::new(),
+                stats: ScanStats::default(),
+                started: Utc::now().to_rfc3339(),
+                finished: None,
+            }),
+            library: library,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.progress.lock().unwrap().id
+    }
+
+    pub fn progress(&self) -> ScanProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.progress.lock().unwrap().state == ScanState::Running
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn started_file(&self, path: &Path) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.files_seen += 1;
+        progress.current_path = Some(path.display().to_string());
+    }
+
+    pub fn finished_file(&self, path: &Path, outcome: io::Result<FileOutcome>) {
+        let mut progress = self.progress.lock().unwrap();
+        if let Err(ref err) = outcome {
+            error!("Could not index {}: {}", path.display(), err);
+            if progress.errors.len() < MAX_ERRORS {
+                progress.errors.push(format!("{}: {}", path.display(), err));
+            }
+        }
+        progress.stats.record(outcome);
+    }
+
+    pub fn removed(&self, count: usize) {
+        self.progress.lock().unwrap().stats.removed += count;
+    }
+
+    pub fn finish(&self, state: ScanState) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.state = state;
+        progress.current_path = None;
+        progress.finished = Some(Utc::now().to_rfc3339());
+    }
+}
+
+/// Runs scans on background threads, at most one per library at a time.
+/// Scans of different libraries queue up behind each other rather than
+/// compete for the database. Clones share their jobs, so the API and the
+/// scheduler see the same scans.
+#[derive(Clone)]
+pub struct Scanner {
+    runner: Arc<Fn(&ScanJob) + Send + Sync>,
+    jobs: Arc<Mutex<HashMap<usize, Arc<ScanJob>>>>,
+    run_lock: Arc<Mutex<()>>,
+}
+
+fn index_job(job: &ScanJob) {
+    let conn = establish_connection();
+    index::scan(&conn, job);
+}
+
+impl Scanner {
+    pub fn new() -> Scanner {
+        Scanner::with_runner(Arc::new(index_job))
+    }
+
+    pub fn with_runner(runner: Arc<Fn(&ScanJob) + Send + Sync>) -> Scanner {
+        Scanner {
+            runner: runner,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            run_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Starts scanning the library, or hands back the scan already running
+    /// for it. Libraries are told apart by id, so renaming one mid-scan
+    /// doesn't start a second scan of it.
+    pub fn start(&self, library: Library) -> Arc<ScanJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.values().find(|job| job.is_running() && job.library.id == library.id) {
+            return job.clone();
+        }
+        let finished = jobs.values().filter(|job| !job.is_running()).count();
+        if finished >= MAX_FINISHED_SCANS {
+            let oldest = jobs.values().filter(|job| !job.is_running()).map(|job| job.id()).min();
+            if let Some(oldest) = oldest {
+                jobs.remove(&oldest);
+            }
+        }
+
+        let job = Arc::new(ScanJob::new(library));
+        jobs.insert(job.id(), job.clone());
+        let (runner, running, run_lock) = (self.runner.clone(), job.clone(), self.run_lock.clone());
+        thread::spawn(move || {
+            let _turn = run_lock.lock().unwrap();
+            info!("Scanning library {}", running.library.name);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| runner(&running)));
+            let state = match result {
+                Err(_) => ScanState::Failed,
+                Ok(()) if running.is_cancelled() => ScanState::Cancelled,
+                Ok(()) => ScanState::Finished,
+            };
+            running.finish(state);
+            let progress = running.progress();
+            info!("Scan of library {} {:?}: {}", progress.library, progress.state, progress.stats);
+        });
+        job
+    }
+
+    pub fn is_running(&self, library: i32) -> bool {
+        self.jobs.lock().unwrap().values().any(|job| job.is_running() && job.library.id == library)
+    }
+
+    pub fn job(&self, id: usize) -> Option<Arc<ScanJob>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+}
+
+#[cfg(test)]
+fn wait_until_done(job: &ScanJob) {
+    use std::time::Duration;
+    for _ in 0..200 {
+        if !job.is_running() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("scan did not finish");
+}
+
+#[cfg(test)]
+fn library(id: i32, name: &str) -> Library {
+    let mut library = ::file_index::library::test_library(name, &["/movies"]);
+    library.id = id;
+    library
+}
+
+#[test]
+fn scans_of_the_same_library_are_coalesced() {
+    use std::time::Duration;
+    let scanner = Scanner::with_runner(Arc::new(|job: &ScanJob| {
+        while !job.is_cancelled() {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }));
+    let movies = scanner.start(library(1, "movies"));
+    let renamed = scanner.start(library(1, "films"));
+    let shows = scanner.start(library(2, "shows"));
+    assert_eq!(movies.id(), renamed.id());
+    assert!(movies.id() != shows.id());
+    assert!(scanner.clone().is_running(1));
+    assert!(!scanner.is_running(3));
+
+    movies.cancel();
+    shows.cancel();
+    wait_until_done(&movies);
+    wait_until_done(&shows);
+    assert_eq!(ScanState::Cancelled, scanner.job(movies.id()).unwrap().progress().state);
+
+    let next = scanner.start(library(1, "movies"));
+    assert!(next.id() != movies.id());
+    next.cancel();
+    wait_until_done(&next);
+}
+
+#[test]
+fn scans_run_one_at_a_time() {
+    use std::time::Duration;
+    let active = Arc::new(AtomicUsize::new(0));
+    let overlapped = Arc::new(AtomicBool::new(false));
+    let (scanning, overlap) = (active.clone(), overlapped.clone());
+    let scanner = Scanner::with_runner(Arc::new(move |_: &ScanJob| {
+        if scanning.fetch_add(1, Ordering::SeqCst) > 0 {
+            overlap.store(true, Ordering::SeqCst);
+        }
+        thread::sleep(Duration::from_millis(20));
+        scanning.fetch_sub(1, Ordering::SeqCst);
+    }));
+    let jobs : Vec<_> = ["movies", "shows", "home videos"].iter().enumerate()
+        .map(|(id, name)| scanner.start(library(id as i32 + 1, name)))
+        .collect();
+    for job in &jobs {
+        wait_until_done(job);
+    }
+    assert!(!overlapped.load(Ordering::SeqCst));
+}
+
+#[test]
+fn progress_is_reported() {
+    let scanner = Scanner::with_runner(Arc::new(|job: &ScanJob| {
+        job.started_file(Path::new("/movies/Heat (1995).mkv"));
+        job.finished_file(Path::new("/movies/Heat (1995).mkv"), Ok(FileOutcome::New));
+        job.started_file(Path::new("/movies/broken.mkv"));
+        job.finished_file(Path::new("/movies/broken.mkv"), Err(io::Error::new(io::ErrorKind::Other, "unreadable")));
+    }));
+    let job = scanner.start(library(1, "movies"));
+    wait_until_done(&job);
+    let progress = job.progress();
+    assert_eq!(ScanState::Finished, progress.state);
+    assert_eq!(2, progress.files_seen);
+    assert_eq!((1, 1), (progress.stats.new, progress.stats.errors));
+    assert_eq!(vec!["/movies/broken.mkv: unreadable".to_string()], progress.errors);
+    assert_eq!(None, progress.current_path);
+}
+
+#[test]
+fn panicking_scans_fail() {
+    let scanner = Scanner::with_runner(Arc::new(|_: &ScanJob| panic!("database is locked")));
+    let job = scanner.start(library(1, "movies"));
+    wait_until_done(&job);
+    assert_eq!(ScanState::Failed, job.progress().state);
+}
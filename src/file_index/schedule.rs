@@ -0,0 +1,127 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+use data::init::establish_connection;
+use data::scan_runs::{last_scan_run, record_scan_run};
+use diesel::sqlite::SqliteConnection;
+use file_index::cron::Schedule;
+use file_index::library::{libraries, Library};
+use file_index::scan::Scanner;
+
+/// The scheduler wakes at least this often, so a clock change, a suspended
+/// machine or a library whose schedule was just changed isn't missed.
+const MAX_SLEEP_SECONDS: i64 = 60;
+
+fn schedule_from_env() -> Option<Schedule> {
+    let expression = match env::var("CAROLUS_SCAN_SCHEDULE") {
+        Ok(expression) => expression,
+        Err(_) => return None,
+    };
+    match expression.parse() {
+        Ok(schedule) => Some(schedule),
+        Err(err) => {
+            error!("Invalid CAROLUS_SCAN_SCHEDULE '{}': {}", expression, err);
+            None
+        },
+    }
+}
+
+/// A library's own schedule, falling back to `CAROLUS_SCAN_SCHEDULE`.
+fn schedule_of(library: &Library, default: &Option<Schedule>) -> Option<Schedule> {
+    match library.scan_schedule {
+        Some(ref expression) => expression.parse().ok(),
+        None => default.clone(),
+    }
+}
+
+/// When the next scan is due, in local time as the expression is written.
+/// A run missed while the server was down is due straight away.
+pub fn next_run(schedule: &Schedule, last_run: Option<DateTime<Local>>, now: DateTime<Local>) -> Option<NaiveDateTime> {
+    let after = last_run.unwrap_or(now).naive_local();
+    schedule.next_after(after)
+}
+
+fn run_scheduled_scan(conn: &SqliteConnection, scanner: &Scanner, library: Library) {
+    let (id, name) = (library.id, library.name.clone());
+    if scanner.is_running(id) {
+        info!("Skipping scheduled scan of {}, a scan is already running", name);
+    } else {
+        info!("Starting scheduled scan of {}", name);
+        scanner.start(library);
+    }
+    record_scan_run(conn, id, Utc::now().naive_utc());
+}
+
+/// Seconds until the library's next scheduled scan, running it if it is
+/// due. None if the library isn't scheduled.
+fn check_library(conn: &SqliteConnection, scanner: &Scanner, library: Library, default: &Option<Schedule>) -> Option<i64> {
+    let schedule = match schedule_of(&library, default) {
+        Some(schedule) => schedule,
+        None => return None,
+    };
+    let last_run = last_scan_run(conn, library.id)
+        .map(|run| DateTime::<Utc>::from_utc(run, Utc).with_timezone(&Local));
+    let now = Local::now();
+    let due = match next_run(&schedule, last_run, now) {
+        Some(due) => due,
+        None => return None,
+    };
+    let wait = (due - now.naive_local()).num_seconds();
+    if wait <= 0 {
+        run_scheduled_scan(conn, scanner, library);
+        None
+    } else {
+        Some(wait)
+    }
+}
+
+/// Rescans each library on its `scan_schedule` cron expression, or on
+/// `CAROLUS_SCAN_SCHEDULE` for libraries without one. Useful for
+/// directories the watcher can't follow such as network shares.
+pub fn spawn(scanner: Scanner) {
+    let default = schedule_from_env();
+    thread::spawn(move || {
+        loop {
+            let conn = establish_connection();
+            let mut sleep = MAX_SLEEP_SECONDS;
+            for library in libraries(&conn) {
+                if let Some(wait) = check_library(&conn, &scanner, library, &default) {
+                    sleep = cmp::min(sleep, wait);
+                }
+            }
+            thread::sleep(Duration::from_secs(sleep as u64));
+        }
+    });
+}
+
+#[cfg(test)]
+fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Local.ymd(year, month, day).and_hms(hour, minute, 0)
+}
+
+#[test]
+fn first_run_waits_for_the_schedule() {
+    let schedule : Schedule = "0 3 * * *".parse().unwrap();
+    let due = next_run(&schedule, None, local(2017, 12, 14, 12, 0));
+    assert_eq!(Some(local(2017, 12, 15, 3, 0).naive_local()), due);
+}
+
+#[test]
+fn missed_runs_are_caught_up() {
+    let schedule : Schedule = "0 3 * * *".parse().unwrap();
+    let last_run = Some(local(2017, 12, 10, 3, 0));
+    let now = local(2017, 12, 14, 12, 0);
+    let due = next_run(&schedule, last_run, now).unwrap();
+    assert_eq!(local(2017, 12, 11, 3, 0).naive_local(), due);
+    assert!(due < now.naive_local());
+}
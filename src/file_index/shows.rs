@@ -0,0 +1,227 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use chrono::prelude::*;
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use data::models::{Episode, NewEpisode};
+use data::shows::{all_episodes, available_episodes, find_or_create_season, find_or_create_show, save_episode, set_episode_available};
+use media::bytes::invalid;
+
+use file_index::file_name::{self, EpisodeNumber, ParseResult};
+use file_index::index::{index_batch, FileOutcome, FileStamp, BATCH_SIZE};
+use file_index::library::LibraryFilter;
+use file_index::scan::ScanJob;
+
+/// Where an episode goes in its show.
+#[derive(Debug, PartialEq)]
+pub struct Numbering {
+    pub season: i32,
+    pub episode: Option<i32>,
+    pub last_episode: Option<i32>,
+    pub absolute: Option<i32>,
+    pub air_date: Option<NaiveDate>,
+}
+
+fn first_and_last(numbers: &[i32]) -> (Option<i32>, Option<i32>) {
+    let last = if numbers.len() > 1 { numbers.last().cloned() } else { None };
+    (numbers.first().cloned(), last)
+}
+
+/// Episodes named by air date go in a season for their year and absolutely
+/// numbered ones in the first season, unless they are in a season folder.
+pub fn numbering(number: &EpisodeNumber, season_folder: Option<i32>) -> Numbering {
+    match *number {
+        EpisodeNumber::Season { season, ref episodes } => {
+            let (episode, last_episode) = first_and_last(episodes);
+            Numbering { season: season, episode: episode, last_episode: last_episode, absolute: None, air_date: None }
+        },
+        EpisodeNumber::Date(date) => Numbering {
+            season: season_folder.unwrap_or(date.year()),
+            episode: None,
+            last_episode: None,
+            absolute: None,
+            air_date: Some(date),
+        },
+        EpisodeNumber::Absolute(ref numbers) => {
+            let (episode, last_episode) = first_and_last(numbers);
+            Numbering {
+                season: season_folder.unwrap_or(1),
+                episode: episode,
+                last_episode: last_episode,
+                absolute: episode,
+                air_date: None,
+            }
+        },
+    }
+}
+
+/// The season of a folder named `Season 2`, `S02` or `Specials`.
+pub fn season_of_folder(name: &str) -> Option<i32> {
+    let name = name.trim().to_lowercase();
+    if name == "specials" {
+        return Some(0);
+    }
+    let number = if name.starts_with("season") {
+        &name["season".len()..]
+    } else if name.starts_with('s') {
+        &name[1..]
+    } else {
+        return None;
+    };
+    number.trim_left_matches(|c: char| c == ' ' || c == '.' || c == '_').parse().ok()
+}
+
+/// The folders between the library root and the file.
+fn folders_of<'a>(root: &Path, path: &'a Path) -> Vec<&'a str> {
+    match path.parent().and_then(|parent| parent.strip_prefix(root).ok()) {
+        Some(relative) => relative.iter().filter_map(|folder| folder.to_str()).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Adds or refreshes an episode file in a show library. The show is the
+/// first folder below the library root, `Doctor Who (2005)/Season 1/...`,
+/// or the show named in the file for episodes directly in the root.
+pub fn index_episode_file(conn: &SqliteConnection, root: &Path, path: &Path, existing: Option<Episode>) -> io::Result<FileOutcome> {
+    let file_path = path.to_str().ok_or_else(|| invalid("file path is not valid unicode"))?;
+    let stamp = FileStamp::read(path)?;
+    if let Some(ref episode) = existing {
+        if stamp.matches_episode(episode) {
+            if !episode.available {
+                info!("Episode available again: {}", episode.file_path);
+                set_episode_available(conn, episode.id, true);
+            }
+            return Ok(FileOutcome::Unchanged);
+        }
+    }
+    let (named_show, number, title) = match file_name::parse(path)? {
+        ParseResult::Episode { show, year, number, title, .. } => ((show, year), number, title),
+        ParseResult::Movie { .. } => return Err(invalid("no episode number in the file name")),
+    };
+    let folders = folders_of(root, path);
+    let (show_title, show_year) = match folders.first() {
+        Some(folder) => file_name::title_and_year(folder),
+        None => named_show,
+    };
+    if show_title.is_empty() {
+        return Err(invalid("no show name in the path"));
+    }
+    let season_folder = folders.iter().skip(1).filter_map(|folder| season_of_folder(folder)).last();
+    let numbering = numbering(&number, season_folder);
+
+    let show = find_or_create_show(conn, &show_title, show_year);
+    let season = find_or_create_season(conn, show.id, numbering.season);
+    let outcome = match existing {
+        Some(_) => {
+            info!("Update episode: {}", file_path);
+            FileOutcome::Changed
+        },
+        None => {
+            info!("Add episode: {} {:?}", show.title, number);
+            FileOutcome::New
+        },
+    };
+    save_episode(conn, &NewEpisode {
+        season_id: season.id,
+        episode_number: numbering.episode,
+        last_episode_number: numbering.last_episode,
+        absolute_number: numbering.absolute,
+        air_date: numbering.air_date,
+        title: title.as_ref().map(|title| title.as_str()),
+        file_path: file_path,
+        created_date: Utc::now().naive_utc(),
+        file_size: Some(stamp.size),
+        file_modified: stamp.modified,
+        file_inode: stamp.inode,
+    });
+    Ok(outcome)
+}
+
+/// Marks episodes whose files have gone missing as unavailable.
+pub fn reconcile_episodes(conn: &SqliteConnection) -> usize {
+    let mut removed = 0;
+    for episode in available_episodes(conn) {
+        if !Path::new(&episode.file_path).exists() {
+            info!("Episode unavailable: {}", episode.file_path);
+            set_episode_available(conn, episode.id, false);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// Indexes a show library the same way movie libraries are, in batches
+/// with what is already known looked up up front.
+pub fn scan(conn: &SqliteConnection, job: &ScanJob, filter: &LibraryFilter) {
+    let mut known : HashMap<String, Episode> = all_episodes(conn).into_iter()
+        .map(|episode| (episode.file_path.clone(), episode))
+        .collect();
+    for root in job.library.directories() {
+        let files = filter.movie_files_in(&root);
+        for batch in files.chunks(BATCH_SIZE) {
+            let finished = index_batch(conn, job, batch, |file| {
+                let existing = file.to_str().and_then(|file_path| known.remove(file_path));
+                index_episode_file(conn, &root, file, existing)
+            });
+            if !finished {
+                return;
+            }
+        }
+    }
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let excluded = known.values()
+            .filter(|episode| episode.available && filter.contains(Path::new(&episode.file_path)))
+            .filter(|episode| Path::new(&episode.file_path).exists());
+        let mut removed = 0;
+        for episode in excluded {
+            info!("Episode excluded: {}", episode.file_path);
+            set_episode_available(conn, episode.id, false);
+            removed += 1;
+        }
+        job.removed(removed + reconcile_episodes(conn));
+        Ok(())
+    }).expect("Error indexing episodes");
+}
+
+#[test]
+fn season_folders() {
+    assert_eq!(Some(2), season_of_folder("Season 2"));
+    assert_eq!(Some(12), season_of_folder("Season.12"));
+    assert_eq!(Some(3), season_of_folder("S03"));
+    assert_eq!(Some(0), season_of_folder("Specials"));
+    assert_eq!(None, season_of_folder("Sherlock"));
+    assert_eq!(None, season_of_folder("Extras"));
+}
+
+#[test]
+fn episodes_are_numbered_within_a_season() {
+    let number = EpisodeNumber::Season { season: 2, episodes: vec![1, 2] };
+    assert_eq!(Numbering { season: 2, episode: Some(1), last_episode: Some(2), absolute: None, air_date: None },
+        numbering(&number, Some(5)));
+
+    let date = NaiveDate::from_ymd(2017, 12, 14);
+    assert_eq!(Numbering { season: 2017, episode: None, last_episode: None, absolute: None, air_date: Some(date) },
+        numbering(&EpisodeNumber::Date(date), None));
+
+    let number = EpisodeNumber::Absolute(vec![890]);
+    assert_eq!(Numbering { season: 1, episode: Some(890), last_episode: None, absolute: Some(890), air_date: None },
+        numbering(&number, None));
+    assert_eq!(20, numbering(&number, Some(20)).season);
+}
+
+#[test]
+fn show_folders() {
+    let root = Path::new("/shows");
+    assert_eq!(vec!["Doctor Who (2005)", "Season 1"], folders_of(root, Path::new("/shows/Doctor Who (2005)/Season 1/S01E01.mkv")));
+    assert!(folders_of(root, Path::new("/shows/Lost.S01E01.mkv")).is_empty());
+}
@@ -0,0 +1,263 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use diesel::sqlite::SqliteConnection;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+
+use data::init::establish_connection;
+use data::movies::{find_movie_by_path, movies_under, set_available, update_movie_path};
+use file_index::file_name;
+use file_index::index::{index_file, is_movie_file};
+use file_index::library::{libraries, Library, LibraryFilter};
+use file_index::scan::Scanner;
+
+/// How long notify coalesces events for a path before reporting them.
+const DEBOUNCE_SECONDS: u64 = 2;
+
+/// A file is only indexed once its size has stopped changing for this
+/// long, so downloads and copies aren't probed half written.
+const SETTLE_SECONDS: u64 = 10;
+
+/// Files seen changing, with their size and when it last changed.
+pub struct PendingFiles {
+    settle: Duration,
+    files: HashMap<PathBuf, (Option<u64>, Instant)>,
+}
+
+impl PendingFiles {
+    pub fn new(settle: Duration) -> PendingFiles {
+        PendingFiles { settle: settle, files: HashMap::new() }
+    }
+
+    pub fn touch(&mut self, path: PathBuf, size: Option<u64>, now: Instant) {
+        self.files.insert(path, (size, now));
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Files whose size hasn't changed for the settle time. Files that have
+    /// disappeared are dropped, ones still growing wait for another poll.
+    pub fn poll(&mut self, now: Instant, size_of: &Fn(&Path) -> Option<u64>) -> Vec<PathBuf> {
+        let mut settled = Vec::new();
+        let mut gone = Vec::new();
+        for (path, entry) in self.files.iter_mut() {
+            let size = size_of(path);
+            if size.is_none() {
+                gone.push(path.clone());
+            } else if size != entry.0 {
+                *entry = (size, now);
+            } else if now.duration_since(entry.1) >= self.settle {
+                settled.push(path.clone());
+            }
+        }
+        for path in gone.iter().chain(settled.iter()) {
+            self.files.remove(path);
+        }
+        settled.sort();
+        settled
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+fn title_of(path: &Path) -> Option<String> {
+    file_name::movie_title(path).ok()
+}
+
+fn library_of<'a>(filters: &'a [LibraryFilter], path: &Path) -> Option<&'a LibraryFilter> {
+    filters.iter().find(|filter| filter.contains(path))
+}
+
+/// Whether a movie file at `path` belongs in one of the watched libraries.
+fn indexable(filters: &[LibraryFilter], path: &Path) -> bool {
+    is_movie_file(path) && library_of(filters, path).map_or(false, |filter| filter.includes(path))
+}
+
+fn changed(filters: &[LibraryFilter], pending: &mut PendingFiles, path: PathBuf) {
+    let filter = match library_of(filters, &path) {
+        Some(filter) => filter,
+        None => return,
+    };
+    if path.is_dir() {
+        for file in filter.movie_files_in(&path) {
+            let size = file_size(&file);
+            pending.touch(file, size, Instant::now());
+        }
+    } else if is_movie_file(&path) && filter.includes(&path) {
+        let size = file_size(&path);
+        pending.touch(path, size, Instant::now());
+    }
+}
+
+fn removed(conn: &SqliteConnection, pending: &mut PendingFiles, path: &Path) {
+    pending.forget(path);
+    let path = match path.to_str() {
+        Some(path) => path,
+        None => return,
+    };
+    let movies = find_movie_by_path(conn, path).into_iter().chain(movies_under(conn, path));
+    for movie in movies.filter(|movie| movie.available) {
+        info!("Movie unavailable: {}", movie.title);
+        set_available(conn, movie.id, false);
+    }
+}
+
+fn moved(conn: &SqliteConnection, filters: &[LibraryFilter], pending: &mut PendingFiles, from: &Path, to: PathBuf) {
+    let (from_str, to_str) = match (from.to_str(), to.to_str()) {
+        (Some(from), Some(to)) => (from.to_string(), to.to_string()),
+        _ => return,
+    };
+    if to.is_dir() {
+        for movie in movies_under(conn, &from_str) {
+            let new_path = format!("{}{}", to_str, &movie.file_path[from_str.len()..]);
+            if indexable(filters, Path::new(&new_path)) {
+                info!("Move movie: {} to {}", movie.title, new_path);
+                update_movie_path(conn, movie.id, &movie.title, &new_path);
+            } else {
+                removed(conn, pending, Path::new(&movie.file_path));
+            }
+        }
+        return;
+    }
+    let title = if indexable(filters, &to) { title_of(&to) } else { None };
+    match (find_movie_by_path(conn, &from_str), title) {
+        (Some(movie), Some(title)) => {
+            info!("Move movie: {} to {}", movie.title, to_str);
+            update_movie_path(conn, movie.id, &title, &to_str);
+        },
+        _ => {
+            removed(conn, pending, from);
+            changed(filters, pending, to);
+        },
+    }
+}
+
+fn handle(conn: &SqliteConnection, scanner: &Scanner, watched: &[Library], filters: &[LibraryFilter], pending: &mut PendingFiles, event: DebouncedEvent) {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => changed(filters, pending, path),
+        DebouncedEvent::Remove(path) => removed(conn, pending, &path),
+        DebouncedEvent::Rename(from, to) => moved(conn, filters, pending, &from, to),
+        DebouncedEvent::Rescan => {
+            info!("Watcher lost events, rescanning");
+            for library in watched {
+                scanner.start(library.clone());
+            }
+        },
+        DebouncedEvent::Error(err, path) => error!("Watcher error for {:?}: {}", path, err),
+        DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) | DebouncedEvent::Chmod(_) => (),
+    }
+}
+
+/// Watches the directories of every movie library with watching turned on,
+/// keeping the movies table in step with files being added, removed and
+/// moved. Removed files are only marked unavailable, so a move the watcher
+/// sees as a delete and a create is still matched up by fingerprint.
+/// Libraries added or changed later are picked up on the next start. If
+/// events are lost the watched libraries are rescanned with `scanner`.
+pub fn spawn(scanner: Scanner) {
+    let watched : Vec<_> = libraries(&establish_connection()).into_iter()
+        .filter(|library| library.watch && library.has_movies())
+        .collect();
+    if watched.is_empty() {
+        return;
+    }
+    let directories : Vec<PathBuf> = watched.iter().flat_map(|library| library.directories()).collect();
+    let filters : Vec<LibraryFilter> = watched.iter().map(|library| library.filter()).collect();
+    thread::spawn(move || {
+        let (tx, rx) = channel();
+        let mut watcher = match watcher(tx, Duration::from_secs(DEBOUNCE_SECONDS)) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                error!("Could not start the file watcher: {}", err);
+                return;
+            },
+        };
+        for directory in &directories {
+            match watcher.watch(directory, RecursiveMode::Recursive) {
+                Ok(()) => info!("Watching {}", directory.display()),
+                Err(err) => error!("Could not watch {}: {}", directory.display(), err),
+            }
+        }
+        let conn = establish_connection();
+        let mut pending = PendingFiles::new(Duration::from_secs(SETTLE_SECONDS));
+        loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(event) => handle(&conn, &scanner, &watched, &filters, &mut pending, event),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            if !pending.is_empty() {
+                for path in pending.poll(Instant::now(), &file_size) {
+                    if let Err(err) = index_file(&conn, &path) {
+                        error!("Could not index {}: {}", path.display(), err);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn files_are_indexed_once_their_size_settles() {
+    use std::cell::Cell;
+    let start = Instant::now();
+    let settle = Duration::from_secs(10);
+    let mut pending = PendingFiles::new(settle);
+    let size = Cell::new(Some(100));
+    let path = PathBuf::from("/movies/Alien (1979).mkv");
+    pending.touch(path.clone(), Some(100), start);
+
+    assert!(pending.poll(start + Duration::from_secs(5), &|_| size.get()).is_empty());
+
+    // Still being written, so the clock starts again.
+    size.set(Some(200));
+    assert!(pending.poll(start + Duration::from_secs(11), &|_| size.get()).is_empty());
+    assert!(pending.poll(start + Duration::from_secs(20), &|_| size.get()).is_empty());
+    assert_eq!(vec![path], pending.poll(start + Duration::from_secs(21), &|_| size.get()));
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn deleted_files_are_dropped() {
+    let start = Instant::now();
+    let mut pending = PendingFiles::new(Duration::from_secs(10));
+    pending.touch(PathBuf::from("/movies/partial.mkv"), Some(100), start);
+    assert!(pending.poll(start + Duration::from_secs(20), &|_| None).is_empty());
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn moves_out_of_a_library_are_not_indexable() {
+    use std::fs::File;
+    let dir = ::tempdir::TempDir::new("carolus_watcher").unwrap();
+    let movies = dir.path().join("movies");
+    for folder in &["Alien (1979)", "Extras"] {
+        fs::create_dir_all(movies.join(folder)).unwrap();
+        File::create(movies.join(folder).join("Alien (1979).mkv")).unwrap();
+    }
+    let mut library = ::file_index::library::test_library("Movies", &[movies.to_str().unwrap()]);
+    library.exclude = vec!["Extras/**".to_string()];
+    let filters = vec![library.filter()];
+
+    assert!(indexable(&filters, &movies.join("Alien (1979)/Alien (1979).mkv")));
+    assert!(!indexable(&filters, &movies.join("Extras/Alien (1979).mkv")));
+    assert!(!indexable(&filters, &dir.path().join("Alien (1979).mkv")));
+}
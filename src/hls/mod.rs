@@ -0,0 +1,127 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+pub mod playlist;
+pub mod presentation;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use rocket::{Route, State};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{Body, Response, Responder};
+use rocket::response::content::Content;
+
+use hls::presentation::Presentation;
+use movies::playable_movie;
+use partial_file::body::{Segment, SegmentedBody};
+
+const MAX_CACHED_PRESENTATIONS: usize = 16;
+
+struct CachedPresentation {
+    modified: Option<SystemTime>,
+    presentation: Arc<Presentation>,
+    used: Instant,
+}
+
+/// Parsed sample tables of recently played movies, so that every segment
+/// request doesn't re-read the whole moov. The least recently used one is
+/// dropped to make room for another.
+pub struct HlsCache {
+    presentations: Mutex<HashMap<i32, CachedPresentation>>,
+}
+
+impl HlsCache {
+    pub fn new() -> HlsCache {
+        HlsCache { presentations: Mutex::new(HashMap::new()) }
+    }
+
+    /// The movie's presentation, or None if there is no such movie or its
+    /// file has gone missing.
+    pub fn presentation(&self, movie_id: i32) -> io::Result<Option<Arc<Presentation>>> {
+        let movie = match playable_movie(movie_id) {
+            Some(movie) => movie,
+            None => return Ok(None),
+        };
+        let modified = fs::metadata(&movie.file_path)?.modified().ok();
+        if let Some(cached) = self.presentations.lock().unwrap().get_mut(&movie_id) {
+            if cached.modified == modified && cached.presentation.path().to_str() == Some(movie.file_path.as_str()) {
+                cached.used = Instant::now();
+                return Ok(Some(cached.presentation.clone()));
+            }
+        }
+        let presentation = Arc::new(Presentation::open(Path::new(&movie.file_path))?);
+        let mut presentations = self.presentations.lock().unwrap();
+        if presentations.len() >= MAX_CACHED_PRESENTATIONS && !presentations.contains_key(&movie_id) {
+            let oldest = presentations.iter().min_by_key(|&(_, cached)| cached.used).map(|(&id, _)| id);
+            if let Some(oldest) = oldest {
+                presentations.remove(&oldest);
+            }
+        }
+        presentations.insert(movie_id, CachedPresentation {
+            modified: modified,
+            presentation: presentation.clone(),
+            used: Instant::now(),
+        });
+        Ok(Some(presentation))
+    }
+}
+
+pub struct MediaSegment {
+    file: File,
+    segments: Vec<Segment>,
+}
+
+impl Responder<'static> for MediaSegment {
+    fn respond_to(self, _: &Request) -> Result<Response<'static>, Status> {
+        let body = SegmentedBody::new(BufReader::new(self.file), self.segments);
+        let body_len = body.len();
+        let mut response = Response::new();
+        response.set_header(ContentType::new("video", "mp4"));
+        response.set_raw_body(Body::Sized(body, body_len));
+        Ok(response)
+    }
+}
+
+fn playlist_type() -> ContentType {
+    ContentType::new("application", "vnd.apple.mpegurl")
+}
+
+#[get("/<movie_id>/hls/master.m3u8")]
+pub fn master_playlist(movie_id: i32, cache: State<HlsCache>) -> io::Result<Option<Content<String>>> {
+    Ok(cache.presentation(movie_id)?.map(|presentation| Content(playlist_type(), playlist::master(&presentation))))
+}
+
+#[get("/<movie_id>/hls/media.m3u8")]
+pub fn media_playlist(movie_id: i32, cache: State<HlsCache>) -> io::Result<Option<Content<String>>> {
+    Ok(cache.presentation(movie_id)?.map(|presentation| Content(playlist_type(), playlist::media(&presentation))))
+}
+
+#[get("/<movie_id>/hls/init.mp4")]
+pub fn init_segment(movie_id: i32, cache: State<HlsCache>) -> io::Result<Option<Content<Vec<u8>>>> {
+    Ok(cache.presentation(movie_id)?.map(|presentation| Content(ContentType::new("video", "mp4"), presentation.init_segment())))
+}
+
+#[get("/<movie_id>/hls/segments/<index>")]
+pub fn media_segment(movie_id: i32, index: usize, cache: State<HlsCache>) -> io::Result<Option<MediaSegment>> {
+    let presentation = match cache.presentation(movie_id)? {
+        Some(presentation) => presentation,
+        None => return Ok(None),
+    };
+    match presentation.media_segment(index) {
+        Some(segments) => Ok(Some(MediaSegment { file: File::open(presentation.path())?, segments: segments })),
+        None => Ok(None),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![master_playlist, media_playlist, init_segment, media_segment]
+}
@@ -0,0 +1,38 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt::Write;
+
+use hls::presentation::Presentation;
+
+pub fn master(presentation: &Presentation) -> String {
+    let mut playlist = String::new();
+    let _ = writeln!(playlist, "#EXTM3U");
+    let _ = writeln!(playlist, "#EXT-X-VERSION:7");
+    let _ = writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS");
+    let _ = writeln!(playlist, "#EXT-X-STREAM-INF:BANDWIDTH={}", presentation.bandwidth());
+    let _ = writeln!(playlist, "media.m3u8");
+    playlist
+}
+
+pub fn media(presentation: &Presentation) -> String {
+    let durations : Vec<f64> = (0..presentation.segment_count()).map(|i| presentation.segment_duration(i)).collect();
+    let target = durations.iter().fold(0.0f64, |max, &d| max.max(d)).ceil() as u64;
+    let mut playlist = String::new();
+    let _ = writeln!(playlist, "#EXTM3U");
+    let _ = writeln!(playlist, "#EXT-X-VERSION:7");
+    let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target);
+    let _ = writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:0");
+    let _ = writeln!(playlist, "#EXT-X-PLAYLIST-TYPE:VOD");
+    let _ = writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS");
+    let _ = writeln!(playlist, "#EXT-X-MAP:URI=\"init.mp4\"");
+    for (index, duration) in durations.iter().enumerate() {
+        let _ = writeln!(playlist, "#EXTINF:{:.3},", duration);
+        let _ = writeln!(playlist, "segments/{}", index);
+    }
+    let _ = writeln!(playlist, "#EXT-X-ENDLIST");
+    playlist
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use media::bytes::{invalid, put_u32, put_u64};
+use media::mp4::{self, write_box, write_full_box, Mp4, Sample, Track};
+use partial_file::body::Segment;
+
+/// Segments are cut at the first keyframe at least this far into them.
+pub const TARGET_SEGMENT_SECONDS: u64 = 6;
+
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+// trun flags: data offset, then per sample duration, size, flags and
+// composition time offset.
+const TRUN_FLAGS: u32 = 0x0000_0f01;
+
+/// A progressive MP4 laid out as fragmented MP4 for HLS: one init segment
+/// plus media segments cut at keyframes of the reference track, each built
+/// on demand from the sample tables with the sample data read straight out
+/// of the source file.
+#[derive(Debug)]
+pub struct Presentation {
+    path: PathBuf,
+    mp4: Mp4,
+    tracks: Vec<usize>,
+    segments: Vec<(u64, u64)>,
+}
+
+fn scale(time: u64, from: u32, to: u32) -> u64 {
+    time * to as u64 / from as u64
+}
+
+fn cut_segments(track: &Track) -> Vec<(u64, u64)> {
+    let target = TARGET_SEGMENT_SECONDS * track.timescale as u64;
+    let (first, last) = match (track.samples.first(), track.samples.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Vec::new(),
+    };
+    let mut segments = Vec::new();
+    let mut start = first.dts;
+    for sample in track.samples.iter().filter(|s| s.sync) {
+        if sample.dts >= start + target {
+            segments.push((start, sample.dts));
+            start = sample.dts;
+        }
+    }
+    let end = last.dts + last.duration as u64;
+    if end > start {
+        segments.push((start, end));
+    }
+    segments
+}
+
+impl Presentation {
+    pub fn open(path: &Path) -> io::Result<Presentation> {
+        let mp4 = mp4::read(&mut BufReader::new(File::open(path)?))?;
+        let video = mp4.tracks.iter().position(|t| t.is_video() && !t.samples.is_empty());
+        let audio = mp4.tracks.iter().position(|t| t.is_audio() && !t.samples.is_empty());
+        let tracks : Vec<usize> = video.into_iter().chain(audio.into_iter()).collect();
+        let segments = match tracks.first() {
+            Some(&reference) => cut_segments(&mp4.tracks[reference]),
+            None => return Err(invalid("no audio or video tracks")),
+        };
+        Ok(Presentation { path: path.to_path_buf(), mp4: mp4, tracks: tracks, segments: segments })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn reference(&self) -> &Track {
+        &self.mp4.tracks[self.tracks[0]]
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn segment_duration(&self, index: usize) -> f64 {
+        let (start, end) = self.segments[index];
+        (end - start) as f64 / self.reference().timescale as f64
+    }
+
+    /// The samples of every track decoded within the segment. The last
+    /// segment is open ended so no trailing audio is lost.
+    fn segment_samples(&self, index: usize) -> Vec<(&Track, &[Sample])> {
+        let (start, end) = self.segments[index];
+        let reference_timescale = self.reference().timescale;
+        let last = index + 1 == self.segments.len();
+        self.tracks.iter().map(|&t| {
+            let track = &self.mp4.tracks[t];
+            let from = track.sample_at(scale(start, reference_timescale, track.timescale));
+            let to = if last {
+                track.samples.len()
+            } else {
+                track.sample_at(scale(end, reference_timescale, track.timescale))
+            };
+            (track, &track.samples[from..cmp::max(from, to)])
+        }).collect()
+    }
+
+    /// Peak bitrate over all segments, in bits per second.
+    pub fn bandwidth(&self) -> u64 {
+        (0..self.segments.len()).map(|index| {
+            let bytes : u64 = self.segment_samples(index).iter()
+                .flat_map(|&(_, samples)| samples.iter())
+                .map(|s| s.size as u64)
+                .sum();
+            let duration = self.segment_duration(index);
+            if duration > 0.0 { (bytes as f64 * 8.0 / duration) as u64 } else { 0 }
+        }).max().unwrap_or(0)
+    }
+
+    pub fn init_segment(&self) -> Vec<u8> {
+        let ftyp = write_box(b"ftyp", b"iso5\0\0\x02\0iso5iso6mp41");
+        let mut moov = self.mp4.mvhd.clone();
+        let mut mvex = Vec::new();
+        for &t in &self.tracks {
+            let track = &self.mp4.tracks[t];
+            moov.extend(init_track(track));
+            let mut trex = Vec::new();
+            for value in &[track.id, 1, 0, 0, 0] {
+                put_u32(&mut trex, *value);
+            }
+            mvex.extend(write_full_box(b"trex", 0, 0, &trex));
+        }
+        moov.extend(write_box(b"mvex", &mvex));
+        [ftyp, write_box(b"moov", &moov)].concat()
+    }
+
+    /// The `moof` and `mdat` for a segment, as a header followed by the
+    /// ranges of the source file holding its samples.
+    pub fn media_segment(&self, index: usize) -> Option<Vec<Segment>> {
+        if index >= self.segments.len() {
+            return None;
+        }
+        let parts = self.segment_samples(index);
+        let sequence = index as u32 + 1;
+
+        // The data offsets depend on the size of the moof itself, which
+        // doesn't depend on the offsets, so build it once to measure it.
+        let placeholder = moof(sequence, &parts, &vec![0; parts.len()]);
+        let mut offsets = Vec::with_capacity(parts.len());
+        let mut data_offset = placeholder.len() as u64 + 8;
+        for &(_, samples) in &parts {
+            offsets.push(data_offset as u32);
+            data_offset += samples.iter().map(|s| s.size as u64).sum::<u64>();
+        }
+        let mdat_size = data_offset - placeholder.len() as u64;
+        if mdat_size > u32::max_value() as u64 {
+            return None;
+        }
+
+        let mut header = moof(sequence, &parts, &offsets);
+        put_u32(&mut header, mdat_size as u32);
+        header.extend_from_slice(b"mdat");
+
+        let mut segments = vec![Segment::Bytes(header)];
+        let mut pending : Option<(u64, u64)> = None;
+        for sample in parts.iter().flat_map(|&(_, samples)| samples.iter()).filter(|s| s.size > 0) {
+            let to = match sample.offset.checked_add(sample.size as u64) {
+                Some(end) => end - 1,
+                None => return None,
+            };
+            let from = sample.offset;
+            pending = match pending {
+                Some((start, end)) if end + 1 == from => Some((start, to)),
+                Some((start, end)) => {
+                    segments.push(Segment::File(start, end));
+                    Some((from, to))
+                },
+                None => Some((from, to)),
+            };
+        }
+        if let Some((start, end)) = pending {
+            segments.push(Segment::File(start, end));
+        }
+        Some(segments)
+    }
+}
+
+fn init_track(track: &Track) -> Vec<u8> {
+    let empty_tables = [
+        track.stsd.clone(),
+        write_full_box(b"stts", 0, 0, &[0; 4]),
+        write_full_box(b"stsc", 0, 0, &[0; 4]),
+        write_full_box(b"stsz", 0, 0, &[0; 8]),
+        write_full_box(b"stco", 0, 0, &[0; 4]),
+    ].concat();
+    let media_header = track.media_header.clone().unwrap_or_else(|| write_full_box(b"nmhd", 0, 0, &[]));
+    let dinf = track.dinf.clone().unwrap_or_else(|| {
+        let url = write_full_box(b"url ", 0, 1, &[]);
+        let mut dref = Vec::new();
+        put_u32(&mut dref, 1);
+        dref.extend(url);
+        write_box(b"dinf", &write_full_box(b"dref", 0, 0, &dref))
+    });
+    let minf = [media_header, dinf, write_box(b"stbl", &empty_tables)].concat();
+    let mdia = [track.mdhd.clone(), track.hdlr.clone(), write_box(b"minf", &minf)].concat();
+    write_box(b"trak", &[track.tkhd.clone(), write_box(b"mdia", &mdia)].concat())
+}
+
+fn moof(sequence: u32, parts: &[(&Track, &[Sample])], data_offsets: &[u32]) -> Vec<u8> {
+    let mut mfhd = Vec::new();
+    put_u32(&mut mfhd, sequence);
+    let mut moof = write_full_box(b"mfhd", 0, 0, &mfhd);
+    for (&(track, samples), &data_offset) in parts.iter().zip(data_offsets.iter()) {
+        let mut tfhd = Vec::new();
+        put_u32(&mut tfhd, track.id);
+        let mut tfdt = Vec::new();
+        put_u64(&mut tfdt, samples.first().map(|s| s.dts).unwrap_or(0));
+        let mut trun = Vec::with_capacity(8 + samples.len() * 16);
+        put_u32(&mut trun, samples.len() as u32);
+        put_u32(&mut trun, data_offset);
+        for sample in samples {
+            put_u32(&mut trun, sample.duration);
+            put_u32(&mut trun, sample.size);
+            put_u32(&mut trun, if sample.sync { SYNC_SAMPLE_FLAGS } else { NON_SYNC_SAMPLE_FLAGS });
+            put_u32(&mut trun, sample.cts_offset as u32);
+        }
+        let traf = [
+            // default-base-is-moof, so data offsets count from the moof
+            write_full_box(b"tfhd", 0, 0x02_0000, &tfhd),
+            write_full_box(b"tfdt", 1, 0, &tfdt),
+            write_full_box(b"trun", 1, TRUN_FLAGS, &trun),
+        ].concat();
+        moof.extend(write_box(b"traf", &traf));
+    }
+    write_box(b"moof", &moof)
+}
+
+#[cfg(test)]
+fn test_presentation() -> (::tempdir::TempDir, Presentation) {
+    use std::io::Write;
+    let dir = ::tempdir::TempDir::new("carolus_hls").unwrap();
+    let path = dir.path().join("movie.mp4");
+    File::create(&path).unwrap().write_all(&mp4::test_movie()).unwrap();
+    let presentation = Presentation::open(&path).unwrap();
+    (dir, presentation)
+}
+
+#[test]
+fn segments_start_on_keyframes() {
+    let (_dir, presentation) = test_presentation();
+    // Keyframes at 0s and 2s are both short of the target, so it all ends
+    // up in one segment.
+    assert_eq!(1, presentation.segment_count());
+    assert_eq!(4.0, presentation.segment_duration(0));
+}
+
+#[test]
+fn media_segment_points_at_source_samples() {
+    let (_dir, presentation) = test_presentation();
+    let segments = presentation.media_segment(0).unwrap();
+    assert_eq!(3, segments.len());
+    match segments[1] {
+        Segment::File(from, to) => assert_eq!((1000, 1009), (from, to)),
+        ref other => panic!("{:?}", other),
+    }
+    match segments[2] {
+        Segment::File(from, to) => assert_eq!((2000, 2089), (from, to)),
+        ref other => panic!("{:?}", other),
+    }
+    assert!(presentation.media_segment(1).is_none());
+}
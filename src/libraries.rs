@@ -0,0 +1,98 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use rocket::{Route, State};
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket_contrib::{Json, JsonValue};
+use diesel::sqlite::SqliteConnection;
+
+use data::init::establish_connection;
+use data::libraries::{delete_library, find_library_by_name};
+use data::movies::{movies_under, set_available};
+use data::shows::{episodes_under, set_episode_available};
+use file_index::library::{self, Library};
+use file_index::scan::Scanner;
+
+fn error(status: Status, message: String) -> Custom<JsonValue> {
+    Custom(status, json!({
+        "error": message,
+    }))
+}
+
+/// Checks the library and that no other library has its name.
+fn check(conn: &SqliteConnection, library_id: Option<i32>, library: &Library) -> Result<(), Custom<JsonValue>> {
+    library.validate().map_err(|message| error(Status::BadRequest, message))?;
+    match find_library_by_name(conn, &library.name) {
+        Some(ref existing) if Some(existing.id) != library_id =>
+            Err(error(Status::Conflict, format!("a library named '{}' already exists", library.name))),
+        _ => Ok(()),
+    }
+}
+
+#[get("/")]
+pub fn all_libraries() -> JsonValue {
+    let conn = establish_connection();
+    json!({
+        "libraries": library::libraries(&conn),
+    })
+}
+
+#[get("/<library_id>")]
+pub fn get_library(library_id: i32) -> Option<JsonValue> {
+    let conn = establish_connection();
+    library::find(&conn, library_id).map(|library| json!(library))
+}
+
+/// Creates the library and starts its first scan.
+#[post("/", format = "application/json", data = "<library>")]
+pub fn create_library(library: Json<Library>, scanner: State<Scanner>) -> Result<JsonValue, Custom<JsonValue>> {
+    let conn = establish_connection();
+    check(&conn, None, &library)?;
+    let created = library::create(&conn, &library);
+    info!("Created library {}", created.name);
+    scanner.start(created.clone());
+    Ok(json!(created))
+}
+
+#[put("/<library_id>", format = "application/json", data = "<library>")]
+pub fn update_library(library_id: i32, library: Json<Library>) -> Result<Option<JsonValue>, Custom<JsonValue>> {
+    let conn = establish_connection();
+    check(&conn, Some(library_id), &library)?;
+    Ok(library::update(&conn, library_id, &library).map(|library| json!(library)))
+}
+
+/// Removes the library. Its movies and episodes are kept, like any others
+/// whose files have gone, but marked unavailable.
+#[delete("/<library_id>")]
+pub fn remove_library(library_id: i32) -> Option<NoContent> {
+    let conn = establish_connection();
+    let library = match library::find(&conn, library_id) {
+        Some(library) => library,
+        None => return None,
+    };
+    for root in &library.root_paths {
+        for movie in movies_under(&conn, root) {
+            set_available(&conn, movie.id, false);
+        }
+        for episode in episodes_under(&conn, root) {
+            set_episode_available(&conn, episode.id, false);
+        }
+    }
+    delete_library(&conn, library_id);
+    info!("Removed library {}", library.name);
+    Some(NoContent)
+}
+
+#[post("/<library_id>/scan")]
+pub fn scan_library(library_id: i32, scanner: State<Scanner>) -> Option<JsonValue> {
+    let conn = establish_connection();
+    library::find(&conn, library_id).map(|library| json!(scanner.start(library).progress()))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![all_libraries, get_library, create_library, update_library, remove_library, scan_library]
+}
@@ -0,0 +1,41 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use rocket::{Route, State};
+use rocket_contrib::JsonValue;
+
+use data::init::establish_connection;
+use file_index::library::libraries;
+use file_index::scan::{ScanProgress, Scanner};
+
+/// Starts a scan of every library.
+#[post("/scan")]
+pub fn start_scan(scanner: State<Scanner>) -> JsonValue {
+    let conn = establish_connection();
+    let scans : Vec<ScanProgress> = libraries(&conn).into_iter()
+        .map(|library| scanner.start(library).progress())
+        .collect();
+    json!({
+        "scans": scans,
+    })
+}
+
+#[get("/scan/<scan_id>")]
+pub fn scan_progress(scan_id: usize, scanner: State<Scanner>) -> Option<JsonValue> {
+    scanner.job(scan_id).map(|job| json!(job.progress()))
+}
+
+#[delete("/scan/<scan_id>")]
+pub fn cancel_scan(scan_id: usize, scanner: State<Scanner>) -> Option<JsonValue> {
+    scanner.job(scan_id).map(|job| {
+        job.cancel();
+        json!(job.progress())
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![start_scan, scan_progress, cancel_scan]
+}
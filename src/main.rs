@@ -17,19 +17,79 @@
 extern crate regex;
 extern crate blake2;
 extern crate glob;
+extern crate notify;
 extern crate base64;
 extern crate rocket;
 extern crate serde;
+extern crate serde_json;
 extern crate chrono;
+extern crate reqwest;
+extern crate url;
+extern crate xml;
+extern crate image;
+#[cfg(test)] extern crate tempdir;
 
 pub mod data;
 pub mod partial_file;
+pub mod media;
 pub mod movies;
+pub mod hls;
+pub mod transcode;
 pub mod file_index;
+pub mod admin;
+pub mod library;
+pub mod libraries;
+pub mod shows;
+pub mod provider;
+pub mod artwork;
 
-use file_index::index;
+use std::env;
+
+use artwork::ArtworkCache;
+use data::init::establish_connection;
+use file_index::library::{libraries, seed_from_env};
+use file_index::scan::Scanner;
+use file_index::schedule;
+use file_index::watcher;
+use hls::HlsCache;
+use partial_file::throttle::Throttle;
+use provider::nfo;
+use transcode::Transcoder;
+
+/// `carolus export-nfo [--overwrite]` writes an NFO next to every movie
+/// with metadata and exits, leaving existing NFOs alone unless asked.
+fn export_nfo(args: &[String]) {
+    let overwrite = args.iter().any(|arg| arg == "--overwrite");
+    let stats = nfo::export(&establish_connection(), overwrite);
+    println!("{} NFOs written, {} existing NFOs kept, {} errors", stats.written, stats.kept, stats.errors);
+}
 
 fn main() {
-    index::index();
-    rocket::ignite().mount("/api/movies", movies::routes()).launch();
+    let args : Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("export-nfo") {
+        return export_nfo(&args[1..]);
+    }
+    let conn = establish_connection();
+    seed_from_env(&conn);
+    let scanner = Scanner::new();
+    for library in libraries(&conn) {
+        scanner.start(library);
+    }
+    watcher::spawn(scanner.clone());
+    schedule::spawn(scanner.clone());
+    rocket::ignite()
+        .manage(scanner)
+        .manage(Throttle::from_env())
+        .manage(HlsCache::new())
+        .manage(Transcoder::from_env())
+        .manage(ArtworkCache::from_env())
+        .mount("/api/movies", movies::routes())
+        .mount("/api/movies", hls::routes())
+        .mount("/api/movies", transcode::routes())
+        .mount("/api/movies", artwork::routes())
+        .mount("/api/admin", admin::routes())
+        .mount("/api/library", library::routes())
+        .mount("/api/libraries", libraries::routes())
+        .mount("/api/shows", shows::routes())
+        .launch();
 }
@@ -0,0 +1,114 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+/// Reads big-endian values out of an in-memory buffer, failing with
+/// `InvalidData` rather than panicking when the buffer runs out.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data: data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn seek(&mut self, position: usize) -> io::Result<()> {
+        if position > self.data.len() {
+            return Err(invalid("seek past end of buffer"));
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    pub fn skip(&mut self, count: usize) -> io::Result<()> {
+        self.take(count).map(|_| ())
+    }
+
+    pub fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < count {
+            return Err(invalid("unexpected end of data"));
+        }
+        let data = self.data;
+        let bytes = &data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        self.take(2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    pub fn u24(&mut self) -> io::Result<u32> {
+        self.take(3).map(|b| (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32)
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        self.take(4).map(be_u32)
+    }
+
+    pub fn i32(&mut self) -> io::Result<i32> {
+        self.u32().map(|v| v as i32)
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        self.take(8).map(|b| (be_u32(&b[..4]) as u64) << 32 | be_u32(&b[4..]) as u64)
+    }
+
+    pub fn fourcc(&mut self) -> io::Result<[u8; 4]> {
+        self.take(4).map(|b| [b[0], b[1], b[2], b[3]])
+    }
+}
+
+pub fn be_u32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+pub fn put_u64(out: &mut Vec<u8>, value: u64) {
+    put_u32(out, (value >> 32) as u32);
+    put_u32(out, value as u32);
+}
+
+#[test]
+fn reads_big_endian_values() {
+    let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+    let mut reader = ByteReader::new(&data);
+    assert_eq!(0x01, reader.u8().unwrap());
+    assert_eq!(0x0203, reader.u16().unwrap());
+    assert_eq!(0x040506, reader.u24().unwrap());
+    assert!(reader.u32().is_err());
+    assert_eq!(0x0708, reader.u16().unwrap());
+}
@@ -0,0 +1,143 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{self, Read};
+
+use media::bytes::{invalid, ByteReader};
+
+/// Size of an element whose end is only known by reading up to the next
+/// element of the same or a higher level, as in live streams.
+pub const UNKNOWN_SIZE: u64 = ::std::u64::MAX;
+
+/// An EBML element, borrowed from the buffer it was read from.
+#[derive(Debug, Clone, Copy)]
+pub struct Element<'a> {
+    pub id: u32,
+    pub data: &'a [u8],
+}
+
+fn vint_length(first: u8, max: u32) -> io::Result<usize> {
+    let length = first.leading_zeros() + 1;
+    if length > max {
+        return Err(invalid("invalid EBML variable length integer"));
+    }
+    Ok(length as usize)
+}
+
+/// Element ids keep their length marker bits, so they read the same as
+/// they are written in the specification.
+fn parse_id(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |id, &b| id << 8 | b as u32)
+}
+
+fn parse_size(bytes: &[u8]) -> u64 {
+    let length = bytes.len();
+    let value = bytes.iter().skip(1).fold((bytes[0] as u32 & 0xff >> length) as u64, |size, &b| size << 8 | b as u64);
+    if value == (1 << (7 * length)) - 1 { UNKNOWN_SIZE } else { value }
+}
+
+fn read_vint<'a>(reader: &mut ByteReader<'a>, max: u32) -> io::Result<&'a [u8]> {
+    let start = reader.position();
+    let length = vint_length(reader.u8()?, max)?;
+    reader.seek(start)?;
+    reader.take(length)
+}
+
+pub fn children<'a>(data: &'a [u8]) -> io::Result<Vec<Element<'a>>> {
+    let mut elements = Vec::new();
+    let mut reader = ByteReader::new(data);
+    while reader.remaining() > 0 {
+        let id = parse_id(read_vint(&mut reader, 4)?);
+        let mut size = parse_size(read_vint(&mut reader, 8)?);
+        if size == UNKNOWN_SIZE {
+            size = reader.remaining() as u64;
+        }
+        if size > reader.remaining() as u64 {
+            return Err(invalid("EBML element size out of bounds"));
+        }
+        elements.push(Element { id: id, data: reader.take(size as usize)? });
+    }
+    Ok(elements)
+}
+
+pub fn find<'a>(elements: &[Element<'a>], id: u32) -> Option<Element<'a>> {
+    elements.iter().find(|e| e.id == id).cloned()
+}
+
+/// Reads an element header from a stream, returning the id, the size of
+/// the payload and the length of the header itself.
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<(u32, u64, u64)> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer[..1])?;
+    let id_length = vint_length(buffer[0], 4)?;
+    reader.read_exact(&mut buffer[1..id_length])?;
+    let id = parse_id(&buffer[..id_length]);
+    reader.read_exact(&mut buffer[..1])?;
+    let size_length = vint_length(buffer[0], 8)?;
+    reader.read_exact(&mut buffer[1..size_length])?;
+    let size = parse_size(&buffer[..size_length]);
+    Ok((id, size, (id_length + size_length) as u64))
+}
+
+impl<'a> Element<'a> {
+    pub fn uint(&self) -> io::Result<u64> {
+        if self.data.len() > 8 {
+            return Err(invalid("EBML unsigned integer too long"));
+        }
+        Ok(self.data.iter().fold(0, |value, &b| value << 8 | b as u64))
+    }
+
+    pub fn float(&self) -> io::Result<f64> {
+        let bits = self.uint()?;
+        match self.data.len() {
+            0 => Ok(0.0),
+            4 => Ok(f32::from_bits(bits as u32) as f64),
+            8 => Ok(f64::from_bits(bits)),
+            _ => Err(invalid("EBML float must be 4 or 8 bytes")),
+        }
+    }
+
+    /// Strings are zero padded to their element size.
+    pub fn string(&self) -> String {
+        let end = self.data.iter().position(|&b| b == 0).unwrap_or(self.data.len());
+        String::from_utf8_lossy(&self.data[..end]).into_owned()
+    }
+}
+
+#[cfg(test)]
+pub fn write_element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 12);
+    let id_bytes = [(id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8];
+    let first = id_bytes.iter().position(|&b| b != 0).unwrap_or(3);
+    out.extend_from_slice(&id_bytes[first..]);
+    out.push(0x01);
+    for shift in (0..7).rev() {
+        out.push((payload.len() as u64 >> (shift * 8)) as u8);
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+#[test]
+fn reads_variable_length_sizes() {
+    assert_eq!(2, parse_size(&[0x82]));
+    assert_eq!(0x0123, parse_size(&[0x41, 0x23]));
+    assert_eq!(UNKNOWN_SIZE, parse_size(&[0xff]));
+    assert_eq!(UNKNOWN_SIZE, parse_size(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]));
+    assert!(vint_length(0, 8).is_err());
+}
+
+#[test]
+fn reads_nested_elements() {
+    let inner = write_element(0x4282, b"webm\0\0");
+    let outer = write_element(0x1A45DFA3, &inner);
+    let elements = children(&outer).unwrap();
+    assert_eq!(1, elements.len());
+    assert_eq!(0x1A45DFA3, elements[0].id);
+    let doc_type = find(&children(elements[0].data).unwrap(), 0x4282).unwrap();
+    assert_eq!("webm", doc_type.string());
+    assert_eq!((0x1A45DFA3, inner.len() as u64, 12), read_header(&mut io::Cursor::new(&outer)).unwrap());
+}
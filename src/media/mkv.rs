@@ -0,0 +1,292 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use media::bytes::invalid;
+use media::ebml::{self, find, read_header, Element, UNKNOWN_SIZE};
+use media::probe::Chapter;
+
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const LANGUAGE: u32 = 0x22B59C;
+const NAME: u32 = 0x536E;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CHAPTERS: u32 = 0x1043A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_TIME_END: u32 = 0x92;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+
+pub const TRACK_TYPE_VIDEO: u64 = 1;
+pub const TRACK_TYPE_AUDIO: u64 = 2;
+pub const TRACK_TYPE_SUBTITLE: u64 = 0x11;
+
+// Info, Tracks and Chapters are a few kilobytes, anything near this is a
+// broken or hostile file.
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MkvTrack {
+    pub number: u64,
+    pub track_type: u64,
+    pub codec_id: String,
+    pub language: Option<String>,
+    pub name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Matroska {
+    pub doc_type: String,
+    pub duration_ms: Option<u64>,
+    pub tracks: Vec<MkvTrack>,
+    pub chapters: Vec<Chapter>,
+}
+
+/// Reads the segment info, tracks and chapters of a Matroska or WebM file,
+/// seeking over the clusters in between.
+pub fn read<R: Read + Seek>(reader: &mut R) -> io::Result<Matroska> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let (id, size, _) = read_header(reader)?;
+    if id != EBML_HEADER || size > MAX_ELEMENT_SIZE {
+        return Err(invalid("not an EBML file"));
+    }
+    let header = read_payload(reader, size)?;
+    let doc_type = find(&ebml::children(&header)?, DOC_TYPE)
+        .map(|e| e.string())
+        .unwrap_or_else(|| "matroska".to_string());
+
+    let (id, size, _) = read_header(reader)?;
+    if id != SEGMENT {
+        return Err(invalid("no matroska segment"));
+    }
+    let mut position = reader.seek(SeekFrom::Current(0))?;
+    let segment_end = if size == UNKNOWN_SIZE { end } else { position + size };
+
+    let mut matroska = Matroska { doc_type: doc_type, duration_ms: None, tracks: Vec::new(), chapters: Vec::new() };
+    while position < segment_end && position < end {
+        let (id, size, header_len) = read_header(reader)?;
+        match id {
+            INFO | TRACKS | CHAPTERS => {
+                if size > MAX_ELEMENT_SIZE {
+                    return Err(invalid("matroska element too large"));
+                }
+                let data = read_payload(reader, size)?;
+                let children = ebml::children(&data)?;
+                match id {
+                    INFO => matroska.duration_ms = parse_duration(&children)?,
+                    TRACKS => matroska.tracks = parse_tracks(&children)?,
+                    _ => matroska.chapters = parse_chapters(&children)?,
+                }
+            },
+            // A cluster with an unknown size runs to the end of the file,
+            // there is nothing but media data after it.
+            _ if size == UNKNOWN_SIZE => break,
+            _ => (),
+        }
+        if size != UNKNOWN_SIZE {
+            position += header_len + size;
+            reader.seek(SeekFrom::Start(position))?;
+        }
+    }
+    Ok(matroska)
+}
+
+fn read_payload<R: Read>(reader: &mut R, size: u64) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; size as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn parse_duration(info: &[Element]) -> io::Result<Option<u64>> {
+    let scale = match find(info, TIMECODE_SCALE) {
+        Some(scale) => scale.uint()?,
+        None => DEFAULT_TIMECODE_SCALE,
+    };
+    match find(info, DURATION) {
+        Some(duration) => Ok(Some((duration.float()? * scale as f64 / 1_000_000.0) as u64)),
+        None => Ok(None),
+    }
+}
+
+fn optional_string(elements: &[Element], id: u32) -> Option<String> {
+    find(elements, id).map(|e| e.string()).and_then(|s| if s.is_empty() { None } else { Some(s) })
+}
+
+fn optional_uint(elements: &[Element], id: u32) -> io::Result<Option<u32>> {
+    match find(elements, id) {
+        Some(e) => Ok(Some(e.uint()? as u32)),
+        None => Ok(None),
+    }
+}
+
+fn parse_tracks(tracks: &[Element]) -> io::Result<Vec<MkvTrack>> {
+    let mut parsed = Vec::new();
+    for entry in tracks.iter().filter(|e| e.id == TRACK_ENTRY) {
+        let children = ebml::children(entry.data)?;
+        // Matroska's default language is English, "und" means unknown.
+        let language = match optional_string(&children, LANGUAGE) {
+            Some(ref language) if language == "und" => None,
+            Some(language) => Some(language),
+            None => Some("eng".to_string()),
+        };
+        let mut track = MkvTrack {
+            number: find(&children, TRACK_NUMBER).map(|e| e.uint()).unwrap_or(Ok(0))?,
+            track_type: find(&children, TRACK_TYPE).map(|e| e.uint()).unwrap_or(Ok(0))?,
+            codec_id: optional_string(&children, CODEC_ID).unwrap_or_default(),
+            language: language,
+            name: optional_string(&children, NAME),
+            width: None,
+            height: None,
+            channels: None,
+            sample_rate: None,
+        };
+        if let Some(video) = find(&children, VIDEO) {
+            let video = ebml::children(video.data)?;
+            track.width = optional_uint(&video, PIXEL_WIDTH)?;
+            track.height = optional_uint(&video, PIXEL_HEIGHT)?;
+        }
+        if let Some(audio) = find(&children, AUDIO) {
+            let audio = ebml::children(audio.data)?;
+            track.channels = Some(optional_uint(&audio, CHANNELS)?.unwrap_or(1));
+            track.sample_rate = match find(&audio, SAMPLING_FREQUENCY) {
+                Some(frequency) => Some(frequency.float()? as u32),
+                None => Some(8000),
+            };
+        }
+        parsed.push(track);
+    }
+    Ok(parsed)
+}
+
+/// The chapters of the first edition, which is the default one when
+/// none is flagged.
+fn parse_chapters(chapters: &[Element]) -> io::Result<Vec<Chapter>> {
+    let edition = match find(chapters, EDITION_ENTRY) {
+        Some(edition) => ebml::children(edition.data)?,
+        None => return Ok(Vec::new()),
+    };
+    let mut parsed = Vec::new();
+    for atom in edition.iter().filter(|e| e.id == CHAPTER_ATOM) {
+        let children = ebml::children(atom.data)?;
+        let title = match find(&children, CHAPTER_DISPLAY) {
+            Some(display) => optional_string(&ebml::children(display.data)?, CHAP_STRING),
+            None => None,
+        };
+        let start = find(&children, CHAPTER_TIME_START).map(|e| e.uint()).unwrap_or(Ok(0))?;
+        let end = match find(&children, CHAPTER_TIME_END) {
+            Some(end) => Some(end.uint()? / 1_000_000),
+            None => None,
+        };
+        parsed.push(Chapter { start_ms: start / 1_000_000, end_ms: end, title: title });
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+fn uint(id: u32, value: u64) -> Vec<u8> {
+    let mut data = Vec::new();
+    ::media::bytes::put_u64(&mut data, value);
+    ebml::write_element(id, &data)
+}
+
+#[cfg(test)]
+fn float(id: u32, value: f64) -> Vec<u8> {
+    uint(id, value.to_bits())
+}
+
+/// A Matroska file with a 720p video track, a French 5.1 audio track, an
+/// English subtitle track, one cluster and two chapters.
+#[cfg(test)]
+pub fn test_matroska() -> Vec<u8> {
+    use media::ebml::write_element;
+    let header = write_element(EBML_HEADER, &write_element(DOC_TYPE, b"matroska"));
+    let info = write_element(INFO, &[uint(TIMECODE_SCALE, 1_000_000), float(DURATION, 4000.0)].concat());
+    let video = [
+        uint(TRACK_NUMBER, 1),
+        uint(TRACK_TYPE, TRACK_TYPE_VIDEO),
+        write_element(CODEC_ID, b"V_MPEG4/ISO/AVC"),
+        write_element(VIDEO, &[uint(PIXEL_WIDTH, 1280), uint(PIXEL_HEIGHT, 720)].concat()),
+    ].concat();
+    let audio = [
+        uint(TRACK_NUMBER, 2),
+        uint(TRACK_TYPE, TRACK_TYPE_AUDIO),
+        write_element(CODEC_ID, b"A_AC3"),
+        write_element(LANGUAGE, b"fre"),
+        write_element(AUDIO, &[float(SAMPLING_FREQUENCY, 48000.0), uint(CHANNELS, 6)].concat()),
+    ].concat();
+    let subtitle = [
+        uint(TRACK_NUMBER, 3),
+        uint(TRACK_TYPE, TRACK_TYPE_SUBTITLE),
+        write_element(CODEC_ID, b"S_TEXT/UTF8"),
+        write_element(NAME, b"Forced"),
+    ].concat();
+    let tracks = write_element(TRACKS, &[
+        write_element(TRACK_ENTRY, &video),
+        write_element(TRACK_ENTRY, &audio),
+        write_element(TRACK_ENTRY, &subtitle),
+    ].concat());
+    let cluster = write_element(0x1F43B675, &[0xAA; 64]);
+    let chapter = |start: u64, end: u64, title: &[u8]| write_element(CHAPTER_ATOM, &[
+        uint(CHAPTER_TIME_START, start * 1_000_000),
+        uint(CHAPTER_TIME_END, end * 1_000_000),
+        write_element(CHAPTER_DISPLAY, &write_element(CHAP_STRING, title)),
+    ].concat());
+    let chapters = write_element(CHAPTERS, &write_element(EDITION_ENTRY, &[
+        chapter(0, 1000, b"Opening"),
+        chapter(1000, 4000, b"Heist"),
+    ].concat()));
+    let segment = write_element(SEGMENT, &[info, tracks, cluster, chapters].concat());
+    [header, segment].concat()
+}
+
+#[test]
+fn reads_tracks_and_chapters() {
+    let matroska = read(&mut io::Cursor::new(test_matroska())).unwrap();
+    assert_eq!("matroska", matroska.doc_type);
+    assert_eq!(Some(4000), matroska.duration_ms);
+    assert_eq!(3, matroska.tracks.len());
+    let video = &matroska.tracks[0];
+    assert_eq!((TRACK_TYPE_VIDEO, "V_MPEG4/ISO/AVC"), (video.track_type, video.codec_id.as_str()));
+    assert_eq!((Some(1280), Some(720)), (video.width, video.height));
+    assert_eq!(Some("eng".to_string()), video.language);
+    let audio = &matroska.tracks[1];
+    assert_eq!((Some(6), Some(48000)), (audio.channels, audio.sample_rate));
+    assert_eq!(Some("fre".to_string()), audio.language);
+    assert_eq!(Some("Forced".to_string()), matroska.tracks[2].name);
+    assert_eq!(vec![
+        Chapter { start_ms: 0, end_ms: Some(1000), title: Some("Opening".to_string()) },
+        Chapter { start_ms: 1000, end_ms: Some(4000), title: Some("Heist".to_string()) },
+    ], matroska.chapters);
+}
+
+#[test]
+fn rejects_other_files() {
+    assert!(read(&mut io::Cursor::new(b"not a matroska file".to_vec())).is_err());
+}
@@ -0,0 +1,11 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+pub mod bytes;
+pub mod ebml;
+pub mod mkv;
+pub mod mp4;
+pub mod probe;
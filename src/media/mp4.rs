@@ -0,0 +1,545 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use media::bytes::{be_u32, invalid, put_u32, ByteReader};
+
+// A moov for a feature length film is a few megabytes, anything near this
+// is a broken or hostile file.
+const MAX_MOOV_SIZE: u64 = 256 * 1024 * 1024;
+
+// A day of 48fps video, more than any real track has.
+const MAX_SAMPLES: usize = 1 << 22;
+
+/// An ISO base media file format box, borrowed from the buffer it was read
+/// from. `raw` includes the header, `payload` is everything after it.
+#[derive(Debug, Clone, Copy)]
+pub struct Mp4Box<'a> {
+    pub kind: [u8; 4],
+    pub raw: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+pub fn children<'a>(data: &'a [u8]) -> io::Result<Vec<Mp4Box<'a>>> {
+    let mut boxes = Vec::new();
+    let mut reader = ByteReader::new(data);
+    while reader.remaining() >= 8 {
+        let start = reader.position();
+        let mut size = reader.u32()? as u64;
+        let kind = reader.fourcc()?;
+        let mut header_len = 8;
+        if size == 1 {
+            size = reader.u64()?;
+            header_len = 16;
+        } else if size == 0 {
+            size = (data.len() - start) as u64;
+        }
+        if size < header_len || size > (data.len() - start) as u64 {
+            return Err(invalid("box size out of bounds"));
+        }
+        let raw = &data[start..start + size as usize];
+        boxes.push(Mp4Box { kind: kind, raw: raw, payload: &raw[header_len as usize..] });
+        reader.seek(start + size as usize)?;
+    }
+    Ok(boxes)
+}
+
+pub fn write_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    put_u32(&mut out, (payload.len() + 8) as u32);
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+pub fn write_full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut body = vec![version, (flags >> 16) as u8, (flags >> 8) as u8, flags as u8];
+    body.extend_from_slice(payload);
+    write_box(kind, &body)
+}
+
+pub fn find<'a>(boxes: &[Mp4Box<'a>], kind: &[u8; 4]) -> Option<Mp4Box<'a>> {
+    boxes.iter().find(|b| &b.kind == kind).cloned()
+}
+
+fn require<'a>(boxes: &[Mp4Box<'a>], kind: &[u8; 4]) -> io::Result<Mp4Box<'a>> {
+    find(boxes, kind).ok_or_else(|| invalid(&format!("missing {} box", String::from_utf8_lossy(kind))))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub offset: u64,
+    pub dts: u64,
+    pub size: u32,
+    pub duration: u32,
+    pub cts_offset: i32,
+    pub sync: bool,
+}
+
+#[derive(Debug)]
+pub struct Track {
+    pub id: u32,
+    pub handler: [u8; 4],
+    pub timescale: u32,
+    pub duration: u64,
+    pub tkhd: Vec<u8>,
+    pub mdhd: Vec<u8>,
+    pub hdlr: Vec<u8>,
+    pub media_header: Option<Vec<u8>>,
+    pub dinf: Option<Vec<u8>>,
+    pub stsd: Vec<u8>,
+    pub samples: Vec<Sample>,
+}
+
+impl Track {
+    pub fn is_video(&self) -> bool {
+        &self.handler == b"vide"
+    }
+
+    pub fn is_audio(&self) -> bool {
+        &self.handler == b"soun"
+    }
+
+    /// The codec and format of the track's first sample description.
+    pub fn sample_entry(&self) -> io::Result<Option<SampleEntry>> {
+        let stsd = require(&children(&self.stsd)?, b"stsd")?;
+        let mut reader = ByteReader::new(stsd.payload);
+        reader.skip(8)?;
+        let remaining = reader.remaining();
+        let entries = children(reader.take(remaining)?)?;
+        let entry = match entries.first() {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        let mut reader = ByteReader::new(entry.payload);
+        // reserved and data reference index
+        reader.skip(8)?;
+        let mut sample_entry = SampleEntry {
+            codec: String::from_utf8_lossy(&entry.kind).trim().to_string(),
+            width: None,
+            height: None,
+            channels: None,
+            sample_rate: None,
+        };
+        if self.is_video() {
+            reader.skip(16)?;
+            sample_entry.width = Some(reader.u16()? as u32);
+            sample_entry.height = Some(reader.u16()? as u32);
+        } else if self.is_audio() {
+            reader.skip(8)?;
+            sample_entry.channels = Some(reader.u16()? as u32);
+            reader.skip(6)?;
+            sample_entry.sample_rate = Some(reader.u32()? >> 16);
+        }
+        Ok(Some(sample_entry))
+    }
+
+    /// The ISO 639-2 language from the `mdhd`, `None` when undetermined.
+    pub fn language(&self) -> io::Result<Option<String>> {
+        let mdhd = require(&children(&self.mdhd)?, b"mdhd")?;
+        let mut reader = ByteReader::new(mdhd.payload);
+        let version = reader.u8()?;
+        reader.skip(3)?;
+        reader.skip(if version == 1 { 28 } else { 16 })?;
+        let packed = reader.u16()?;
+        let language : String = [10, 5, 0].iter()
+            .map(|shift| (((packed >> shift) & 0x1f) as u8 + 0x60) as char)
+            .collect();
+        if packed == 0 || language == "und" || !language.chars().all(|c| c >= 'a' && c <= 'z') {
+            Ok(None)
+        } else {
+            Ok(Some(language))
+        }
+    }
+
+    /// Index of the first sample decoded at or after `dts`.
+    pub fn sample_at(&self, dts: u64) -> usize {
+        let (mut low, mut high) = (0, self.samples.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.samples[middle].dts < dts {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleEntry {
+    pub codec: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Mp4 {
+    pub mvhd: Vec<u8>,
+    pub timescale: u32,
+    pub duration: u64,
+    pub tracks: Vec<Track>,
+}
+
+/// Reads the movie header and sample tables of a (non-fragmented) MP4.
+pub fn read<R: Read + Seek>(reader: &mut R) -> io::Result<Mp4> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut position = 0;
+    let mut moov = None;
+    while position + 8 <= end {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header[..8])?;
+        let mut size = be_u32(&header[..4]) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            reader.read_exact(&mut header[8..])?;
+            size = (be_u32(&header[8..12]) as u64) << 32 | be_u32(&header[12..]) as u64;
+            header_len = 16;
+        } else if size == 0 {
+            size = end - position;
+        }
+        if size < header_len {
+            return Err(invalid("box smaller than its header"));
+        }
+        if size > end - position {
+            return Err(invalid("box extends past the end of the file"));
+        }
+        let kind = [header[4], header[5], header[6], header[7]];
+        match &kind {
+            b"moov" => {
+                if size > MAX_MOOV_SIZE {
+                    return Err(invalid("moov box too large"));
+                }
+                let mut data = vec![0; (size - header_len) as usize];
+                reader.read_exact(&mut data)?;
+                moov = Some(data);
+            },
+            b"moof" => return Err(invalid("fragmented mp4 files are not supported")),
+            _ => (),
+        }
+        position = position.checked_add(size).ok_or_else(|| invalid("box size out of bounds"))?;
+    }
+    match moov {
+        Some(moov) => parse_moov(&moov, end),
+        None => Err(invalid("no moov box")),
+    }
+}
+
+/// The timescale and duration from an `mvhd` or `mdhd`, which share their
+/// leading layout.
+fn parse_time_header(payload: &[u8]) -> io::Result<(u32, u64)> {
+    let mut reader = ByteReader::new(payload);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    if version == 1 {
+        reader.skip(16)?;
+        Ok((reader.u32()?, reader.u64()?))
+    } else {
+        reader.skip(8)?;
+        Ok((reader.u32()?, reader.u32()? as u64))
+    }
+}
+
+fn parse_moov(data: &[u8], file_len: u64) -> io::Result<Mp4> {
+    let boxes = children(data)?;
+    let mvhd = require(&boxes, b"mvhd")?;
+    let (timescale, duration) = parse_time_header(mvhd.payload)?;
+    let mut tracks = Vec::new();
+    for trak in boxes.iter().filter(|b| &b.kind == b"trak") {
+        tracks.push(parse_trak(trak.payload, file_len)?);
+    }
+    Ok(Mp4 { mvhd: mvhd.raw.to_vec(), timescale: timescale, duration: duration, tracks: tracks })
+}
+
+fn parse_track_id(tkhd: &[u8]) -> io::Result<u32> {
+    let mut reader = ByteReader::new(tkhd);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    reader.skip(if version == 1 { 16 } else { 8 })?;
+    reader.u32()
+}
+
+fn parse_handler(hdlr: &[u8]) -> io::Result<[u8; 4]> {
+    let mut reader = ByteReader::new(hdlr);
+    reader.skip(8)?;
+    reader.fourcc()
+}
+
+fn is_media_header(kind: &[u8; 4]) -> bool {
+    match kind {
+        b"vmhd" | b"smhd" | b"sthd" | b"nmhd" => true,
+        _ => false,
+    }
+}
+
+fn parse_trak(data: &[u8], file_len: u64) -> io::Result<Track> {
+    let boxes = children(data)?;
+    let tkhd = require(&boxes, b"tkhd")?;
+    let mdia = children(require(&boxes, b"mdia")?.payload)?;
+    let mdhd = require(&mdia, b"mdhd")?;
+    let hdlr = require(&mdia, b"hdlr")?;
+    let minf = children(require(&mdia, b"minf")?.payload)?;
+    let stbl = children(require(&minf, b"stbl")?.payload)?;
+    let (timescale, duration) = parse_time_header(mdhd.payload)?;
+    if timescale == 0 {
+        return Err(invalid("track timescale is zero"));
+    }
+    Ok(Track {
+        id: parse_track_id(tkhd.payload)?,
+        handler: parse_handler(hdlr.payload)?,
+        timescale: timescale,
+        duration: duration,
+        tkhd: tkhd.raw.to_vec(),
+        mdhd: mdhd.raw.to_vec(),
+        hdlr: hdlr.raw.to_vec(),
+        media_header: minf.iter().find(|b| is_media_header(&b.kind)).map(|b| b.raw.to_vec()),
+        dinf: find(&minf, b"dinf").map(|b| b.raw.to_vec()),
+        stsd: require(&stbl, b"stsd")?.raw.to_vec(),
+        samples: parse_samples(&stbl, file_len)?,
+    })
+}
+
+// Reads the entry count of a full box table and checks the table can
+// actually hold that many entries before anything is allocated.
+fn table<'a>(payload: &'a [u8], entry_size: usize) -> io::Result<(usize, ByteReader<'a>)> {
+    let mut reader = ByteReader::new(payload);
+    reader.skip(4)?;
+    let count = reader.u32()? as usize;
+    if count > reader.remaining() / entry_size {
+        return Err(invalid("sample table truncated"));
+    }
+    Ok((count, reader))
+}
+
+fn parse_samples(stbl: &[Mp4Box], file_len: u64) -> io::Result<Vec<Sample>> {
+    let sizes = parse_sample_sizes(require(stbl, b"stsz")?.payload, file_len)?;
+    let sample_count = sizes.len();
+    let mut samples : Vec<Sample> = sizes.into_iter()
+        .map(|size| Sample { offset: 0, dts: 0, size: size, duration: 0, cts_offset: 0, sync: true })
+        .collect();
+
+    let (count, mut reader) = table(require(stbl, b"stts")?.payload, 8)?;
+    let (mut index, mut dts) = (0, 0u64);
+    for _ in 0..count {
+        let (run, delta) = (reader.u32()?, reader.u32()?);
+        for _ in 0..run {
+            if index >= sample_count {
+                break;
+            }
+            samples[index].dts = dts;
+            samples[index].duration = delta;
+            dts += delta as u64;
+            index += 1;
+        }
+    }
+
+    if let Some(ctts) = find(stbl, b"ctts") {
+        let (count, mut reader) = table(ctts.payload, 8)?;
+        let mut index = 0;
+        for _ in 0..count {
+            let (run, offset) = (reader.u32()?, reader.i32()?);
+            for _ in 0..run {
+                if index >= sample_count {
+                    break;
+                }
+                samples[index].cts_offset = offset;
+                index += 1;
+            }
+        }
+    }
+
+    if let Some(stss) = find(stbl, b"stss") {
+        for sample in samples.iter_mut() {
+            sample.sync = false;
+        }
+        let (count, mut reader) = table(stss.payload, 4)?;
+        for _ in 0..count {
+            let number = reader.u32()? as usize;
+            if number >= 1 && number <= sample_count {
+                samples[number - 1].sync = true;
+            }
+        }
+    }
+
+    let chunk_offsets = match (find(stbl, b"stco"), find(stbl, b"co64")) {
+        (Some(stco), _) => {
+            let (count, mut reader) = table(stco.payload, 4)?;
+            let mut offsets = Vec::with_capacity(count);
+            for _ in 0..count {
+                offsets.push(reader.u32()? as u64);
+            }
+            offsets
+        },
+        (None, Some(co64)) => {
+            let (count, mut reader) = table(co64.payload, 8)?;
+            let mut offsets = Vec::with_capacity(count);
+            for _ in 0..count {
+                offsets.push(reader.u64()?);
+            }
+            offsets
+        },
+        (None, None) => return Err(invalid("missing chunk offsets")),
+    };
+
+    let (count, mut reader) = table(require(stbl, b"stsc")?.payload, 12)?;
+    let mut sample_to_chunk = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (first_chunk, samples_per_chunk) = (reader.u32()?, reader.u32()?);
+        reader.skip(4)?;
+        sample_to_chunk.push((first_chunk, samples_per_chunk));
+    }
+
+    let (mut index, mut run) = (0, 0);
+    for (chunk, &chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk_number = chunk as u32 + 1;
+        while run + 1 < sample_to_chunk.len() && sample_to_chunk[run + 1].0 <= chunk_number {
+            run += 1;
+        }
+        let samples_per_chunk = sample_to_chunk.get(run).map(|r| r.1).unwrap_or(0);
+        let mut offset = chunk_offset;
+        for _ in 0..samples_per_chunk {
+            if index >= sample_count {
+                break;
+            }
+            samples[index].offset = offset;
+            offset = offset.checked_add(samples[index].size as u64).ok_or_else(|| invalid("chunk offset overflow"))?;
+            index += 1;
+        }
+    }
+    Ok(samples)
+}
+
+/// A uniform `stsz` is a few bytes whatever its sample count, so the count
+/// is only believed if that many samples fit in the file.
+fn parse_sample_sizes(stsz: &[u8], file_len: u64) -> io::Result<Vec<u32>> {
+    let mut reader = ByteReader::new(stsz);
+    reader.skip(4)?;
+    let uniform_size = reader.u32()?;
+    let count = reader.u32()? as usize;
+    if count > MAX_SAMPLES {
+        return Err(invalid("sample count too large"));
+    }
+    if uniform_size != 0 {
+        if count as u64 * uniform_size as u64 > file_len {
+            return Err(invalid("samples larger than the file"));
+        }
+        return Ok(vec![uniform_size; count]);
+    }
+    if count > reader.remaining() / 4 {
+        return Err(invalid("sample size table truncated"));
+    }
+    let mut sizes = Vec::with_capacity(count);
+    for _ in 0..count {
+        sizes.push(reader.u32()?);
+    }
+    Ok(sizes)
+}
+
+#[cfg(test)]
+fn u32s(values: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &value in values {
+        put_u32(&mut out, value);
+    }
+    out
+}
+
+/// A minimal progressive MP4: a 1000Hz 1920x1080 avc1 video track of four
+/// 1 second samples with keyframes at 0 and 2 seconds, stored in two chunks.
+#[cfg(test)]
+pub fn test_movie() -> Vec<u8> {
+    let ftyp = write_box(b"ftyp", b"isom\0\0\x02\0isommp41");
+    let mut mvhd = u32s(&[0, 0, 1000, 4000]);
+    mvhd.extend_from_slice(&[0; 80]);
+    let mut tkhd = u32s(&[0, 0, 1, 0, 4000]);
+    tkhd.extend_from_slice(&[0; 60]);
+    let mut hdlr = u32s(&[0]);
+    hdlr.extend_from_slice(b"vide");
+    hdlr.extend_from_slice(&[0; 13]);
+    let mut avc1 = vec![0; 24];
+    avc1.extend_from_slice(&[0x07, 0x80, 0x04, 0x38]);
+    avc1.extend_from_slice(&[0; 50]);
+    let mut stsd = u32s(&[1]);
+    stsd.extend(write_box(b"avc1", &avc1));
+    let stbl = [
+        write_full_box(b"stsd", 0, 0, &stsd),
+        write_full_box(b"stts", 0, 0, &u32s(&[1, 4, 1000])),
+        write_full_box(b"stss", 0, 0, &u32s(&[2, 1, 3])),
+        write_full_box(b"stsc", 0, 0, &u32s(&[2, 1, 1, 1, 2, 3, 1])),
+        write_full_box(b"stsz", 0, 0, &u32s(&[0, 4, 10, 20, 30, 40])),
+        write_full_box(b"stco", 0, 0, &u32s(&[2, 1000, 2000])),
+    ].concat();
+    let minf = [
+        write_full_box(b"vmhd", 0, 1, &[0; 8]),
+        write_box(b"stbl", &stbl),
+    ].concat();
+    let mdia = [
+        write_full_box(b"mdhd", 0, 0, &u32s(&[0, 0, 1000, 4000, 0])),
+        write_full_box(b"hdlr", 0, 0, &hdlr),
+        write_box(b"minf", &minf),
+    ].concat();
+    let trak = [write_full_box(b"tkhd", 0, 3, &tkhd), write_box(b"mdia", &mdia)].concat();
+    let moov = [write_full_box(b"mvhd", 0, 0, &mvhd), write_box(b"trak", &trak)].concat();
+    [ftyp, write_box(b"moov", &moov)].concat()
+}
+
+#[test]
+fn reads_sample_tables() {
+    let mp4 = read(&mut io::Cursor::new(test_movie())).unwrap();
+    assert_eq!(1000, mp4.timescale);
+    assert_eq!(1, mp4.tracks.len());
+    let track = &mp4.tracks[0];
+    assert!(track.is_video());
+    assert_eq!(1, track.id);
+    let layout : Vec<(u64, u32, u64, bool)> = track.samples.iter().map(|s| (s.offset, s.size, s.dts, s.sync)).collect();
+    assert_eq!(vec![(1000, 10, 0, true), (2000, 20, 1000, false), (2020, 30, 2000, true), (2050, 40, 3000, false)], layout);
+    assert_eq!(2, track.sample_at(1500));
+}
+
+#[test]
+fn rejects_boxes_past_the_end() {
+    let mut data = test_movie();
+    // A 64-bit size of nearly u64::MAX on a box after the moov
+    data.extend_from_slice(&[0, 0, 0, 1, b'f', b'r', b'e', b'e', 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf0]);
+    assert!(read(&mut io::Cursor::new(data)).is_err());
+}
+
+#[test]
+fn sample_counts_are_bounded_by_the_file() {
+    let stsz = u32s(&[0, 10, 1000]);
+    assert_eq!(vec![10; 1000], parse_sample_sizes(&stsz, 10000).unwrap());
+    assert!(parse_sample_sizes(&stsz, 9999).is_err());
+    assert!(parse_sample_sizes(&u32s(&[0, 1, 1 << 28]), u64::max_value()).is_err());
+}
+
+#[test]
+fn rejects_chunk_offsets_that_overflow() {
+    use media::bytes::put_u64;
+    let mut co64 = u32s(&[1]);
+    put_u64(&mut co64, u64::max_value() - 5);
+    let stbl = [
+        write_full_box(b"stts", 0, 0, &u32s(&[1, 2, 1000])),
+        write_full_box(b"stsc", 0, 0, &u32s(&[1, 1, 2, 1])),
+        write_full_box(b"stsz", 0, 0, &u32s(&[0, 2, 10, 10])),
+        write_full_box(b"co64", 0, 0, &co64),
+    ].concat();
+    assert!(parse_samples(&children(&stbl).unwrap(), 1000).is_err());
+}
+
+#[test]
+fn reads_sample_entry() {
+    let mp4 = read(&mut io::Cursor::new(test_movie())).unwrap();
+    let entry = mp4.tracks[0].sample_entry().unwrap().unwrap();
+    assert_eq!("avc1", entry.codec);
+    assert_eq!((Some(1920), Some(1080)), (entry.width, entry.height));
+    assert_eq!(None, entry.channels);
+    assert_eq!(None, mp4.tracks[0].language().unwrap());
+}
@@ -0,0 +1,203 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use media::bytes::invalid;
+use media::mkv::{self, TRACK_TYPE_AUDIO, TRACK_TYPE_SUBTITLE, TRACK_TYPE_VIDEO};
+use media::mp4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+impl StreamKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StreamKind::Video => "video",
+            StreamKind::Audio => "audio",
+            StreamKind::Subtitle => "subtitle",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec: String,
+    pub language: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_rate: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start_ms: u64,
+    pub end_ms: Option<u64>,
+    pub title: Option<String>,
+}
+
+/// What clients need to know to decide whether they can direct play a
+/// file, read from the container without decoding anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaInfo {
+    pub container: &'static str,
+    pub duration_ms: Option<u64>,
+    pub bit_rate: Option<u64>,
+    pub streams: Vec<StreamInfo>,
+    pub chapters: Vec<Chapter>,
+}
+
+fn bit_rate(bytes: u64, duration_ms: u64) -> Option<u64> {
+    if duration_ms == 0 { None } else { bytes.checked_mul(8 * 1000).map(|bits| bits / duration_ms) }
+}
+
+/// A duration in `timescale` units as milliseconds. None for a zero
+/// timescale, or a corrupt 64-bit duration too long to convert.
+fn to_millis(duration: u64, timescale: u32) -> Option<u64> {
+    if timescale == 0 { None } else { duration.checked_mul(1000).map(|ms| ms / timescale as u64) }
+}
+
+pub fn probe(path: &Path) -> io::Result<MediaInfo> {
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+    match extension.as_ref().map(String::as_str) {
+        Some("mp4") | Some("m4v") => probe_mp4(path),
+        Some("mkv") | Some("webm") => probe_mkv(path),
+        _ => Err(invalid("unsupported container")),
+    }
+}
+
+fn probe_mp4(path: &Path) -> io::Result<MediaInfo> {
+    let file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+    let mp4 = mp4::read(&mut BufReader::new(file))?;
+    let duration_ms = to_millis(mp4.duration, mp4.timescale);
+    let mut streams = Vec::new();
+    for (index, track) in mp4.tracks.iter().enumerate() {
+        let kind = match &track.handler {
+            b"vide" => StreamKind::Video,
+            b"soun" => StreamKind::Audio,
+            b"sbtl" | b"subt" | b"text" => StreamKind::Subtitle,
+            _ => continue,
+        };
+        let entry = match track.sample_entry()? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let bytes : u64 = track.samples.iter().map(|s| s.size as u64).sum();
+        streams.push(StreamInfo {
+            index: index as u32,
+            kind: kind,
+            codec: entry.codec,
+            language: track.language()?,
+            width: entry.width,
+            height: entry.height,
+            channels: entry.channels,
+            sample_rate: entry.sample_rate,
+            bit_rate: to_millis(track.duration, track.timescale).and_then(|duration| bit_rate(bytes, duration)),
+        });
+    }
+    Ok(MediaInfo {
+        container: "mp4",
+        duration_ms: duration_ms,
+        bit_rate: duration_ms.and_then(|duration| bit_rate(file_length, duration)),
+        streams: streams,
+        chapters: Vec::new(),
+    })
+}
+
+fn probe_mkv(path: &Path) -> io::Result<MediaInfo> {
+    let file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+    let matroska = mkv::read(&mut BufReader::new(file))?;
+    let streams = matroska.tracks.into_iter().enumerate().filter_map(|(index, track)| {
+        let kind = match track.track_type {
+            TRACK_TYPE_VIDEO => StreamKind::Video,
+            TRACK_TYPE_AUDIO => StreamKind::Audio,
+            TRACK_TYPE_SUBTITLE => StreamKind::Subtitle,
+            _ => return None,
+        };
+        Some(StreamInfo {
+            index: index as u32,
+            kind: kind,
+            codec: track.codec_id,
+            language: track.language,
+            width: track.width,
+            height: track.height,
+            channels: track.channels,
+            sample_rate: track.sample_rate,
+            bit_rate: None,
+        })
+    }).collect();
+    Ok(MediaInfo {
+        container: if matroska.doc_type == "webm" { "webm" } else { "matroska" },
+        duration_ms: matroska.duration_ms,
+        bit_rate: matroska.duration_ms.and_then(|duration| bit_rate(file_length, duration)),
+        streams: streams,
+        chapters: matroska.chapters,
+    })
+}
+
+#[test]
+fn probes_mp4_streams() {
+    use std::io::Write;
+    let dir = ::tempdir::TempDir::new("carolus_probe").unwrap();
+    let path = dir.path().join("movie.mp4");
+    let contents = mp4::test_movie();
+    File::create(&path).unwrap().write_all(&contents).unwrap();
+    let info = probe(&path).unwrap();
+    assert_eq!("mp4", info.container);
+    assert_eq!(Some(4000), info.duration_ms);
+    assert_eq!(Some(contents.len() as u64 * 2), info.bit_rate);
+    assert_eq!(vec![StreamInfo {
+        index: 0,
+        kind: StreamKind::Video,
+        codec: "avc1".to_string(),
+        language: None,
+        width: Some(1920),
+        height: Some(1080),
+        channels: None,
+        sample_rate: None,
+        bit_rate: Some(200),
+    }], info.streams);
+}
+
+#[test]
+fn probes_matroska_streams_and_chapters() {
+    use std::io::Write;
+    let dir = ::tempdir::TempDir::new("carolus_probe").unwrap();
+    let path = dir.path().join("movie.mkv");
+    File::create(&path).unwrap().write_all(&mkv::test_matroska()).unwrap();
+    let info = probe(&path).unwrap();
+    assert_eq!("matroska", info.container);
+    assert_eq!(Some(4000), info.duration_ms);
+    let kinds : Vec<StreamKind> = info.streams.iter().map(|s| s.kind).collect();
+    assert_eq!(vec![StreamKind::Video, StreamKind::Audio, StreamKind::Subtitle], kinds);
+    assert_eq!("S_TEXT/UTF8", info.streams[2].codec);
+    assert_eq!(2, info.chapters.len());
+}
+
+#[test]
+fn corrupt_durations_are_unknown() {
+    assert_eq!(Some(4000), to_millis(4000, 1000));
+    assert_eq!(None, to_millis(u64::max_value() / 10, 1000));
+    assert_eq!(None, to_millis(4000, 0));
+    assert_eq!(None, bit_rate(u64::max_value() / 10, 4000));
+}
+
+#[test]
+fn unknown_containers_are_not_probed() {
+    assert!(probe(Path::new("movie.txt")).is_err());
+}
@@ -4,15 +4,29 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::io;
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::Path;
+use std::time::Duration;
 
-use rocket::Route;
+use rocket::{Route, State};
+use rocket::http::ContentType;
+use rocket::response::content::Content;
 use rocket_contrib::JsonValue;
+use serde_json;
 
 use data::init::establish_connection;
-use data::movies::{page_movies, get_movie};
+use data::media::{get_chapters, get_media_info};
+use data::metadata::get_movie_metadata;
+use data::models;
+use data::movies::{page_movies, find_movie};
 use partial_file::{serve_partial, PartialFile};
+use partial_file::throttle::{StreamClient, Throttle};
+use provider::{Actor, Rating};
+use transcode::{rewrite_playlist, Transcoder};
+use transcode::profile::Profile;
+
+const PLAYLIST_TIMEOUT_SECONDS: u64 = 30;
 
 #[derive(Serialize)]
 pub struct Movie {
@@ -20,10 +34,60 @@ pub struct Movie {
     pub play_path: String
 }
 
+#[derive(Serialize)]
+pub struct Media {
+    pub container: String,
+    pub duration_ms: Option<i64>,
+    pub bit_rate: Option<i64>,
+    pub streams: Vec<Stream>,
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Serialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub year: Option<i32>,
+    pub overview: Option<String>,
+    pub release_date: Option<String>,
+    pub poster: Option<String>,
+    pub backdrop: Option<String>,
+    pub tmdb_id: Option<i32>,
+    pub imdb_id: Option<String>,
+    pub genres: Vec<String>,
+    pub actors: Vec<Actor>,
+    pub ratings: Vec<Rating>,
+    pub sources: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Stream {
+    pub index: i32,
+    pub kind: String,
+    pub codec: String,
+    pub language: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub channels: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub bit_rate: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Chapter {
+    pub start_ms: i64,
+    pub end_ms: Option<i64>,
+    pub title: Option<String>,
+}
+
 #[derive(FromForm)]
 pub struct PageRequest {
-    page: Option<i64>,
-    count: Option<i64>
+    pub page: Option<i64>,
+    pub count: Option<i64>
+}
+
+#[derive(FromForm)]
+pub struct PlayRequest {
+    profile: String
 }
 
 #[get("/")]
@@ -44,13 +108,99 @@ pub fn all_movies(page_request: PageRequest) -> JsonValue {
     })
 }
 
-#[get("/play/<movie_id>")]
-pub fn play_movie(movie_id: i32) -> io::Result<PartialFile>  {
+/// The movie, unless its file has gone missing since it was indexed.
+pub fn playable_movie(movie_id: i32) -> Option<models::Movie> {
     let conn = establish_connection();
-    let movie = get_movie(&conn, movie_id as i64);
-    serve_partial(Path::new(&movie.file_path))
+    find_movie(&conn, movie_id as i64).and_then(|movie| if movie.available { Some(movie) } else { None })
+}
+
+#[get("/<movie_id>")]
+pub fn movie_details(movie_id: i32) -> Option<JsonValue> {
+    let conn = establish_connection();
+    let movie = match find_movie(&conn, movie_id as i64) {
+        Some(movie) => movie,
+        None => return None,
+    };
+    let media = get_media_info(&conn, movie.id).map(|(media, streams)| Media {
+        container: media.container,
+        duration_ms: media.duration_ms,
+        bit_rate: media.bit_rate,
+        streams: streams.into_iter().map(|stream| Stream {
+            index: stream.stream_index,
+            kind: stream.kind,
+            codec: stream.codec,
+            language: stream.language,
+            width: stream.width,
+            height: stream.height,
+            channels: stream.channels,
+            sample_rate: stream.sample_rate,
+            bit_rate: stream.bit_rate,
+        }).collect(),
+        chapters: get_chapters(&conn, movie.id).into_iter().map(|chapter| Chapter {
+            start_ms: chapter.start_ms,
+            end_ms: chapter.end_ms,
+            title: chapter.title,
+        }).collect(),
+    });
+    let metadata = get_movie_metadata(&conn, movie.id).map(|metadata| Metadata {
+        genres: serde_json::from_str(&metadata.genres).unwrap_or_default(),
+        actors: serde_json::from_str(&metadata.actors).unwrap_or_default(),
+        ratings: serde_json::from_str(&metadata.ratings).unwrap_or_default(),
+        title: metadata.title,
+        year: metadata.year,
+        overview: metadata.overview,
+        release_date: metadata.release_date.map(|date| date.to_string()),
+        poster: metadata.poster,
+        backdrop: metadata.backdrop,
+        tmdb_id: metadata.tmdb_id,
+        imdb_id: metadata.imdb_id,
+        sources: metadata.sources.split(',').map(String::from).collect(),
+    });
+
+    Some(json!({
+        "title": movie.title,
+        "available": movie.available,
+        "play_path": uri!("/api/movies/play", play_movie: movie.id).to_string(),
+        "media": media,
+        "metadata": metadata,
+    }))
+}
+
+#[get("/play/<movie_id>")]
+pub fn play_movie(movie_id: i32, client: StreamClient, throttle: State<Throttle>) -> io::Result<Option<PartialFile>>  {
+    let movie = match playable_movie(movie_id) {
+        Some(movie) => movie,
+        None => return Ok(None),
+    };
+    let partial_file = serve_partial(Path::new(&movie.file_path))?;
+    Ok(Some(partial_file.throttled(throttle.limits_for(&client))))
+}
+
+#[get("/play/<movie_id>?<play_request>")]
+pub fn play_movie_transcoded(movie_id: i32, play_request: PlayRequest, transcoder: State<Transcoder>) -> io::Result<Option<Content<String>>> {
+    let profile = match Profile::find(&play_request.profile) {
+        Some(profile) => profile,
+        None => return Ok(None),
+    };
+    let movie = match playable_movie(movie_id) {
+        Some(movie) => movie,
+        None => return Ok(None),
+    };
+    let job = transcoder.start(movie_id, Path::new(&movie.file_path), profile)?;
+    let playlist_path = job.wait_for_playlist(Duration::from_secs(PLAYLIST_TIMEOUT_SECONDS))?;
+    let mut playlist = String::new();
+    File::open(playlist_path)?.read_to_string(&mut playlist)?;
+    Ok(Some(Content(ContentType::new("application", "vnd.apple.mpegurl"), rewrite_playlist(&playlist, movie_id, profile))))
+}
+
+#[head("/play/<movie_id>")]
+pub fn play_movie_head(movie_id: i32) -> io::Result<Option<PartialFile>>  {
+    match playable_movie(movie_id) {
+        Some(movie) => serve_partial(Path::new(&movie.file_path)).map(Some),
+        None => Ok(None),
+    }
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![all_movies_root, all_movies, play_movie]
+    routes![all_movies_root, all_movies, movie_details, play_movie, play_movie_transcoded, play_movie_head]
 }
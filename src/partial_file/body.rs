@@ -0,0 +1,100 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+/// A piece of a response body, either literal bytes or an inclusive byte
+/// range of the underlying file.
+#[derive(Debug)]
+pub enum Segment {
+    Bytes(Vec<u8>),
+    File(u64, u64),
+}
+
+impl Segment {
+    pub fn len(&self) -> u64 {
+        match *self {
+            Segment::Bytes(ref bytes) => bytes.len() as u64,
+            Segment::File(from, to) => to - from + 1,
+        }
+    }
+}
+
+enum Current {
+    Bytes(Cursor<Vec<u8>>),
+    File(u64),
+    Idle,
+}
+
+/// Streams a list of segments one after the other, seeking the file to the
+/// start of each file range as it is reached.
+pub struct SegmentedBody<F> {
+    file: F,
+    segments: VecDeque<Segment>,
+    current: Current,
+}
+
+impl<F: Read + Seek> SegmentedBody<F> {
+    pub fn new(file: F, segments: Vec<Segment>) -> SegmentedBody<F> {
+        SegmentedBody { file: file, segments: segments.into_iter().collect(), current: Current::Idle }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.segments.iter().map(Segment::len).sum()
+    }
+
+    fn advance(&mut self) -> io::Result<bool> {
+        match self.segments.pop_front() {
+            Some(Segment::Bytes(bytes)) => {
+                self.current = Current::Bytes(Cursor::new(bytes));
+            },
+            Some(Segment::File(from, to)) => {
+                self.file.seek(SeekFrom::Start(from))?;
+                self.current = Current::File(to - from + 1);
+            },
+            None => {
+                self.current = Current::Idle;
+                return Ok(false);
+            },
+        }
+        Ok(true)
+    }
+}
+
+impl<F: Read + Seek> Read for SegmentedBody<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let read = match self.current {
+                Current::Bytes(ref mut cursor) => cursor.read(buf)?,
+                Current::File(ref mut remaining) => {
+                    if *remaining == 0 {
+                        0
+                    } else {
+                        let max = cmp::min(buf.len() as u64, *remaining) as usize;
+                        let read = self.file.read(&mut buf[..max])?;
+                        if read == 0 {
+                            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file truncated while streaming"));
+                        }
+                        *remaining -= read as u64;
+                        read
+                    }
+                },
+                Current::Idle => 0,
+            };
+            if read > 0 {
+                return Ok(read);
+            }
+            if !self.advance()? {
+                return Ok(0);
+            }
+        }
+    }
+}
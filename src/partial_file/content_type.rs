@@ -0,0 +1,41 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use rocket::http::ContentType;
+
+pub fn from_extension(extension: &str) -> ContentType {
+    match extension.to_ascii_lowercase().as_str() {
+        "mp4" => ContentType::new("video", "mp4"),
+        "m4v" => ContentType::new("video", "x-m4v"),
+        "mkv" => ContentType::new("video", "x-matroska"),
+        "webm" => ContentType::new("video", "webm"),
+        "avi" => ContentType::new("video", "x-msvideo"),
+        "ts" => ContentType::new("video", "mp2t"),
+        "jpg" | "jpeg" => ContentType::JPEG,
+        "png" => ContentType::PNG,
+        "gif" => ContentType::GIF,
+        "webp" => ContentType::new("image", "webp"),
+        _ => ContentType::Binary,
+    }
+}
+
+pub fn from_path(path: &Path) -> ContentType {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(from_extension)
+        .unwrap_or(ContentType::Binary)
+}
+
+#[test]
+fn container_types() {
+    assert_eq!(ContentType::new("video", "mp4"), from_path(Path::new("/movies/Heat (1995).mp4")));
+    assert_eq!(ContentType::new("video", "x-matroska"), from_path(Path::new("Alien.MKV")));
+    assert_eq!(ContentType::new("video", "mp2t"), from_path(Path::new("recording.ts")));
+    assert_eq!(ContentType::JPEG, from_path(Path::new("w342.jpg")));
+    assert_eq!(ContentType::Binary, from_path(Path::new("README")));
+}
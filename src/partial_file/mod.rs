@@ -0,0 +1,328 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+pub mod body;
+pub mod content_type;
+pub mod range;
+pub mod throttle;
+pub mod validators;
+mod multipart;
+
+use std::fs::File;
+use std::io::{self, BufReader, SeekFrom, Seek, Read};
+use std::path::{Path, PathBuf};
+
+use rocket::request::Request;
+use rocket::response::{Body, Response, Responder};
+use rocket::http::{ContentType, Status};
+use rocket::http::hyper::header::{ContentRangeSpec, AcceptRanges, RangeUnit, ContentRange};
+
+use partial_file::body::SegmentedBody;
+use partial_file::range::RangeRequest;
+use partial_file::throttle::StreamLimits;
+use partial_file::validators::{Precondition, Validators};
+
+fn set_sized_body<B: Read + Send + 'static>(response: &mut Response, limits: Option<StreamLimits>, body: B, length: u64) {
+    match limits {
+        Some(limits) => response.set_raw_body(Body::Sized(limits.wrap(body), length)),
+        None => response.set_raw_body(Body::Sized(body, length)),
+    }
+}
+
+#[derive(Debug)]
+pub struct PartialFile {
+    path: PathBuf,
+    file: File,
+    limits: Option<StreamLimits>,
+}
+
+impl PartialFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<PartialFile> {
+        let file = File::open(path.as_ref())?;
+        Ok(PartialFile{ path: path.as_ref().to_path_buf(), file: file, limits: None })
+    }
+
+    pub fn throttled(mut self, limits: Option<StreamLimits>) -> PartialFile {
+        self.limits = limits;
+        self
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        content_type::from_path(&self.path)
+    }
+
+    pub fn set_body(self, response: &mut Response, request: RangeRequest, file_length: u64) {
+        let limits = self.limits;
+        match request {
+            RangeRequest::Full => {
+                response.set_status(Status::Ok);
+                set_sized_body(response, limits, BufReader::new(self.file), file_length);
+            },
+            RangeRequest::Unsatisfiable => {
+                response.set_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(file_length),
+                }));
+                response.set_status(Status::RangeNotSatisfiable);
+            },
+            RangeRequest::Partial(ref ranges) if ranges.len() == 1 => {
+                let range = ranges[0];
+                let content_range = ContentRange(ContentRangeSpec::Bytes {
+                    range: Some(range),
+                    instance_length: Some(file_length),
+                });
+                let content_len = range.1 - range.0 + 1;
+                response.set_header(content_range);
+                let mut partial_content = BufReader::new(self.file);
+                let _ = partial_content.seek(SeekFrom::Start(range.0));
+                let result = partial_content.take(content_len);
+                response.set_status(Status::PartialContent);
+                set_sized_body(response, limits, result, content_len);
+            },
+            RangeRequest::Partial(ref ranges) => {
+                let boundary = multipart::boundary();
+                let part_type = content_type::from_path(&self.path).to_string();
+                let segments = multipart::byteranges(ranges, file_length, &part_type, &boundary);
+                let body = SegmentedBody::new(BufReader::new(self.file), segments);
+                let body_len = body.len();
+                response.set_raw_header("Content-Type", multipart::content_type(&boundary));
+                response.set_status(Status::PartialContent);
+                set_sized_body(response, limits, body, body_len);
+            },
+        }
+    }
+}
+
+impl Responder<'static> for PartialFile {
+    fn respond_to(self, req: &Request) -> Result<Response<'static>, Status> {
+        // Bodies are always sized so that HEAD requests, which Rocket strips
+        // of their body, still report the length that a GET would return.
+        let mut response = Response::new();
+        response.set_header(AcceptRanges(vec![RangeUnit::Bytes]));
+        response.set_header(self.content_type());
+        let metadata = self.file.metadata().map_err(|_| Status::InternalServerError)?;
+        let validators = Validators::from_metadata(&metadata);
+        validators.set_headers(&mut response);
+        let range_header = match validators.evaluate(req.method(), req.headers()) {
+            Precondition::NotModified => {
+                response.set_status(Status::NotModified);
+                return Ok(response);
+            },
+            Precondition::Failed => {
+                response.set_status(Status::PreconditionFailed);
+                return Ok(response);
+            },
+            Precondition::Proceed { honour_range: true } => req.headers().get_one("Range"),
+            Precondition::Proceed { honour_range: false } => None,
+        };
+        let request = range::evaluate(range_header, metadata.len());
+        self.set_body(&mut response, request, metadata.len());
+        Ok(response)
+    }
+}
+
+pub fn serve_partial(video_path: &Path) -> io::Result<PartialFile> {
+    PartialFile::open(video_path)
+}
+
+#[cfg(test)]
+#[get("/")]
+fn serve_test_file(path: ::rocket::State<PathBuf>) -> io::Result<PartialFile> {
+    PartialFile::open(path.inner())
+}
+
+#[cfg(test)]
+fn test_client(contents: &[u8]) -> (::tempdir::TempDir, ::rocket::local::Client) {
+    use std::io::Write;
+    let dir = ::tempdir::TempDir::new("carolus_partial_file").unwrap();
+    let path = dir.path().join("movie.mp4");
+    File::create(&path).unwrap().write_all(contents).unwrap();
+    let rocket = ::rocket::ignite().manage(path).mount("/", routes![serve_test_file]);
+    (dir, ::rocket::local::Client::new(rocket).unwrap())
+}
+
+#[cfg(test)]
+fn test_contents() -> Vec<u8> {
+    (0..100).map(|i| b'a' + (i % 26) as u8).collect()
+}
+
+#[cfg(test)]
+fn get_range(client: &::rocket::local::Client, range: &str) -> ::rocket::local::LocalResponse {
+    client.get("/").header(::rocket::http::Header::new("Range", range.to_owned())).dispatch()
+}
+
+#[test]
+fn without_range_sends_whole_file() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = client.get("/").dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some("bytes"), response.headers().get_one("Accept-Ranges"));
+    assert_eq!(Some("video/mp4"), response.headers().get_one("Content-Type"));
+    assert_eq!(Some(test_contents()), response.body_bytes());
+}
+
+#[test]
+fn open_ended_range_from_zero() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=0-");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 0-99/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some(test_contents()), response.body_bytes());
+}
+
+#[test]
+fn bounded_range() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=10-19");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 10-19/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some("10"), response.headers().get_one("Content-Length"));
+    assert_eq!(Some(test_contents()[10..20].to_vec()), response.body_bytes());
+}
+
+#[test]
+fn range_past_end_is_truncated() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=90-500");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 90-99/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some(test_contents()[90..].to_vec()), response.body_bytes());
+}
+
+#[test]
+fn suffix_range() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=-5");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 95-99/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some(test_contents()[95..].to_vec()), response.body_bytes());
+}
+
+#[test]
+fn suffix_range_larger_than_file() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=-500");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 0-99/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some(test_contents()), response.body_bytes());
+}
+
+#[test]
+fn zero_length_suffix_is_unsatisfiable() {
+    let (_dir, client) = test_client(&test_contents());
+    let response = get_range(&client, "bytes=-0");
+    assert_eq!(Status::RangeNotSatisfiable, response.status());
+    assert_eq!(Some("bytes */100"), response.headers().get_one("Content-Range"));
+}
+
+#[test]
+fn range_starting_past_end_is_unsatisfiable() {
+    let (_dir, client) = test_client(&test_contents());
+    let response = get_range(&client, "bytes=100-");
+    assert_eq!(Status::RangeNotSatisfiable, response.status());
+    assert_eq!(Some("bytes */100"), response.headers().get_one("Content-Range"));
+}
+
+#[test]
+fn unsatisfiable_ranges_are_dropped_from_a_set() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=0-4,200-300");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 0-4/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some(test_contents()[..5].to_vec()), response.body_bytes());
+}
+
+#[test]
+fn zero_length_file() {
+    let (_dir, client) = test_client(&[]);
+    let mut response = client.get("/").dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some("0"), response.headers().get_one("Content-Length"));
+    assert_eq!(Some(Vec::new()), response.body_bytes());
+
+    let response = get_range(&client, "bytes=0-");
+    assert_eq!(Status::RangeNotSatisfiable, response.status());
+    assert_eq!(Some("bytes */0"), response.headers().get_one("Content-Range"));
+}
+
+#[test]
+fn overlapping_ranges_are_coalesced() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=0-10,5-20,21-29");
+    assert_eq!(Status::PartialContent, response.status());
+    assert_eq!(Some("bytes 0-29/100"), response.headers().get_one("Content-Range"));
+    assert_eq!(Some(test_contents()[..30].to_vec()), response.body_bytes());
+}
+
+#[test]
+fn disjoint_ranges_are_multipart() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "bytes=50-54,0-4");
+    assert_eq!(Status::PartialContent, response.status());
+    let content_type = response.headers().get_one("Content-Type").unwrap().to_owned();
+    assert!(content_type.starts_with("multipart/byteranges; boundary="));
+    let boundary = &content_type["multipart/byteranges; boundary=".len()..];
+    let expected = format!("\r\n--{b}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 0-4/100\r\n\r\nabcde\
+                            \r\n--{b}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 50-54/100\r\n\r\nyzabc\
+                            \r\n--{b}--\r\n", b = boundary);
+    assert_eq!(Some(expected.len().to_string()), response.headers().get_one("Content-Length").map(str::to_owned));
+    assert_eq!(Some(expected), response.body_string());
+}
+
+#[test]
+fn too_many_ranges_sends_whole_file() {
+    let (_dir, client) = test_client(&test_contents());
+    let ranges : Vec<String> = (0..20).map(|i| format!("{}-{}", i * 4, i * 4 + 1)).collect();
+    let mut response = get_range(&client, &format!("bytes={}", ranges.join(",")));
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some(test_contents()), response.body_bytes());
+}
+
+#[test]
+fn malformed_range_is_ignored() {
+    let (_dir, client) = test_client(&test_contents());
+    for range in &["bytes=abc", "bytes=", "bytes=10-5", "0-10"] {
+        let mut response = get_range(&client, range);
+        assert_eq!(Status::Ok, response.status(), "{}", range);
+        assert_eq!(Some(test_contents()), response.body_bytes());
+    }
+}
+
+#[test]
+fn non_byte_unit_is_ignored() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = get_range(&client, "items=0-5");
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some(test_contents()), response.body_bytes());
+}
+
+#[test]
+fn head_reports_length_without_body() {
+    let (_dir, client) = test_client(&test_contents());
+    let response = client.head("/").dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some("100"), response.headers().get_one("Content-Length"));
+    assert!(response.headers().get_one("ETag").is_some());
+}
+
+#[test]
+fn stale_if_range_sends_whole_file() {
+    let (_dir, client) = test_client(&test_contents());
+    let mut response = client.get("/")
+        .header(::rocket::http::Header::new("Range", "bytes=0-4"))
+        .header(::rocket::http::Header::new("If-Range", "\"stale\""))
+        .dispatch();
+    assert_eq!(Status::Ok, response.status());
+    assert_eq!(Some(test_contents()), response.body_bytes());
+}
+
+#[test]
+fn matching_if_none_match_is_not_modified() {
+    let (_dir, client) = test_client(&test_contents());
+    let etag = client.get("/").dispatch().headers().get_one("ETag").unwrap().to_owned();
+    let response = client.get("/").header(::rocket::http::Header::new("If-None-Match", etag)).dispatch();
+    assert_eq!(Status::NotModified, response.status());
+}
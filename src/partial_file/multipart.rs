@@ -0,0 +1,38 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use partial_file::body::Segment;
+
+static BOUNDARY_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub fn boundary() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64 ^ d.as_secs())
+        .unwrap_or(0);
+    let count = BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+    format!("carolus_{:016x}{:08x}", nanos, count)
+}
+
+pub fn content_type(boundary: &str) -> String {
+    format!("multipart/byteranges; boundary={}", boundary)
+}
+
+/// Lays out a `multipart/byteranges` body (RFC 7233 appendix A) for the given
+/// inclusive ranges, each part carrying its own `Content-Range`.
+pub fn byteranges(ranges: &[(u64, u64)], instance_length: u64, part_type: &str, boundary: &str) -> Vec<Segment> {
+    let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+    for &(from, to) in ranges {
+        let header = format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                             boundary, part_type, from, to, instance_length);
+        segments.push(Segment::Bytes(header.into_bytes()));
+        segments.push(Segment::File(from, to));
+    }
+    segments.push(Segment::Bytes(format!("\r\n--{}--\r\n", boundary).into_bytes()));
+    segments
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::str::FromStr;
+
+use rocket::http::hyper::header::{ByteRangeSpec, Range};
+
+/// More ranges than this (after coalescing) and the whole file is sent
+/// instead, so a single request can't fan out into thousands of seeks.
+pub const MAX_RANGES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartialFileRange {
+    AllFrom(u64),
+    FromTo(u64,u64),
+    Last(u64),
+}
+
+impl From<ByteRangeSpec> for PartialFileRange {
+    fn from(b: ByteRangeSpec) -> PartialFileRange {
+        match b {
+            ByteRangeSpec::AllFrom(from) => PartialFileRange::AllFrom(from),
+            ByteRangeSpec::FromTo(from, to) => PartialFileRange::FromTo(from, to),
+            ByteRangeSpec::Last(last) => PartialFileRange::Last(last),
+        }
+    }
+}
+
+impl PartialFileRange {
+    /// A `first-last` spec with `last < first` makes the whole header
+    /// invalid rather than just unsatisfiable (RFC 7233 section 2.1).
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            PartialFileRange::FromTo(from, to) => from <= to,
+            _ => true,
+        }
+    }
+
+    /// The inclusive byte positions this range covers in a file of
+    /// `file_length` bytes, or `None` if it is unsatisfiable.
+    pub fn resolve(&self, file_length: u64) -> Option<(u64, u64)> {
+        use self::PartialFileRange::*;
+        if file_length == 0 {
+            return None;
+        }
+        match *self {
+            FromTo(from, to) => {
+                if from <= to && from < file_length {
+                    Some((from, cmp::min(to, file_length - 1)))
+                } else {
+                    None
+                }
+            },
+            AllFrom(from) => {
+                if from < file_length {
+                    Some((from, file_length - 1))
+                } else {
+                    None
+                }
+            },
+            Last(0) => None,
+            Last(last) => {
+                if last < file_length {
+                    Some((file_length - last, file_length - 1))
+                } else {
+                    Some((0, file_length - 1))
+                }
+            },
+        }
+    }
+}
+
+/// What to send back for a request, given its `Range` header.
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    Full,
+    Partial(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+/// Sorts the ranges and merges any that overlap or touch.
+pub fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_by_key(|r| r.0);
+    let mut merged : Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (from, to) in ranges {
+        if let Some(last) = merged.last_mut() {
+            if from <= last.1.saturating_add(1) {
+                last.1 = cmp::max(last.1, to);
+                continue;
+            }
+        }
+        merged.push((from, to));
+    }
+    merged
+}
+
+/// Decides how to answer a request for a file of `file_length` bytes.
+///
+/// Headers that don't parse, or that use a unit other than bytes, are
+/// ignored and the whole file is sent, as RFC 7233 requires. A 416 is only
+/// the answer when the header is valid but none of its ranges overlap the
+/// file.
+pub fn evaluate(header: Option<&str>, file_length: u64) -> RangeRequest {
+    let specs = match header.map(Range::from_str) {
+        Some(Ok(Range::Bytes(specs))) => specs,
+        _ => return RangeRequest::Full,
+    };
+    let specs : Vec<PartialFileRange> = specs.into_iter().map(PartialFileRange::from).collect();
+    if specs.is_empty() || specs.iter().any(|spec| !spec.is_valid()) {
+        return RangeRequest::Full;
+    }
+    let ranges = coalesce(specs.iter().filter_map(|spec| spec.resolve(file_length)).collect());
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else if ranges.len() > MAX_RANGES {
+        RangeRequest::Full
+    } else {
+        RangeRequest::Partial(ranges)
+    }
+}
+
+#[test]
+fn coalesces_overlapping_and_adjacent_ranges() {
+    assert_eq!(vec![(0, 20), (30, 40)], coalesce(vec![(30, 40), (5, 20), (0, 10)]));
+    assert_eq!(vec![(0, 9)], coalesce(vec![(5, 9), (0, 4)]));
+    assert_eq!(vec![(0, 4), (6, 9)], coalesce(vec![(6, 9), (0, 4)]));
+}
+
+#[test]
+fn evaluates_without_a_file() {
+    assert_eq!(RangeRequest::Full, evaluate(None, 100));
+    assert_eq!(RangeRequest::Full, evaluate(Some("bytes=10-5"), 100));
+    assert_eq!(RangeRequest::Partial(vec![(90, 99)]), evaluate(Some("bytes=-10"), 100));
+    assert_eq!(RangeRequest::Unsatisfiable, evaluate(Some("bytes=100-"), 100));
+}
@@ -0,0 +1,298 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
+use std::hash::Hash;
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
+
+// Largest read done between two throttling decisions, keeps the pacing
+// smooth even when the caller hands over a huge buffer.
+const THROTTLE_CHUNK: usize = 64 * 1024;
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+fn from_secs_f64(secs: f64) -> Duration {
+    let nanos = (secs * 1_000_000_000.0) as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last: Instant,
+}
+
+/// A token bucket refilled at `rate` bytes a second, holding at most
+/// `capacity` tokens and starting full.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    capacity: u64,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    /// A bucket holding one second's worth of tokens.
+    pub fn new(rate: u64) -> TokenBucket {
+        TokenBucket::with_capacity(rate, rate)
+    }
+
+    pub fn with_capacity(rate: u64, capacity: u64) -> TokenBucket {
+        TokenBucket {
+            rate: rate,
+            capacity: capacity,
+            state: Mutex::new(BucketState { tokens: capacity as f64, last: Instant::now() }),
+        }
+    }
+
+    /// Takes `amount` tokens, going into debt if there aren't enough, and
+    /// returns how long the caller should wait for the debt to be paid off.
+    pub fn reserve(&self, amount: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = as_secs_f64(now.duration_since(state.last)) * self.rate as f64;
+        state.last = now;
+        state.tokens = (state.tokens + refill).min(self.capacity as f64) - amount as f64;
+        if state.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            from_secs_f64(-state.tokens / self.rate as f64)
+        }
+    }
+
+    /// Whether the bucket has refilled, at which point it is no different
+    /// from a new one.
+    pub fn is_full(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let refill = as_secs_f64(state.last.elapsed()) * self.rate as f64;
+        state.tokens + refill >= self.capacity as f64
+    }
+}
+
+/// The buckets a single stream has to draw from.
+#[derive(Debug)]
+pub struct StreamLimits {
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl StreamLimits {
+    pub fn wrap<R: Read>(self, body: R) -> ThrottledBody<R> {
+        ThrottledBody { inner: body, buckets: self.buckets }
+    }
+}
+
+pub struct ThrottledBody<R> {
+    inner: R,
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl<R: Read> Read for ThrottledBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = cmp::min(buf.len(), THROTTLE_CHUNK);
+        let read = self.inner.read(&mut buf[..max])?;
+        let wait = self.buckets.iter()
+            .map(|bucket| bucket.reserve(read as u64))
+            .max()
+            .unwrap_or(Duration::from_secs(0));
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+        Ok(read)
+    }
+}
+
+/// Who a stream is being sent to, the user comes from the
+/// `X-Carolus-User` header until there is proper authentication. Clients
+/// choose that header themselves, so it can't be relied on to enforce a
+/// limit.
+pub struct StreamClient {
+    pub ip: Option<IpAddr>,
+    pub user: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for StreamClient {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<StreamClient, ()> {
+        Outcome::Success(StreamClient {
+            ip: request.remote().map(|address| address.ip()),
+            user: request.headers().get_one("X-Carolus-User").map(str::to_owned),
+        })
+    }
+}
+
+// Streams from the same client share a bucket. The burst is part of the
+// bucket rather than each stream, so the many range requests of a player
+// seeking around only get it once until it has refilled, and buckets are
+// kept until then even when no stream is using them.
+fn shared_bucket<K: Hash + Eq>(buckets: &Mutex<HashMap<K, Arc<TokenBucket>>>, key: K, rate: u64, burst: u64) -> Arc<TokenBucket> {
+    let mut buckets = buckets.lock().unwrap();
+    if let Some(bucket) = buckets.get(&key) {
+        return bucket.clone();
+    }
+    buckets.retain(|_, bucket| Arc::strong_count(bucket) > 1 || !bucket.is_full());
+    let bucket = Arc::new(TokenBucket::with_capacity(rate, cmp::max(rate, burst)));
+    buckets.insert(key, bucket.clone());
+    bucket
+}
+
+/// Parses a rate in bytes, with an optional `K`, `M` or `G` suffix.
+pub fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 1024),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') | Some('g') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.trim().parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+fn rate_from_env(name: &str) -> Option<u64> {
+    env::var(name).ok().and_then(|value| {
+        let rate = parse_bytes(&value);
+        if rate.is_none() {
+            warn!("Ignoring {}, {} is not a byte rate", name, value);
+        }
+        rate
+    }).and_then(|rate| if rate == 0 { None } else { Some(rate) })
+}
+
+/// Who a per-user limit applies to, the client's address when it doesn't
+/// say who it is so that leaving the header out doesn't escape the limit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum UserKey {
+    User(String),
+    Ip(IpAddr),
+}
+
+/// Bandwidth limits for playback, configured with `CAROLUS_RATE_LIMIT`,
+/// `CAROLUS_RATE_LIMIT_PER_IP` and `CAROLUS_RATE_LIMIT_PER_USER` (bytes per
+/// second) and `CAROLUS_RATE_LIMIT_BURST` (bytes each client can take
+/// faster than its per-IP or per-user rate after being idle). The global
+/// limit is never exceeded, even in a burst.
+pub struct Throttle {
+    global: Option<Arc<TokenBucket>>,
+    per_ip: Option<u64>,
+    per_user: Option<u64>,
+    burst: u64,
+    ip_buckets: Mutex<HashMap<IpAddr, Arc<TokenBucket>>>,
+    user_buckets: Mutex<HashMap<UserKey, Arc<TokenBucket>>>,
+}
+
+impl Throttle {
+    pub fn new(global: Option<u64>, per_ip: Option<u64>, per_user: Option<u64>, burst: u64) -> Throttle {
+        Throttle {
+            global: global.map(|rate| Arc::new(TokenBucket::new(rate))),
+            per_ip: per_ip,
+            per_user: per_user,
+            burst: burst,
+            ip_buckets: Mutex::new(HashMap::new()),
+            user_buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Throttle {
+        Throttle::new(
+            rate_from_env("CAROLUS_RATE_LIMIT"),
+            rate_from_env("CAROLUS_RATE_LIMIT_PER_IP"),
+            rate_from_env("CAROLUS_RATE_LIMIT_PER_USER"),
+            env::var("CAROLUS_RATE_LIMIT_BURST").ok().and_then(|burst| parse_bytes(&burst)).unwrap_or(0))
+    }
+
+    pub fn limits_for(&self, client: &StreamClient) -> Option<StreamLimits> {
+        let mut buckets = Vec::new();
+        if let Some(ref global) = self.global {
+            buckets.push(global.clone());
+        }
+        if let (Some(rate), Some(ip)) = (self.per_ip, client.ip) {
+            buckets.push(shared_bucket(&self.ip_buckets, ip, rate, self.burst));
+        }
+        let user = match (client.user.as_ref(), client.ip) {
+            (Some(user), _) => Some(UserKey::User(user.clone())),
+            (None, Some(ip)) => Some(UserKey::Ip(ip)),
+            (None, None) => None,
+        };
+        if let (Some(rate), Some(user)) = (self.per_user, user) {
+            buckets.push(shared_bucket(&self.user_buckets, user, rate, self.burst));
+        }
+        if buckets.is_empty() {
+            None
+        } else {
+            Some(StreamLimits { buckets: buckets })
+        }
+    }
+}
+
+#[test]
+fn parses_byte_rates() {
+    assert_eq!(Some(500), parse_bytes("500"));
+    assert_eq!(Some(2 * 1024 * 1024), parse_bytes("2M"));
+    assert_eq!(Some(10 * 1024), parse_bytes(" 10k "));
+    assert_eq!(None, parse_bytes("fast"));
+}
+
+#[test]
+fn bucket_goes_into_debt() {
+    let bucket = TokenBucket::new(1000);
+    assert_eq!(Duration::from_secs(0), bucket.reserve(1000));
+    let wait = bucket.reserve(500);
+    assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{:?}", wait);
+}
+
+#[test]
+fn clients_share_buckets() {
+    let throttle = Throttle::new(None, Some(1000), None, 0);
+    let client = StreamClient { ip: Some("127.0.0.1".parse().unwrap()), user: None };
+    let first = throttle.limits_for(&client).unwrap();
+    let second = throttle.limits_for(&client).unwrap();
+    assert!(Arc::ptr_eq(&first.buckets[0], &second.buckets[0]));
+    assert!(Throttle::new(None, None, None, 0).limits_for(&client).is_none());
+}
+
+#[test]
+fn users_without_a_name_are_limited_by_address() {
+    let throttle = Throttle::new(None, None, Some(1000), 0);
+    let anonymous = StreamClient { ip: Some("127.0.0.1".parse().unwrap()), user: None };
+    let named = StreamClient { ip: Some("127.0.0.1".parse().unwrap()), user: Some("simon".to_string()) };
+    let first = throttle.limits_for(&anonymous).unwrap();
+    let second = throttle.limits_for(&anonymous).unwrap();
+    let third = throttle.limits_for(&named).unwrap();
+    assert!(Arc::ptr_eq(&first.buckets[0], &second.buckets[0]));
+    assert!(!Arc::ptr_eq(&first.buckets[0], &third.buckets[0]));
+}
+
+#[test]
+fn bursts_are_shared_by_a_clients_streams() {
+    let throttle = Throttle::new(None, Some(1000), None, 5000);
+    let client = StreamClient { ip: Some("127.0.0.1".parse().unwrap()), user: None };
+    let first = throttle.limits_for(&client).unwrap();
+    assert_eq!(Duration::from_secs(0), first.buckets[0].reserve(5000));
+    drop(first);
+    // The next range request doesn't get a fresh burst
+    let second = throttle.limits_for(&client).unwrap();
+    assert!(second.buckets[0].reserve(1000) > Duration::from_millis(900));
+}
+
+#[test]
+fn bursts_stay_within_the_global_limit() {
+    let throttle = Throttle::new(Some(1000), Some(1000), None, 5000);
+    let client = StreamClient { ip: Some("127.0.0.1".parse().unwrap()), user: None };
+    let limits = throttle.limits_for(&client).unwrap();
+    let wait = limits.buckets.iter().map(|bucket| bucket.reserve(5000)).max().unwrap();
+    assert!(wait > Duration::from_secs(3), "{:?}", wait);
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::http::{HeaderMap, Method};
+use rocket::http::hyper::header::{EntityTag, ETag, HttpDate, LastModified};
+use rocket::response::Response;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Precondition {
+    Proceed { honour_range: bool },
+    NotModified,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: EntityTag,
+    pub last_modified: Option<SystemTime>,
+}
+
+enum Matcher {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl Matcher {
+    fn parse(value: &str) -> Matcher {
+        if value.trim() == "*" {
+            Matcher::Any
+        } else {
+            Matcher::Tags(value.split(',').filter_map(|tag| tag.trim().parse::<EntityTag>().ok()).collect())
+        }
+    }
+
+    fn strong_matches(&self, etag: &EntityTag) -> bool {
+        match *self {
+            Matcher::Any => true,
+            Matcher::Tags(ref tags) => tags.iter().any(|tag| tag.strong_eq(etag)),
+        }
+    }
+
+    fn weak_matches(&self, etag: &EntityTag) -> bool {
+        match *self {
+            Matcher::Any => true,
+            Matcher::Tags(ref tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+// HTTP dates only carry whole seconds, so comparisons are done at that
+// precision too.
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + Duration::from_secs(duration.as_secs()),
+        Err(_) => time,
+    }
+}
+
+fn parse_date(value: &str) -> Option<SystemTime> {
+    value.trim().parse::<HttpDate>().ok().map(SystemTime::from)
+}
+
+impl Validators {
+    /// A strong validator built from the inode, size and modification time,
+    /// which changes whenever the file is replaced or rewritten.
+    pub fn from_metadata(metadata: &Metadata) -> Validators {
+        let modified = metadata.modified().ok();
+        let nanos = modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        let tag = format!("{:x}-{:x}-{:x}", inode(metadata), metadata.len(), nanos);
+        Validators {
+            etag: EntityTag::strong(tag),
+            last_modified: modified.map(truncate_to_seconds),
+        }
+    }
+
+    pub fn set_headers(&self, response: &mut Response) {
+        response.set_header(ETag(self.etag.clone()));
+        if let Some(last_modified) = self.last_modified {
+            response.set_header(LastModified(HttpDate::from(last_modified)));
+        }
+    }
+
+    /// Evaluates the conditional request headers in the order given by
+    /// RFC 7232 section 6, then decides whether an `If-Range` still allows
+    /// the `Range` header to be used.
+    pub fn evaluate(&self, method: Method, headers: &HeaderMap) -> Precondition {
+        let safe = method == Method::Get || method == Method::Head;
+
+        if let Some(if_match) = headers.get_one("If-Match") {
+            if !Matcher::parse(if_match).strong_matches(&self.etag) {
+                return Precondition::Failed;
+            }
+        }
+
+        match headers.get_one("If-None-Match") {
+            Some(if_none_match) => {
+                if Matcher::parse(if_none_match).weak_matches(&self.etag) {
+                    return if safe { Precondition::NotModified } else { Precondition::Failed };
+                }
+            },
+            None => {
+                let since = headers.get_one("If-Modified-Since").and_then(parse_date);
+                if let (true, Some(since), Some(last_modified)) = (safe, since, self.last_modified) {
+                    if last_modified <= since {
+                        return Precondition::NotModified;
+                    }
+                }
+            },
+        }
+
+        Precondition::Proceed { honour_range: self.if_range_matches(headers) }
+    }
+
+    fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let if_range = match headers.get_one("If-Range") {
+            Some(if_range) => if_range.trim(),
+            None => return true,
+        };
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            match if_range.parse::<EntityTag>() {
+                Ok(tag) => tag.strong_eq(&self.etag),
+                Err(_) => false,
+            }
+        } else {
+            match (parse_date(if_range), self.last_modified) {
+                (Some(date), Some(last_modified)) => date == last_modified,
+                _ => false,
+            }
+        }
+    }
+}
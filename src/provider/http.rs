@@ -0,0 +1,335 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp;
+use std::env;
+use std::error::Error;
+use std::io::Read;
+use std::str;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use chrono;
+use diesel::sqlite::SqliteConnection;
+use reqwest::{self, Client, Response};
+use serde::de::DeserializeOwned;
+use serde_json;
+use url::Url;
+
+use data::init::connect;
+use data::models::NewProviderResponse;
+use data::provider_responses::{find_provider_response, save_provider_response};
+use partial_file::throttle::TokenBucket;
+
+/// Rate limited and failing requests are tried this many more times.
+const MAX_RETRIES: u32 = 3;
+
+/// The wait before the first retry, doubled for each one after it unless
+/// the service sends `Retry-After`.
+const BACKOFF_MS: u64 = 1000;
+
+const MAX_BACKOFF_SECONDS: u64 = 60;
+
+const DEFAULT_CACHE_DAYS: i64 = 7;
+
+lazy_static! {
+    static ref SHARED: Arc<HttpClient> = Arc::new(HttpClient::new(ResponseCache::from_env()));
+}
+
+/// Provider responses kept in the database, so rescanning a library only
+/// asks the services about movies that haven't been looked up lately.
+pub struct ResponseCache {
+    database_url: String,
+    ttl: chrono::Duration,
+}
+
+impl ResponseCache {
+    pub fn new(database_url: &str, ttl: chrono::Duration) -> ResponseCache {
+        ResponseCache { database_url: database_url.to_string(), ttl: ttl }
+    }
+
+    /// Reads `DATABASE_URL` and `CAROLUS_PROVIDER_CACHE_DAYS` (default 7,
+    /// 0 turns the cache off).
+    pub fn from_env() -> Option<ResponseCache> {
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(database_url) => database_url,
+            Err(_) => return None,
+        };
+        let days = env::var("CAROLUS_PROVIDER_CACHE_DAYS").ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_DAYS);
+        if days <= 0 {
+            return None;
+        }
+        Some(ResponseCache::new(&database_url, chrono::Duration::days(days)))
+    }
+
+    // Requests come from scan and watcher threads, each opening its own
+    // connection like the indexer does. Lookups are made outside the
+    // indexer's transactions, so writing here only waits for them.
+    fn open(&self) -> Option<SqliteConnection> {
+        match connect(&self.database_url) {
+            Ok(conn) => Some(conn),
+            Err(err) => {
+                warn!("Could not open the provider response cache: {}", err);
+                None
+            },
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.open()
+            .and_then(|conn| find_provider_response(&conn, key, Utc::now().naive_utc()))
+            .map(|response| response.body)
+    }
+
+    pub fn put(&self, key: &str, body: &str) {
+        let conn = match self.open() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let now = Utc::now().naive_utc();
+        let response = NewProviderResponse {
+            url: key,
+            body: body,
+            fetched_date: now,
+            expires_date: now + self.ttl,
+        };
+        if let Err(err) = save_provider_response(&conn, &response) {
+            warn!("Could not cache provider response: {}", err);
+        }
+    }
+}
+
+/// The URL a response is cached under, without the API key so keys aren't
+/// kept in the database and a new key doesn't empty the cache.
+fn cache_key(url: &Url) -> String {
+    let pairs : Vec<(String, String)> = url.query_pairs()
+        .filter(|&(ref name, _)| name != "api_key" && name != "apikey")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    let mut key = url.clone();
+    if pairs.is_empty() {
+        key.set_query(None);
+    } else {
+        key.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    key.into_string()
+}
+
+/// The HTTP client metadata providers share. Responses are cached, each
+/// request waits for the provider's rate limit, and requests that were
+/// rate limited or hit a server error are retried with backoff.
+pub struct HttpClient {
+    client: Client,
+    cache: Option<ResponseCache>,
+    backoff: Duration,
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response.headers().get_raw("Retry-After")
+        .and_then(|raw| raw.one())
+        .and_then(|value| str::from_utf8(value).ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Where a request went, leaving out the query and the API key in it.
+fn redacted(url: &Url) -> String {
+    format!("{}{}", url.host_str().unwrap_or(""), url.path())
+}
+
+/// reqwest's errors print the whole URL, so only their cause is kept.
+fn request_error(url: &Url, err: &reqwest::Error) -> Box<Error> {
+    match err.get_ref() {
+        Some(cause) => format!("Request to {} failed: {}", redacted(url), cause).into(),
+        None => format!("Request to {} failed", redacted(url)).into(),
+    }
+}
+
+fn read_body(url: &Url, mut response: Response) -> Result<String, Box<Error>> {
+    if !response.status().is_success() {
+        return Err(format!("{} returned {}", redacted(url), response.status()).into());
+    }
+    let mut body = String::new();
+    response.read_to_string(&mut body)
+        .map_err(|err| format!("Could not read the response from {}: {}", redacted(url), err))?;
+    Ok(body)
+}
+
+impl HttpClient {
+    pub fn new(cache: Option<ResponseCache>) -> HttpClient {
+        HttpClient { client: Client::new(), cache: cache, backoff: Duration::from_millis(BACKOFF_MS) }
+    }
+
+    /// The client configured from the environment, shared by every provider.
+    pub fn shared() -> Arc<HttpClient> {
+        SHARED.clone()
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration) -> HttpClient {
+        self.backoff = backoff;
+        self
+    }
+
+    fn fetch(&self, url: &Url, limit: &TokenBucket) -> Result<String, Box<Error>> {
+        let mut attempt = 0;
+        loop {
+            let wait = limit.reserve(1);
+            if wait > Duration::from_secs(0) {
+                thread::sleep(wait);
+            }
+            let delay = match self.client.get(url.clone()).send() {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    if (status == 429 || status >= 500) && attempt < MAX_RETRIES {
+                        retry_after(&response)
+                    } else {
+                        return read_body(url, response);
+                    }
+                },
+                Err(ref err) if attempt < MAX_RETRIES => {
+                    warn!("{}", request_error(url, err));
+                    None
+                },
+                Err(ref err) => return Err(request_error(url, err)),
+            };
+            let delay = delay.unwrap_or_else(|| self.backoff * 2u32.pow(attempt));
+            let delay = cmp::min(delay, Duration::from_secs(MAX_BACKOFF_SECONDS));
+            info!("Retrying {} in {}ms", redacted(url),
+                delay.as_secs() * 1000 + delay.subsec_nanos() as u64 / 1_000_000);
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// The body of a successful response, from the cache if it is there.
+    pub fn get(&self, url: &Url, limit: &TokenBucket) -> Result<String, Box<Error>> {
+        let key = cache_key(url);
+        if let Some(body) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            return Ok(body);
+        }
+        let body = self.fetch(url, limit)?;
+        if let Some(ref cache) = self.cache {
+            cache.put(&key, &body);
+        }
+        Ok(body)
+    }
+
+    pub fn get_json<T: DeserializeOwned>(&self, url: &Url, limit: &TokenBucket) -> Result<T, Box<Error>> {
+        Ok(serde_json::from_str(&self.get(url, limit)?)?)
+    }
+}
+
+#[cfg(test)]
+fn test_client(cache: Option<ResponseCache>) -> HttpClient {
+    HttpClient::new(cache).with_backoff(Duration::from_millis(1))
+}
+
+#[test]
+fn rate_limited_and_failed_requests_are_retried() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::with_responses(&[(429, "{}"), (503, "{}"), (200, r#"{"results": []}"#)]);
+    let url = Url::parse(&format!("{}/search/movie?query=Heat", server.url)).unwrap();
+    let body = test_client(None).get(&url, &TokenBucket::new(100)).unwrap();
+    assert_eq!(r#"{"results": []}"#, body);
+    assert_eq!(3, server.requests().len());
+}
+
+#[test]
+fn retries_give_up_eventually() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(500, "{}");
+    let url = Url::parse(&server.url).unwrap();
+    assert!(test_client(None).get(&url, &TokenBucket::new(100)).is_err());
+    assert_eq!(MAX_RETRIES as usize + 1, server.requests().len());
+
+    // Client errors won't get better by asking again
+    let server = MockServer::start(401, "{}");
+    let url = Url::parse(&server.url).unwrap();
+    assert!(test_client(None).get(&url, &TokenBucket::new(100)).is_err());
+    assert_eq!(1, server.requests().len());
+}
+
+#[cfg(test)]
+fn cache_database(dir: &::tempdir::TempDir) -> String {
+    use diesel::prelude::*;
+    let database_url = dir.path().join("carolus.db").to_str().unwrap().to_string();
+    connect(&database_url).unwrap()
+        .execute(include_str!("../../migrations/2018-01-16-000000_create_provider_responses/up.sql"))
+        .unwrap();
+    database_url
+}
+
+#[test]
+fn api_keys_are_left_out_of_cache_keys() {
+    let url = Url::parse("https://api.themoviedb.org/3/search/movie?api_key=secret&query=Heat&year=1995").unwrap();
+    assert_eq!("https://api.themoviedb.org/3/search/movie?query=Heat&year=1995", cache_key(&url));
+    let url = Url::parse("https://www.omdbapi.com/?apikey=secret").unwrap();
+    assert_eq!("https://www.omdbapi.com/", cache_key(&url));
+}
+
+#[test]
+fn errors_leave_out_the_api_key() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(401, "{}");
+    let url = Url::parse(&format!("{}/search/movie?api_key=secret&query=Heat", server.url)).unwrap();
+    let err = test_client(None).get(&url, &TokenBucket::new(100)).unwrap_err();
+    assert!(err.to_string().contains("/search/movie returned 401"));
+    assert!(!err.to_string().contains("secret"));
+
+    // Nothing listens on port 1, so the request itself fails
+    let url = Url::parse("http://127.0.0.1:1/?apikey=secret").unwrap();
+    let err = test_client(None).get(&url, &TokenBucket::new(100)).unwrap_err();
+    assert!(!err.to_string().contains("secret"));
+}
+
+#[test]
+fn responses_are_cached_until_they_expire() {
+    use provider::mock_server::MockServer;
+    let dir = ::tempdir::TempDir::new("carolus_http").unwrap();
+    let database_url = cache_database(&dir);
+    let server = MockServer::start(200, r#"{"results": []}"#);
+    let url = Url::parse(&format!("{}/search/movie?query=Heat", server.url)).unwrap();
+    let limit = TokenBucket::new(100);
+
+    let client = test_client(Some(ResponseCache::new(&database_url, chrono::Duration::days(1))));
+    let first : serde_json::Value = client.get_json(&url, &limit).unwrap();
+    let second : serde_json::Value = client.get_json(&url, &limit).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(1, server.requests().len());
+
+    let url = Url::parse(&format!("{}/search/movie?query=Alien", server.url)).unwrap();
+    let expired = test_client(Some(ResponseCache::new(&database_url, chrono::Duration::days(-1))));
+    expired.get(&url, &limit).unwrap();
+    expired.get(&url, &limit).unwrap();
+    assert_eq!(3, server.requests().len());
+}
+
+#[test]
+fn responses_are_cached_while_another_connection_writes() {
+    use diesel::prelude::*;
+    use std::sync::mpsc::channel;
+    let dir = ::tempdir::TempDir::new("carolus_http").unwrap();
+    let database_url = cache_database(&dir);
+    let (locked, wait_for_lock) = channel();
+    let writer_url = database_url.clone();
+    let writer = thread::spawn(move || {
+        let conn = connect(&writer_url).unwrap();
+        conn.execute("BEGIN IMMEDIATE").unwrap();
+        locked.send(()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        conn.execute("COMMIT").unwrap();
+    });
+    wait_for_lock.recv().unwrap();
+
+    let cache = ResponseCache::new(&database_url, chrono::Duration::days(1));
+    cache.put("https://api.themoviedb.org/3/search/movie?query=Heat", "{}");
+    writer.join().unwrap();
+    assert_eq!(Some("{}".to_string()), cache.get("https://api.themoviedb.org/3/search/movie?query=Heat"));
+}
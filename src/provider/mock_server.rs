@@ -0,0 +1,80 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A local HTTP server for provider tests, answering requests with the
+/// given statuses and JSON bodies in turn, the last one over and over, and
+/// remembering the method and path of each request it saw.
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Error",
+    }
+}
+
+fn answer(stream: TcpStream, status: u16, body: &str, requests: &Mutex<Vec<String>>) {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header) {
+            Ok(0) | Err(_) => break,
+            Ok(_) if header.trim().is_empty() => break,
+            Ok(_) => (),
+        }
+    }
+    let request : Vec<&str> = request_line.split_whitespace().take(2).collect();
+    requests.lock().unwrap().push(request.join(" "));
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason(status), body.len(), body);
+    let _ = reader.get_mut().write_all(response.as_bytes());
+}
+
+impl MockServer {
+    pub fn start(status: u16, body: &str) -> MockServer {
+        MockServer::with_responses(&[(status, body)])
+    }
+
+    pub fn with_responses(responses: &[(u16, &str)]) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let responses : Vec<(u16, String)> = responses.iter().map(|&(status, body)| (status, body.to_string())).collect();
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().enumerate() {
+                let (status, ref body) = responses[index.min(responses.len() - 1)];
+                match stream {
+                    Ok(stream) => answer(stream, status, body, &seen),
+                    Err(_) => return,
+                }
+            }
+        });
+        MockServer { url: url, requests: requests }
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -1,8 +1,349 @@
-#[macro_use] extern crate serde_derive;
-extern crate serde;
-extern crate serde_json;
-extern crate reqwest;
-extern crate url;
-extern crate chrono;
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+pub mod http;
+pub mod nfo;
+pub mod omdb;
 pub mod themoviedb;
+#[cfg(test)]
+pub mod mock_server;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use serde_json;
+
+use data::metadata::{get_movie_metadata, save_movie_metadata};
+use data::models::{self, NewMovieMetadata};
+use provider::nfo::{self, Nfo};
+use provider::omdb::Omdb;
+use provider::themoviedb::TheMovieDb;
+
+/// Every provider, in the order they are used unless a library says
+/// otherwise: curated NFO files first, then the online services.
+pub const PROVIDERS: &'static [&'static str] = &["nfo", "tmdb", "omdb"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Actor {
+    pub name: String,
+    pub role: Option<String>,
+}
+
+/// A rating from one source, like `imdb`, out of ten.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub source: String,
+    pub value: f32,
+    pub votes: Option<i64>,
+}
+
+/// What a provider knows about a movie. Every field is optional, so the
+/// results of several providers can be merged field by field.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub year: Option<i32>,
+    pub overview: Option<String>,
+    pub release_date: Option<NaiveDate>,
+    /// A URL, or the path of a local file.
+    pub poster: Option<String>,
+    pub backdrop: Option<String>,
+    pub tmdb_id: Option<i32>,
+    pub imdb_id: Option<String>,
+    pub genres: Vec<String>,
+    /// In billing order.
+    pub actors: Vec<Actor>,
+    pub ratings: Vec<Rating>,
+}
+
+fn fill<T>(field: &mut Option<T>, other: Option<T>) {
+    if field.is_none() {
+        *field = other;
+    }
+}
+
+fn fill_list<T>(list: &mut Vec<T>, other: Vec<T>) {
+    if list.is_empty() {
+        *list = other;
+    }
+}
+
+fn json_list<T>(json: &str) -> Vec<T>
+    where T: ::serde::de::DeserializeOwned
+{
+    serde_json::from_str(json).unwrap_or_else(|_| Vec::new())
+}
+
+impl Metadata {
+    /// Takes the fields this doesn't have yet from `other`, so merging
+    /// results in priority order keeps each field from the first provider
+    /// that has it.
+    pub fn merge(&mut self, other: Metadata) {
+        fill(&mut self.title, other.title);
+        fill(&mut self.year, other.year);
+        fill(&mut self.overview, other.overview);
+        fill(&mut self.release_date, other.release_date);
+        fill(&mut self.poster, other.poster);
+        fill(&mut self.backdrop, other.backdrop);
+        fill(&mut self.tmdb_id, other.tmdb_id);
+        fill(&mut self.imdb_id, other.imdb_id);
+        fill_list(&mut self.genres, other.genres);
+        fill_list(&mut self.actors, other.actors);
+        fill_list(&mut self.ratings, other.ratings);
+    }
+
+    /// Whether there's nothing left for another provider to fill in.
+    pub fn is_complete(&self) -> bool {
+        self.title.is_some() && self.year.is_some() && self.overview.is_some()
+            && self.release_date.is_some() && self.poster.is_some() && self.backdrop.is_some()
+            && self.tmdb_id.is_some() && self.imdb_id.is_some()
+            && !self.genres.is_empty() && !self.actors.is_empty() && !self.ratings.is_empty()
+    }
+
+    /// What was stored for a movie, with the title and year from its file
+    /// name where no provider had them.
+    pub fn from_model(metadata: models::MovieMetadata, title: &str) -> Metadata {
+        Metadata {
+            title: Some(metadata.title.unwrap_or_else(|| title.to_string())),
+            year: metadata.year,
+            overview: metadata.overview,
+            release_date: metadata.release_date,
+            poster: metadata.poster,
+            backdrop: metadata.backdrop,
+            tmdb_id: metadata.tmdb_id,
+            imdb_id: metadata.imdb_id,
+            genres: json_list(&metadata.genres),
+            actors: json_list(&metadata.actors),
+            ratings: json_list(&metadata.ratings),
+        }
+    }
+}
+
+/// The movie being looked up: its file, and the title and year from the
+/// file name or from providers earlier in the list.
+pub struct MovieQuery<'a> {
+    pub path: &'a Path,
+    pub title: &'a str,
+    pub year: Option<i32>,
+}
+
+/// A source of movie metadata. `Ok(None)` means the provider has nothing
+/// for the movie, errors are for when it couldn't be asked.
+pub trait MetadataProvider {
+    fn name(&self) -> &'static str;
+
+    fn movie(&self, query: &MovieQuery) -> Result<Option<Metadata>, Box<Error>>;
+}
+
+/// The named provider, or None if it doesn't exist or isn't configured,
+/// like an online service without an API key.
+pub fn provider(name: &str) -> Option<Box<MetadataProvider>> {
+    match name {
+        "nfo" => Some(Box::new(Nfo)),
+        "tmdb" => TheMovieDb::from_env().map(|tmdb| Box::new(tmdb) as Box<MetadataProvider>),
+        "omdb" => Omdb::from_env().map(|omdb| Box::new(omdb) as Box<MetadataProvider>),
+        _ => None,
+    }
+}
+
+pub fn providers(names: &[String]) -> Vec<Box<MetadataProvider>> {
+    names.iter().filter_map(|name| provider(name)).collect()
+}
+
+/// Asks each provider in turn, merging what they find. Once a provider has
+/// found the movie its title and year are used for the ones after it, as
+/// they are likely to be better than what was in the file name.
+pub fn lookup_movie(providers: &[Box<MetadataProvider>], path: &Path, title: &str, year: Option<i32>) -> (Metadata, Vec<&'static str>) {
+    let mut metadata = Metadata::default();
+    let mut sources = Vec::new();
+    for provider in providers {
+        if metadata.is_complete() {
+            break;
+        }
+        let found = {
+            let query = MovieQuery {
+                path: path,
+                title: metadata.title.as_ref().map_or(title, |title| title.as_str()),
+                year: metadata.year.or(year),
+            };
+            provider.movie(&query)
+        };
+        match found {
+            Ok(Some(found)) => {
+                metadata.merge(found);
+                sources.push(provider.name());
+            },
+            Ok(None) => debug!("No {} match for {}", provider.name(), title),
+            Err(err) => warn!("Could not look up {} with {}: {}", title, provider.name(), err),
+        }
+    }
+    (metadata, sources)
+}
+
+fn save_lookup(conn: &SqliteConnection, providers: &[Box<MetadataProvider>], movie_id: i32, path: &Path, title: &str, year: Option<i32>) {
+    let (metadata, sources) = lookup_movie(providers, path, title, year);
+    if sources.is_empty() {
+        info!("No metadata found for {}", title);
+        return;
+    }
+    let sources = sources.join(",");
+    let genres = serde_json::to_string(&metadata.genres).unwrap();
+    let actors = serde_json::to_string(&metadata.actors).unwrap();
+    let ratings = serde_json::to_string(&metadata.ratings).unwrap();
+    let new_metadata = NewMovieMetadata {
+        movie_id: movie_id,
+        title: metadata.title.as_ref().map(String::as_str),
+        year: metadata.year,
+        overview: metadata.overview.as_ref().map(String::as_str),
+        release_date: metadata.release_date,
+        poster: metadata.poster.as_ref().map(String::as_str),
+        backdrop: metadata.backdrop.as_ref().map(String::as_str),
+        tmdb_id: metadata.tmdb_id,
+        imdb_id: metadata.imdb_id.as_ref().map(String::as_str),
+        sources: &sources,
+        fetched_date: Utc::now().naive_utc(),
+        genres: &genres,
+        actors: &actors,
+        ratings: &ratings,
+    };
+    if let Err(err) = save_movie_metadata(conn, &new_metadata) {
+        error!("Error saving metadata for {}: {}", title, err);
+    }
+}
+
+/// Whether the movie has an NFO that was written after its metadata was
+/// last looked up, and the library reads NFOs.
+fn nfo_changed(conn: &SqliteConnection, providers: &[Box<MetadataProvider>], movie_id: i32, path: &Path) -> bool {
+    if !providers.iter().any(|provider| provider.name() == "nfo") {
+        return false;
+    }
+    let modified = match nfo::nfo_path(path).and_then(|nfo| nfo::modified(&nfo)) {
+        Some(modified) => modified,
+        None => return false,
+    };
+    match get_movie_metadata(conn, movie_id) {
+        Some(metadata) => modified > metadata.fetched_date.timestamp(),
+        None => true,
+    }
+}
+
+/// Looks a new or changed movie up with the library's providers and keeps
+/// what they find, unless it has been looked up already and its NFO hasn't
+/// been edited since. Failures are only logged, a movie without metadata
+/// still plays.
+pub fn fetch_movie_metadata(conn: &SqliteConnection, providers: &[Box<MetadataProvider>], movie_id: i32, path: &Path, title: &str, year: Option<i32>) {
+    if providers.is_empty() {
+        return;
+    }
+    if get_movie_metadata(conn, movie_id).is_some() && !nfo_changed(conn, providers, movie_id, path) {
+        return;
+    }
+    save_lookup(conn, providers, movie_id, path, title, year);
+}
+
+/// Looks an unchanged movie up again only if its NFO has been edited, as
+/// hand curated NFOs are the authority on their movie.
+pub fn refresh_movie_metadata(conn: &SqliteConnection, providers: &[Box<MetadataProvider>], movie_id: i32, path: &Path, title: &str, year: Option<i32>) {
+    if nfo_changed(conn, providers, movie_id, path) {
+        info!("NFO changed for {}", title);
+        save_lookup(conn, providers, movie_id, path, title, year);
+    }
+}
+
+/// A movie to look up once the scan that found it has committed, so the
+/// database isn't held locked while providers are waited on.
+pub struct MetadataLookup {
+    pub movie_id: i32,
+    pub path: PathBuf,
+    pub title: String,
+    pub year: Option<i32>,
+    /// Movies whose file hasn't changed are only looked up again if their
+    /// NFO has been edited.
+    pub unchanged: bool,
+}
+
+impl MetadataLookup {
+    pub fn run(&self, conn: &SqliteConnection, providers: &[Box<MetadataProvider>]) {
+        if self.unchanged {
+            refresh_movie_metadata(conn, providers, self.movie_id, &self.path, &self.title, self.year);
+        } else {
+            fetch_movie_metadata(conn, providers, self.movie_id, &self.path, &self.title, self.year);
+        }
+    }
+}
+
+#[cfg(test)]
+type Queries = ::std::rc::Rc<::std::cell::RefCell<Vec<(String, Option<i32>)>>>;
+
+#[cfg(test)]
+struct FakeProvider {
+    name: &'static str,
+    metadata: Option<Metadata>,
+    queries: Queries,
+}
+
+#[cfg(test)]
+impl MetadataProvider for FakeProvider {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn movie(&self, query: &MovieQuery) -> Result<Option<Metadata>, Box<Error>> {
+        self.queries.borrow_mut().push((query.title.to_string(), query.year));
+        Ok(self.metadata.clone())
+    }
+}
+
+#[cfg(test)]
+fn fake(name: &'static str, metadata: Option<Metadata>, queries: &Queries) -> Box<MetadataProvider> {
+    Box::new(FakeProvider { name: name, metadata: metadata, queries: queries.clone() })
+}
+
+#[test]
+fn fields_come_from_the_first_provider_that_has_them() {
+    let nfo = Metadata {
+        title: Some("Heat".to_string()),
+        year: Some(1995),
+        imdb_id: Some("tt0113277".to_string()),
+        ..Metadata::default()
+    };
+    let tmdb = Metadata {
+        title: Some("Heat (1995 film)".to_string()),
+        overview: Some("A group of professional bank robbers.".to_string()),
+        poster: Some("https://image.tmdb.org/t/p/original/heat.jpg".to_string()),
+        tmdb_id: Some(949),
+        ..Metadata::default()
+    };
+    let queries = Queries::default();
+    let providers = vec![fake("nfo", Some(nfo), &queries), fake("omdb", None, &queries), fake("tmdb", Some(tmdb), &queries)];
+    let (metadata, sources) = lookup_movie(&providers, Path::new("/movies/heat.mkv"), "heat", None);
+    assert_eq!(vec!["nfo", "tmdb"], sources);
+    assert_eq!(Some("Heat".to_string()), metadata.title);
+    assert_eq!(Some(1995), metadata.year);
+    assert_eq!(Some("tt0113277".to_string()), metadata.imdb_id);
+    assert_eq!(Some(949), metadata.tmdb_id);
+    assert_eq!(Some("https://image.tmdb.org/t/p/original/heat.jpg".to_string()), metadata.poster);
+    assert_eq!(None, metadata.backdrop);
+}
+
+#[test]
+fn later_providers_are_asked_with_the_title_found_so_far() {
+    let (nfo_queries, tmdb_queries) = (Queries::default(), Queries::default());
+    let found = Metadata { title: Some("Heat".to_string()), year: Some(1995), ..Metadata::default() };
+    let providers = vec![fake("nfo", Some(found), &nfo_queries), fake("tmdb", None, &tmdb_queries)];
+    lookup_movie(&providers, Path::new("/movies/heat.mkv"), "heat 1080p", None);
+    assert_eq!(vec![("heat 1080p".to_string(), None)], *nfo_queries.borrow());
+    assert_eq!(vec![("Heat".to_string(), Some(1995))], *tmdb_queries.borrow());
+}
+
+#[test]
+fn unknown_providers_are_left_out() {
+    assert!(provider("imdb-scraper").is_none());
+    let names : Vec<&str> = providers(&["nfo".to_string(), "nope".to_string()]).iter().map(|provider| provider.name()).collect();
+    assert_eq!(vec!["nfo"], names);
+}
@@ -0,0 +1,414 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use chrono::NaiveDate;
+use diesel::sqlite::SqliteConnection;
+use regex::Regex;
+use xml::reader::{EventReader, XmlEvent};
+use xml::writer::{EmitterConfig, EventWriter, Result as WriterResult, XmlEvent as WriteEvent};
+
+use data::metadata::get_movie_metadata;
+use data::movies::available_movies;
+use file_index::index::is_movie_file;
+use provider::{Actor, Metadata, MetadataProvider, MovieQuery, Rating};
+
+/// Reads Kodi style NFO files kept next to movies, `<movie name>.nfo` or
+/// `movie.nfo` in the movie's folder.
+pub struct Nfo;
+
+/// The NFO for a movie file, if there is one. As in Kodi, `movie.nfo` is
+/// only read for a movie that has its folder to itself.
+pub fn nfo_path(movie_path: &Path) -> Option<PathBuf> {
+    let own = movie_path.with_extension("nfo");
+    if own.is_file() {
+        return Some(own);
+    }
+    let folder = match movie_path.parent() {
+        Some(folder) => folder,
+        None => return None,
+    };
+    let nfo = folder.join("movie.nfo");
+    if nfo.is_file() && is_only_movie_in(folder, movie_path) { Some(nfo) } else { None }
+}
+
+fn is_only_movie_in(folder: &Path, movie_path: &Path) -> bool {
+    match fs::read_dir(folder) {
+        Ok(entries) => !entries.filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .any(|path| path != movie_path && is_movie_file(&path)),
+        Err(_) => false,
+    }
+}
+
+/// An element being read, with the attribute telling apart elements of
+/// the same name, like `<uniqueid type="tmdb">`, `<thumb aspect="poster">`
+/// or `<rating name="imdb">`.
+struct Element {
+    name: String,
+    kind: Option<String>,
+    text: String,
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() { None } else { Some(text.to_string()) }
+}
+
+/// The metadata read so far, and the actor or rating being read.
+#[derive(Default)]
+struct NfoReader {
+    metadata: Metadata,
+    actor: Option<Actor>,
+    rating: Option<Rating>,
+}
+
+impl NfoReader {
+    fn start(&mut self, path: &str) {
+        match path {
+            "movie/actor" => self.actor = Some(Actor { name: String::new(), role: None }),
+            "movie/ratings/rating" => self.rating = Some(Rating { source: String::new(), value: 0.0, votes: None }),
+            _ => (),
+        }
+    }
+
+    fn end(&mut self, path: &str, kind: Option<&str>, text: &str) {
+        let metadata = &mut self.metadata;
+        match path {
+            "movie/actor" => {
+                if let Some(actor) = self.actor.take() {
+                    if !actor.name.is_empty() {
+                        metadata.actors.push(actor);
+                    }
+                }
+                return;
+            },
+            "movie/ratings/rating" => {
+                if let Some(mut rating) = self.rating.take() {
+                    rating.source = kind.unwrap_or("default").to_string();
+                    if rating.value > 0.0 {
+                        metadata.ratings.push(rating);
+                    }
+                }
+                return;
+            },
+            _ => (),
+        }
+        let value = match non_empty(text) {
+            Some(value) => value,
+            None => return,
+        };
+        match (path, kind) {
+            ("movie/title", _) => metadata.title = Some(value),
+            ("movie/year", _) => metadata.year = value.parse().ok(),
+            ("movie/plot", _) => metadata.overview = Some(value),
+            ("movie/outline", _) if metadata.overview.is_none() => metadata.overview = Some(value),
+            ("movie/premiered", _) | ("movie/releasedate", _) => metadata.release_date = NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok(),
+            ("movie/thumb", Some("poster")) | ("movie/thumb", None) if metadata.poster.is_none() => metadata.poster = Some(value),
+            ("movie/fanart/thumb", _) if metadata.backdrop.is_none() => metadata.backdrop = Some(value),
+            ("movie/uniqueid", Some("tmdb")) | ("movie/tmdbid", _) => metadata.tmdb_id = value.parse().ok(),
+            ("movie/uniqueid", Some("imdb")) | ("movie/imdbid", _) => metadata.imdb_id = Some(value),
+            ("movie/id", _) if value.starts_with("tt") => metadata.imdb_id = Some(value),
+            ("movie/genre", _) => metadata.genres.extend(value.split('/').map(|genre| genre.trim().to_string()).filter(|genre| !genre.is_empty())),
+            ("movie/actor/name", _) => if let Some(ref mut actor) = self.actor { actor.name = value },
+            ("movie/actor/role", _) => if let Some(ref mut actor) = self.actor { actor.role = Some(value) },
+            ("movie/ratings/rating/value", _) => if let Some(ref mut rating) = self.rating { rating.value = value.parse().unwrap_or(0.0) },
+            ("movie/ratings/rating/votes", _) => if let Some(ref mut rating) = self.rating { rating.votes = value.replace(',', "").parse().ok() },
+            // Older NFOs have a single rating and vote count
+            ("movie/rating", _) => if let Ok(rating) = value.parse() {
+                metadata.ratings.push(Rating { source: "default".to_string(), value: rating, votes: None });
+            },
+            ("movie/votes", _) => if let Some(rating) = metadata.ratings.iter_mut().find(|rating| rating.source == "default") {
+                rating.votes = value.replace(',', "").parse().ok();
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Some NFO files are only a link to the movie's IMDb page.
+fn imdb_link(contents: &str) -> Option<Metadata> {
+    lazy_static! {
+        static ref IMDB_ID: Regex = Regex::new(r"\btt\d{7,8}\b").unwrap();
+    }
+    IMDB_ID.find(contents).map(|id| Metadata { imdb_id: Some(id.as_str().to_string()), ..Metadata::default() })
+}
+
+/// The metadata in an NFO file, or None if it has none for a movie.
+pub fn parse(contents: &str) -> Option<Metadata> {
+    let mut reader = NfoReader::default();
+    let mut elements : Vec<Element> = Vec::new();
+    let mut found = false;
+    for event in EventReader::new(contents.as_bytes()) {
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                let kind = attributes.into_iter()
+                    .find(|attribute| ["type", "aspect", "name"].contains(&attribute.name.local_name.as_str()))
+                    .map(|attribute| attribute.value);
+                elements.push(Element { name: name.local_name, kind: kind, text: String::new() });
+                reader.start(&element_path(&elements));
+            },
+            Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::CData(text)) => {
+                if let Some(element) = elements.last_mut() {
+                    element.text.push_str(&text);
+                }
+            },
+            Ok(XmlEvent::EndElement { .. }) => {
+                let path = element_path(&elements);
+                if let Some(element) = elements.pop() {
+                    found = found || path == "movie";
+                    reader.end(&path, element.kind.as_ref().map(String::as_str), &element.text);
+                }
+            },
+            Ok(_) => (),
+            Err(_) => return imdb_link(contents),
+        }
+    }
+    if found { Some(reader.metadata) } else { imdb_link(contents) }
+}
+
+fn element_path(elements: &[Element]) -> String {
+    elements.iter().map(|element| element.name.as_str()).collect::<Vec<_>>().join("/")
+}
+
+/// When the NFO was last written, in seconds since the epoch.
+pub fn modified(nfo: &Path) -> Option<i64> {
+    fs::metadata(nfo).and_then(|metadata| metadata.modified()).ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs() as i64)
+}
+
+fn text_element<'a, W, E>(writer: &mut EventWriter<W>, element: E, text: &str) -> WriterResult<()>
+    where W: Write, E: Into<WriteEvent<'a>>
+{
+    writer.write(element)?;
+    writer.write(WriteEvent::characters(text))?;
+    writer.write(WriteEvent::end_element())
+}
+
+/// Writes the metadata as a Kodi movie NFO.
+pub fn write<W: Write>(metadata: &Metadata, out: W) -> WriterResult<()> {
+    let mut writer = EmitterConfig::new().perform_indent(true).create_writer(out);
+    writer.write(WriteEvent::start_element("movie"))?;
+    if let Some(ref title) = metadata.title {
+        text_element(&mut writer, WriteEvent::start_element("title"), title)?;
+    }
+    if let Some(year) = metadata.year {
+        text_element(&mut writer, WriteEvent::start_element("year"), &year.to_string())?;
+    }
+    if let Some(ref overview) = metadata.overview {
+        text_element(&mut writer, WriteEvent::start_element("plot"), overview)?;
+    }
+    if let Some(date) = metadata.release_date {
+        text_element(&mut writer, WriteEvent::start_element("premiered"), &date.to_string())?;
+    }
+    if let Some(ref poster) = metadata.poster {
+        text_element(&mut writer, WriteEvent::start_element("thumb").attr("aspect", "poster"), poster)?;
+    }
+    if let Some(ref backdrop) = metadata.backdrop {
+        writer.write(WriteEvent::start_element("fanart"))?;
+        text_element(&mut writer, WriteEvent::start_element("thumb"), backdrop)?;
+        writer.write(WriteEvent::end_element())?;
+    }
+    if let Some(ref imdb_id) = metadata.imdb_id {
+        text_element(&mut writer, WriteEvent::start_element("uniqueid").attr("type", "imdb").attr("default", "true"), imdb_id)?;
+    }
+    if let Some(tmdb_id) = metadata.tmdb_id {
+        let tmdb_id = tmdb_id.to_string();
+        let uniqueid = WriteEvent::start_element("uniqueid").attr("type", "tmdb");
+        let uniqueid = if metadata.imdb_id.is_none() { uniqueid.attr("default", "true") } else { uniqueid };
+        text_element(&mut writer, uniqueid, &tmdb_id)?;
+    }
+    for genre in &metadata.genres {
+        text_element(&mut writer, WriteEvent::start_element("genre"), genre)?;
+    }
+    if !metadata.ratings.is_empty() {
+        writer.write(WriteEvent::start_element("ratings"))?;
+        for (index, rating) in metadata.ratings.iter().enumerate() {
+            let default = if index == 0 { "true" } else { "false" };
+            writer.write(WriteEvent::start_element("rating").attr("name", &rating.source).attr("max", "10").attr("default", default))?;
+            text_element(&mut writer, WriteEvent::start_element("value"), &rating.value.to_string())?;
+            if let Some(votes) = rating.votes {
+                text_element(&mut writer, WriteEvent::start_element("votes"), &votes.to_string())?;
+            }
+            writer.write(WriteEvent::end_element())?;
+        }
+        writer.write(WriteEvent::end_element())?;
+    }
+    for (index, actor) in metadata.actors.iter().enumerate() {
+        writer.write(WriteEvent::start_element("actor"))?;
+        text_element(&mut writer, WriteEvent::start_element("name"), &actor.name)?;
+        if let Some(ref role) = actor.role {
+            text_element(&mut writer, WriteEvent::start_element("role"), role)?;
+        }
+        text_element(&mut writer, WriteEvent::start_element("order"), &index.to_string())?;
+        writer.write(WriteEvent::end_element())?;
+    }
+    writer.write(WriteEvent::end_element())
+}
+
+/// Counts of what an export did.
+#[derive(Debug, Default)]
+pub struct ExportStats {
+    pub written: usize,
+    pub kept: usize,
+    pub errors: usize,
+}
+
+/// Writes an NFO next to every available movie with metadata, named after
+/// the movie's file. Existing NFOs are likely hand curated and are only
+/// replaced with `overwrite`.
+pub fn export(conn: &SqliteConnection, overwrite: bool) -> ExportStats {
+    let mut stats = ExportStats::default();
+    for movie in available_movies(conn) {
+        let metadata = match get_movie_metadata(conn, movie.id) {
+            Some(metadata) => Metadata::from_model(metadata, &movie.title),
+            None => continue,
+        };
+        let movie_path = Path::new(&movie.file_path);
+        if !overwrite && nfo_path(movie_path).is_some() {
+            stats.kept += 1;
+            continue;
+        }
+        let nfo = movie_path.with_extension("nfo");
+        let written = File::create(&nfo)
+            .map_err(|err| err.to_string())
+            .and_then(|file| write(&metadata, file).map_err(|err| err.to_string()));
+        match written {
+            Ok(()) => stats.written += 1,
+            Err(err) => {
+                error!("Could not write {}: {}", nfo.display(), err);
+                stats.errors += 1;
+            },
+        }
+    }
+    stats
+}
+
+impl MetadataProvider for Nfo {
+    fn name(&self) -> &'static str {
+        "nfo"
+    }
+
+    fn movie(&self, query: &MovieQuery) -> Result<Option<Metadata>, Box<Error>> {
+        let path = match nfo_path(query.path) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut contents = String::new();
+        File::open(&path)?.read_to_string(&mut contents)?;
+        Ok(parse(&contents))
+    }
+}
+
+#[test]
+fn kodi_movies() {
+    let metadata = parse(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+<movie>
+    <title>Heat</title>
+    <originaltitle>Heat</originaltitle>
+    <year>1995</year>
+    <outline>A short outline.</outline>
+    <plot>Obsessive master thief Neil McCauley leads a top-notch crew.</plot>
+    <thumb aspect="banner">https://images.example.com/heat-banner.jpg</thumb>
+    <thumb aspect="poster">https://images.example.com/heat-poster.jpg</thumb>
+    <fanart>
+        <thumb>https://images.example.com/heat-fanart.jpg</thumb>
+    </fanart>
+    <id>tt0113277</id>
+    <uniqueid type="tmdb">949</uniqueid>
+    <premiered>1995-12-15</premiered>
+    <genre>Crime</genre>
+    <genre>Drama / Thriller</genre>
+    <ratings>
+        <rating name="imdb" max="10" default="true">
+            <value>8.3</value>
+            <votes>612,523</votes>
+        </rating>
+        <rating name="themoviedb" max="10">
+            <value>7.9</value>
+        </rating>
+    </ratings>
+    <actor><name>Al Pacino</name><role>Vincent Hanna</role><order>0</order></actor>
+    <actor><name>Robert De Niro</name><role>Neil McCauley</role><order>1</order></actor>
+</movie>"#).unwrap();
+    assert_eq!(Metadata {
+        title: Some("Heat".to_string()),
+        year: Some(1995),
+        overview: Some("Obsessive master thief Neil McCauley leads a top-notch crew.".to_string()),
+        release_date: Some(NaiveDate::from_ymd(1995, 12, 15)),
+        poster: Some("https://images.example.com/heat-poster.jpg".to_string()),
+        backdrop: Some("https://images.example.com/heat-fanart.jpg".to_string()),
+        tmdb_id: Some(949),
+        imdb_id: Some("tt0113277".to_string()),
+        genres: vec!["Crime".to_string(), "Drama".to_string(), "Thriller".to_string()],
+        actors: vec![
+            Actor { name: "Al Pacino".to_string(), role: Some("Vincent Hanna".to_string()) },
+            Actor { name: "Robert De Niro".to_string(), role: Some("Neil McCauley".to_string()) },
+        ],
+        ratings: vec![
+            Rating { source: "imdb".to_string(), value: 8.3, votes: Some(612523) },
+            Rating { source: "themoviedb".to_string(), value: 7.9, votes: None },
+        ],
+    }, metadata);
+}
+
+#[test]
+fn older_single_ratings() {
+    let metadata = parse("<movie><title>Heat</title><rating>8.2</rating><votes>1000</votes></movie>").unwrap();
+    assert_eq!(vec![Rating { source: "default".to_string(), value: 8.2, votes: Some(1000) }], metadata.ratings);
+}
+
+#[test]
+fn exported_nfos_read_back_the_same() {
+    let metadata = Metadata {
+        title: Some("Heat & Dust".to_string()),
+        year: Some(1983),
+        overview: Some("Two stories <of> India.".to_string()),
+        release_date: Some(NaiveDate::from_ymd(1983, 2, 3)),
+        poster: Some("https://images.example.com/poster.jpg".to_string()),
+        backdrop: Some("/movies/Heat and Dust/fanart.jpg".to_string()),
+        tmdb_id: Some(42),
+        imdb_id: Some("tt0085672".to_string()),
+        genres: vec!["Drama".to_string(), "Romance".to_string()],
+        actors: vec![Actor { name: "Julie Christie".to_string(), role: Some("Anne".to_string()) }],
+        ratings: vec![Rating { source: "imdb".to_string(), value: 6.6, votes: Some(3000) }],
+    };
+    let mut nfo = Vec::new();
+    write(&metadata, &mut nfo).unwrap();
+    let nfo = String::from_utf8(nfo).unwrap();
+    assert!(nfo.contains("<uniqueid type=\"imdb\" default=\"true\">tt0085672</uniqueid>"), "{}", nfo);
+    assert_eq!(Some(metadata), parse(&nfo));
+}
+
+#[test]
+fn links_and_other_files() {
+    assert_eq!(Some("tt0113277".to_string()), parse("https://www.imdb.com/title/tt0113277/\n").unwrap().imdb_id);
+    assert_eq!(None, parse("<tvshow><title>Doctor Who</title></tvshow>"));
+    assert_eq!(None, parse("Ripped by someone"));
+}
+
+#[test]
+fn nfo_files_next_to_movies() {
+    let dir = ::tempdir::TempDir::new("carolus_nfo").unwrap();
+    let movie = dir.path().join("Heat (1995).mkv");
+    assert_eq!(None, nfo_path(&movie));
+    File::create(dir.path().join("movie.nfo")).unwrap().write_all(b"<movie><title>Heat</title></movie>").unwrap();
+    assert_eq!(Some(dir.path().join("movie.nfo")), nfo_path(&movie));
+    // Shared with another movie, so it could be for either
+    File::create(dir.path().join("Heat (1986).mkv")).unwrap();
+    assert_eq!(None, nfo_path(&movie));
+    File::create(dir.path().join("Heat (1995).nfo")).unwrap().write_all(b"<movie><title>Heat</title></movie>").unwrap();
+    assert_eq!(Some(dir.path().join("Heat (1995).nfo")), nfo_path(&movie));
+
+    let query = MovieQuery { path: &movie, title: "Heat", year: None };
+    assert_eq!(Some("Heat".to_string()), Nfo.movie(&query).unwrap().unwrap().title);
+}
@@ -0,0 +1,197 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::env;
+use std::error::Error;
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use url::Url;
+
+use partial_file::throttle::TokenBucket;
+use provider::{Actor, Metadata, MetadataProvider, MovieQuery, Rating};
+use provider::http::HttpClient;
+
+const DEFAULT_URL: &'static str = "https://www.omdbapi.com";
+
+/// OMDb only publishes a daily limit, so this just keeps a polite pace.
+const REQUESTS_PER_SECOND: u64 = 5;
+
+lazy_static! {
+    static ref RATE_LIMIT: Arc<TokenBucket> = Arc::new(TokenBucket::new(REQUESTS_PER_SECOND));
+}
+
+/// An OMDb title lookup. Missing values are `"N/A"` rather than absent,
+/// and a failed lookup is still a 200 with `Response` set to `"False"`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Movie {
+    pub response: String,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub year: Option<String>,
+    #[serde(default)]
+    pub released: Option<String>,
+    #[serde(default)]
+    pub plot: Option<String>,
+    #[serde(default)]
+    pub poster: Option<String>,
+    #[serde(default, rename = "imdbID")]
+    pub imdb_id: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub actors: Option<String>,
+    #[serde(default, rename = "imdbRating")]
+    pub imdb_rating: Option<String>,
+    #[serde(default, rename = "imdbVotes")]
+    pub imdb_votes: Option<String>,
+}
+
+fn known(value: Option<String>) -> Option<String> {
+    value.and_then(|value| if value.is_empty() || value == "N/A" { None } else { Some(value) })
+}
+
+/// OMDb's lists are comma separated, `Crime, Drama, Thriller`.
+fn list(value: Option<String>) -> Vec<String> {
+    match known(value) {
+        Some(value) => value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect(),
+        None => Vec::new(),
+    }
+}
+
+impl Movie {
+    pub fn into_metadata(self) -> Metadata {
+        let votes = known(self.imdb_votes).and_then(|votes| votes.replace(',', "").parse().ok());
+        let rating = known(self.imdb_rating).and_then(|rating| rating.parse().ok()).map(|value| Rating {
+            source: "imdb".to_string(),
+            value: value,
+            votes: votes,
+        });
+        Metadata {
+            title: known(self.title),
+            // Series have years like `2005–`
+            year: known(self.year).and_then(|year| year.chars().take(4).collect::<String>().parse().ok()),
+            overview: known(self.plot),
+            release_date: known(self.released).and_then(|released| NaiveDate::parse_from_str(&released, "%d %b %Y").ok()),
+            poster: known(self.poster),
+            backdrop: None,
+            tmdb_id: None,
+            imdb_id: known(self.imdb_id),
+            genres: list(self.genre),
+            actors: list(self.actors).into_iter().map(|name| Actor { name: name, role: None }).collect(),
+            ratings: rating.into_iter().collect(),
+        }
+    }
+}
+
+/// A client for OMDb and services with the same API. The base URL can be
+/// changed with `OMDB_URL`.
+pub struct Omdb {
+    http: Arc<HttpClient>,
+    limit: Arc<TokenBucket>,
+    base_url: String,
+    api_key: String,
+}
+
+impl Omdb {
+    /// A client with its own connection and rate limit, and no cache.
+    pub fn new(base_url: &str, api_key: &str) -> Omdb {
+        let limit = Arc::new(TokenBucket::new(REQUESTS_PER_SECOND));
+        Omdb::with_client(base_url, api_key, Arc::new(HttpClient::new(None)), limit)
+    }
+
+    pub fn with_client(base_url: &str, api_key: &str, http: Arc<HttpClient>, limit: Arc<TokenBucket>) -> Omdb {
+        Omdb {
+            http: http,
+            limit: limit,
+            base_url: base_url.trim_right_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// The client configured from the environment, if there is an API key,
+    /// using the shared cache and rate limit.
+    pub fn from_env() -> Option<Omdb> {
+        let key = match env::var("OMDB_API_KEY") {
+            Ok(key) => key,
+            Err(_) => return None,
+        };
+        let base_url = env::var("OMDB_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+        Some(Omdb::with_client(&base_url, &key, HttpClient::shared(), RATE_LIMIT.clone()))
+    }
+
+    pub fn find_movie(&self, movie_name: &str, year: Option<i32>) -> Result<Option<Movie>, Box<Error>> {
+        let mut params = vec![("apikey", self.api_key.clone()), ("t", movie_name.to_string()), ("type", "movie".to_string())];
+        if let Some(year) = year {
+            params.push(("y", year.to_string()));
+        }
+        let url = Url::parse_with_params(&format!("{}/", self.base_url), &params)?;
+        let movie : Movie = self.http.get_json(&url, &self.limit)?;
+        if movie.response == "True" {
+            return Ok(Some(movie));
+        }
+        match movie.error {
+            Some(ref error) if error.ends_with("not found!") => Ok(None),
+            Some(error) => Err(error.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl MetadataProvider for Omdb {
+    fn name(&self) -> &'static str {
+        "omdb"
+    }
+
+    fn movie(&self, query: &MovieQuery) -> Result<Option<Metadata>, Box<Error>> {
+        Ok(self.find_movie(query.title, query.year)?.map(Movie::into_metadata))
+    }
+}
+
+#[test]
+fn looks_up_movies_by_title_and_year() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(200, r#"{
+        "Title": "Heat",
+        "Year": "1995",
+        "Released": "15 Dec 1995",
+        "Plot": "A group of professional bank robbers start to feel the heat from police.",
+        "Poster": "https://images.example.com/heat.jpg",
+        "imdbID": "tt0113277",
+        "Genre": "Crime, Drama, Thriller",
+        "Actors": "Al Pacino, Robert De Niro",
+        "imdbRating": "8.3",
+        "imdbVotes": "612,523",
+        "Response": "True"
+    }"#);
+    let metadata = Omdb::new(&server.url, "secret").find_movie("Heat", Some(1995)).unwrap().unwrap().into_metadata();
+    assert_eq!(Some("Heat".to_string()), metadata.title);
+    assert_eq!(Some(1995), metadata.year);
+    assert_eq!(Some(NaiveDate::from_ymd(1995, 12, 15)), metadata.release_date);
+    assert_eq!(Some("tt0113277".to_string()), metadata.imdb_id);
+    assert_eq!(vec!["Crime", "Drama", "Thriller"], metadata.genres);
+    assert_eq!(vec![Actor { name: "Al Pacino".to_string(), role: None }, Actor { name: "Robert De Niro".to_string(), role: None }], metadata.actors);
+    assert_eq!(vec![Rating { source: "imdb".to_string(), value: 8.3, votes: Some(612523) }], metadata.ratings);
+    assert_eq!(vec!["GET /?apikey=secret&t=Heat&type=movie&y=1995".to_string()], server.requests());
+}
+
+#[test]
+fn missing_values_and_movies() {
+    use provider::mock_server::MockServer;
+    let movie : Movie = ::serde_json::from_str(r#"{"Title": "Heat", "Year": "1995", "Poster": "N/A", "Released": "N/A", "Response": "True"}"#).unwrap();
+    let metadata = movie.into_metadata();
+    assert_eq!((None, None), (metadata.poster, metadata.release_date));
+
+    let server = MockServer::start(200, r#"{"Response": "False", "Error": "Movie not found!"}"#);
+    assert!(Omdb::new(&server.url, "secret").find_movie("Not A Real Movie", None).unwrap().is_none());
+
+    let server = MockServer::start(200, r#"{"Response": "False", "Error": "Invalid API key!"}"#);
+    assert!(Omdb::new(&server.url, "wrong").find_movie("Heat", None).is_err());
+}
@@ -1,46 +1,207 @@
 use std::env;
 use std::result::Result;
 use std::error::Error;
+use std::sync::Arc;
 
-use reqwest::Client;
 use url::Url;
-use chrono::Date;
-use chrono::offset::Utc;
+use chrono::{Datelike, NaiveDate};
+
+use partial_file::throttle::TokenBucket;
+use provider::{Metadata, MetadataProvider, MovieQuery};
+use provider::http::HttpClient;
+
+const DEFAULT_URL: &'static str = "https://api.themoviedb.org/3";
+
+/// Where TheMovieDB serves the original size of poster and backdrop paths.
+const IMAGE_URL: &'static str = "https://image.tmdb.org/t/p/original";
+
+/// TheMovieDB allows 40 requests every 10 seconds.
+const REQUESTS_PER_SECOND: u64 = 4;
+
+lazy_static! {
+    // The limit is per API key, so every lookup shares one
+    static ref RATE_LIMIT: Arc<TokenBucket> = Arc::new(TokenBucket::new(REQUESTS_PER_SECOND));
+}
 
 #[derive(Deserialize)]
 pub struct Response<T> {
     pub results: Vec<T>
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 pub struct Movie {
+    pub id: i32,
     pub title: String,
-    pub poster_path: String,
-    pub overview: String,
-    pub backdrop_path: String,
-    #[serde(with = "my_date_format")]
-    pub release_date: Date<Utc>,
+    #[serde(default)]
+    pub poster_path: Option<String>,
+    #[serde(default)]
+    pub overview: Option<String>,
+    #[serde(default)]
+    pub backdrop_path: Option<String>,
+    #[serde(default, with = "my_date_format")]
+    pub release_date: Option<NaiveDate>,
 }
 
-pub fn find_movie(client: Client, movie_name: String, year: i32) -> Result<Response<Movie>, Box<Error>> {
-    let key = env::var("THE_MOVIE_DB_API_KEY")?;
-    let url = Url::parse_with_params("https://api.themoviedb.org/3/search/movies",
-                &[("api_key", key), ("query", movie_name), ("year", year.to_string())])?;
-    Ok(client.get(url).send()?.json()?)
+/// A client for TheMovieDB's v3 API. The base URL can be changed with
+/// `THE_MOVIE_DB_URL`, to go through a proxy or for tests.
+pub struct TheMovieDb {
+    http: Arc<HttpClient>,
+    limit: Arc<TokenBucket>,
+    base_url: String,
+    api_key: String,
 }
 
+impl TheMovieDb {
+    /// A client with its own connection and rate limit, and no cache.
+    pub fn new(base_url: &str, api_key: &str) -> TheMovieDb {
+        let limit = Arc::new(TokenBucket::new(REQUESTS_PER_SECOND));
+        TheMovieDb::with_client(base_url, api_key, Arc::new(HttpClient::new(None)), limit)
+    }
+
+    pub fn with_client(base_url: &str, api_key: &str, http: Arc<HttpClient>, limit: Arc<TokenBucket>) -> TheMovieDb {
+        TheMovieDb {
+            http: http,
+            limit: limit,
+            base_url: base_url.trim_right_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// The client configured from the environment, if there is an API key,
+    /// using the shared cache and rate limit.
+    pub fn from_env() -> Option<TheMovieDb> {
+        let key = match env::var("THE_MOVIE_DB_API_KEY") {
+            Ok(key) => key,
+            Err(_) => return None,
+        };
+        let base_url = env::var("THE_MOVIE_DB_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+        Some(TheMovieDb::with_client(&base_url, &key, HttpClient::shared(), RATE_LIMIT.clone()))
+    }
+
+    pub fn search_movie(&self, movie_name: &str, year: Option<i32>) -> Result<Response<Movie>, Box<Error>> {
+        let mut params = vec![("api_key", self.api_key.clone()), ("query", movie_name.to_string())];
+        if let Some(year) = year {
+            params.push(("year", year.to_string()));
+        }
+        let url = Url::parse_with_params(&format!("{}/search/movie", self.base_url), &params)?;
+        self.http.get_json(&url, &self.limit)
+    }
+
+    /// The best match for the title, which TheMovieDB puts first.
+    pub fn find_movie(&self, movie_name: &str, year: Option<i32>) -> Result<Option<Movie>, Box<Error>> {
+        Ok(self.search_movie(movie_name, year)?.results.into_iter().next())
+    }
+}
+
+impl Movie {
+    pub fn into_metadata(self) -> Metadata {
+        Metadata {
+            year: self.release_date.map(|date| date.year()),
+            title: Some(self.title),
+            overview: self.overview.and_then(|overview| if overview.is_empty() { None } else { Some(overview) }),
+            release_date: self.release_date,
+            poster: self.poster_path.map(|path| format!("{}{}", IMAGE_URL, path)),
+            backdrop: self.backdrop_path.map(|path| format!("{}{}", IMAGE_URL, path)),
+            tmdb_id: Some(self.id),
+            imdb_id: None,
+            genres: Vec::new(),
+            actors: Vec::new(),
+            ratings: Vec::new(),
+        }
+    }
+}
+
+impl MetadataProvider for TheMovieDb {
+    fn name(&self) -> &'static str {
+        "tmdb"
+    }
+
+    fn movie(&self, query: &MovieQuery) -> Result<Option<Metadata>, Box<Error>> {
+        Ok(self.find_movie(query.title, query.year)?.map(Movie::into_metadata))
+    }
+}
+
+/// Release dates are `YYYY-MM-DD`, or empty or null when TheMovieDB doesn't
+/// know.
 mod my_date_format {
-    use chrono::{Date, TimeZone};
-    use chrono::offset::Utc;
+    use chrono::NaiveDate;
     use serde::{self, Deserialize, Deserializer};
 
     const FORMAT: &'static str = "%Y-%m-%d";
-    
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Date<Utc>, D::Error>
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
         where D: Deserializer<'de>
     {
-        let s = String::deserialize(deserializer)?;
-        let x = Utc.datetime_from_str(&s, FORMAT).map_err(serde::de::Error::custom)?;
-        Ok(x.date())
+        match Option::<String>::deserialize(deserializer)? {
+            Some(ref s) if !s.is_empty() => NaiveDate::parse_from_str(s, FORMAT)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            _ => Ok(None),
+        }
     }
 }
+
+#[test]
+fn searches_movies_by_title_and_year() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(200, r#"{
+        "page": 1,
+        "results": [{
+            "id": 949,
+            "title": "Heat",
+            "overview": "Obsessive master thief Neil McCauley leads a top-notch crew.",
+            "poster_path": "/rrBuGu0Pjq7Y2BWSI6teGfZzviY.jpg",
+            "backdrop_path": null,
+            "release_date": "1995-12-15"
+        }]
+    }"#);
+    let movie = TheMovieDb::new(&server.url, "secret").find_movie("Heat", Some(1995)).unwrap().unwrap();
+    assert_eq!(949, movie.id);
+    assert_eq!("Heat", movie.title);
+    assert_eq!(Some("/rrBuGu0Pjq7Y2BWSI6teGfZzviY.jpg".to_string()), movie.poster_path);
+    assert_eq!(None, movie.backdrop_path);
+    assert_eq!(Some(NaiveDate::from_ymd(1995, 12, 15)), movie.release_date);
+    assert_eq!(vec!["GET /search/movie?api_key=secret&query=Heat&year=1995".to_string()], server.requests());
+}
+
+#[test]
+fn movies_become_metadata() {
+    let movie = Movie {
+        id: 949,
+        title: "Heat".to_string(),
+        poster_path: Some("/heat.jpg".to_string()),
+        overview: Some("".to_string()),
+        backdrop_path: None,
+        release_date: Some(NaiveDate::from_ymd(1995, 12, 15)),
+    };
+    let metadata = movie.into_metadata();
+    assert_eq!(Some(1995), metadata.year);
+    assert_eq!(None, metadata.overview);
+    assert_eq!(Some("https://image.tmdb.org/t/p/original/heat.jpg".to_string()), metadata.poster);
+    assert_eq!(Some(949), metadata.tmdb_id);
+}
+
+#[test]
+fn unknown_movies_have_no_match() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(200, r#"{"page": 1, "results": []}"#);
+    let tmdb = TheMovieDb::new(&format!("{}/", server.url), "secret");
+    assert!(tmdb.find_movie("Not A Real Movie", None).unwrap().is_none());
+    assert_eq!(vec!["GET /search/movie?api_key=secret&query=Not+A+Real+Movie".to_string()], server.requests());
+}
+
+#[test]
+fn missing_release_dates() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(200, r#"{"results": [{"id": 1, "title": "Untitled", "release_date": ""}]}"#);
+    let movie = TheMovieDb::new(&server.url, "secret").find_movie("Untitled", None).unwrap().unwrap();
+    assert_eq!(None, movie.release_date);
+    assert_eq!(None, movie.overview);
+}
+
+#[test]
+fn errors_are_returned() {
+    use provider::mock_server::MockServer;
+    let server = MockServer::start(401, r#"{"status_code": 7, "status_message": "Invalid API key"}"#);
+    assert!(TheMovieDb::new(&server.url, "wrong").find_movie("Heat", None).is_err());
+}
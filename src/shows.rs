@@ -0,0 +1,159 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use rocket::{Route, State};
+use rocket_contrib::JsonValue;
+
+use data::init::establish_connection;
+use data::models;
+use data::shows::{episodes_of, find_episode, find_season, find_show, page_shows, seasons_of};
+use movies::PageRequest;
+use partial_file::{serve_partial, PartialFile};
+use partial_file::throttle::{StreamClient, Throttle};
+
+#[derive(Serialize)]
+pub struct Show {
+    pub id: i32,
+    pub title: String,
+    pub year: Option<i32>,
+    pub details_path: String,
+}
+
+#[derive(Serialize)]
+pub struct Season {
+    pub season: i32,
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Serialize)]
+pub struct Episode {
+    pub id: i32,
+    pub episode: Option<i32>,
+    pub last_episode: Option<i32>,
+    pub absolute: Option<i32>,
+    pub air_date: Option<String>,
+    pub title: Option<String>,
+    pub play_path: String,
+}
+
+impl Episode {
+    fn from_model(episode: models::Episode) -> Episode {
+        Episode {
+            play_path: uri!("/api/shows", play_episode: episode.id).to_string(),
+            id: episode.id,
+            episode: episode.episode_number,
+            last_episode: episode.last_episode_number,
+            absolute: episode.absolute_number,
+            air_date: episode.air_date.map(|date| date.to_string()),
+            title: episode.title,
+        }
+    }
+}
+
+#[get("/")]
+pub fn all_shows_root() -> JsonValue {
+    all_shows(PageRequest{ page: Some(0), count: Some(10)})
+}
+
+#[get("/?<page_request>")]
+pub fn all_shows(page_request: PageRequest) -> JsonValue {
+    let conn = establish_connection();
+    let page = match page_request.page { Some(v) => v, None => 0 };
+    let count = match page_request.count { Some(v) => v, None => 10 };
+
+    let shows = page_shows(&conn, page, count);
+
+    json!({
+        "results": shows.into_iter().map(|s| Show { id: s.id, title: s.title, year: s.year, details_path: uri!("/api/shows", show_details: s.id).to_string() }).collect::<Vec<_>>(),
+    })
+}
+
+/// The show with its seasons in order, each with the episodes that are
+/// available to play.
+#[get("/<show_id>")]
+pub fn show_details(show_id: i32) -> Option<JsonValue> {
+    let conn = establish_connection();
+    let show = match find_show(&conn, show_id) {
+        Some(show) => show,
+        None => return None,
+    };
+    let seasons = seasons_of(&conn, show.id);
+    let season_ids : Vec<i32> = seasons.iter().map(|season| season.id).collect();
+    let mut episodes : HashMap<i32, Vec<Episode>> = HashMap::new();
+    for episode in episodes_of(&conn, &season_ids) {
+        episodes.entry(episode.season_id).or_insert_with(Vec::new).push(Episode::from_model(episode));
+    }
+    let seasons : Vec<Season> = seasons.into_iter()
+        .filter_map(|season| episodes.remove(&season.id).map(|episodes| Season {
+            season: season.season_number,
+            episodes: episodes,
+        }))
+        .collect();
+
+    Some(json!({
+        "id": show.id,
+        "title": show.title,
+        "year": show.year,
+        "seasons": seasons,
+    }))
+}
+
+#[get("/episodes/<episode_id>")]
+pub fn episode_details(episode_id: i32) -> Option<JsonValue> {
+    let conn = establish_connection();
+    let episode = match find_episode(&conn, episode_id) {
+        Some(episode) => episode,
+        None => return None,
+    };
+    let season = match find_season(&conn, episode.season_id) {
+        Some(season) => season,
+        None => return None,
+    };
+    let show = match find_show(&conn, season.show_id) {
+        Some(show) => show,
+        None => return None,
+    };
+    let available = episode.available;
+
+    Some(json!({
+        "show": Show { id: show.id, title: show.title, year: show.year, details_path: uri!("/api/shows", show_details: show.id).to_string() },
+        "season": season.season_number,
+        "available": available,
+        "episode": Episode::from_model(episode),
+    }))
+}
+
+/// The episode, unless its file has gone missing since it was indexed.
+fn playable_episode(episode_id: i32) -> Option<models::Episode> {
+    let conn = establish_connection();
+    find_episode(&conn, episode_id).and_then(|episode| if episode.available { Some(episode) } else { None })
+}
+
+#[get("/play/<episode_id>")]
+pub fn play_episode(episode_id: i32, client: StreamClient, throttle: State<Throttle>) -> io::Result<Option<PartialFile>> {
+    let episode = match playable_episode(episode_id) {
+        Some(episode) => episode,
+        None => return Ok(None),
+    };
+    let partial_file = serve_partial(Path::new(&episode.file_path))?;
+    Ok(Some(partial_file.throttled(throttle.limits_for(&client))))
+}
+
+#[head("/play/<episode_id>")]
+pub fn play_episode_head(episode_id: i32) -> io::Result<Option<PartialFile>> {
+    match playable_episode(episode_id) {
+        Some(episode) => serve_partial(Path::new(&episode.file_path)).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![all_shows_root, all_shows, show_details, episode_details, play_episode, play_episode_head]
+}
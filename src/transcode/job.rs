@@ -0,0 +1,147 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use transcode::profile::{Profile, PLAYLIST_NAME};
+
+const POLL_INTERVAL_MS: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+    Running,
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+/// One encoder process writing a profile of a movie into its own cache
+/// directory.
+#[derive(Debug)]
+pub struct Job {
+    pub movie_id: i32,
+    pub profile: &'static Profile,
+    pub output: PathBuf,
+    state: Mutex<JobState>,
+    child: Mutex<Option<Child>>,
+}
+
+impl Job {
+    /// A job for output that is already complete on disk.
+    pub fn cached(movie_id: i32, profile: &'static Profile, output: PathBuf) -> Arc<Job> {
+        Arc::new(Job {
+            movie_id: movie_id,
+            profile: profile,
+            output: output,
+            state: Mutex::new(JobState::Finished),
+            child: Mutex::new(None),
+        })
+    }
+
+    pub fn spawn(encoder: &Path, movie_id: i32, profile: &'static Profile, input: &Path, output: PathBuf) -> io::Result<Arc<Job>> {
+        if output.exists() {
+            fs::remove_dir_all(&output)?;
+        }
+        fs::create_dir_all(&output)?;
+        let child = Command::new(encoder)
+            .args(profile.encoder_args(input, &output))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        info!("Transcoding movie {} to {} with pid {}", movie_id, profile.name, child.id());
+        let job = Arc::new(Job {
+            movie_id: movie_id,
+            profile: profile,
+            output: output,
+            state: Mutex::new(JobState::Running),
+            child: Mutex::new(Some(child)),
+        });
+        let watched = job.clone();
+        thread::spawn(move || watched.watch());
+        Ok(job)
+    }
+
+    pub fn state(&self) -> JobState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn playlist_path(&self) -> PathBuf {
+        self.output.join(PLAYLIST_NAME)
+    }
+
+    // Polls rather than blocking in wait() so cancel() can still get at the
+    // child to kill it.
+    fn watch(&self) {
+        loop {
+            let status = {
+                let mut child = self.child.lock().unwrap();
+                match child.as_mut().map(Child::try_wait) {
+                    Some(Ok(Some(status))) => Ok(status),
+                    Some(Ok(None)) => Err(None),
+                    Some(Err(err)) => Err(Some(err.to_string())),
+                    None => return,
+                }
+            };
+            let next = match status {
+                Ok(status) if status.success() => JobState::Finished,
+                Ok(status) => JobState::Failed(format!("encoder exited with {}", status)),
+                Err(Some(err)) => JobState::Failed(err),
+                Err(None) => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    continue;
+                },
+            };
+            if let JobState::Failed(ref reason) = next {
+                error!("Transcoding movie {} to {} failed: {}", self.movie_id, self.profile.name, reason);
+                let _ = fs::remove_dir_all(&self.output);
+            }
+            *self.child.lock().unwrap() = None;
+            let mut state = self.state.lock().unwrap();
+            if *state == JobState::Running {
+                *state = next;
+            }
+            return;
+        }
+    }
+
+    pub fn cancel(&self) {
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        *self.state.lock().unwrap() = JobState::Cancelled;
+        let _ = fs::remove_dir_all(&self.output);
+    }
+
+    /// Blocks until the encoder has written its playlist, so there is
+    /// something for the client to start playing.
+    pub fn wait_for_playlist(&self, timeout: Duration) -> io::Result<PathBuf> {
+        let started = Instant::now();
+        let playlist = self.playlist_path();
+        loop {
+            match self.state() {
+                JobState::Failed(reason) => return Err(io::Error::new(io::ErrorKind::Other, reason)),
+                JobState::Cancelled => return Err(io::Error::new(io::ErrorKind::Other, "transcode cancelled")),
+                JobState::Running | JobState::Finished => {
+                    if playlist.exists() {
+                        return Ok(playlist);
+                    }
+                },
+            }
+            if started.elapsed() > timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for the encoder"));
+            }
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+pub mod job;
+pub mod profile;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use rocket::{Route, State};
+use rocket::response::status::NoContent;
+
+use partial_file::{serve_partial, PartialFile};
+use transcode::job::{Job, JobState};
+use transcode::profile::{Profile, PLAYLIST_NAME, SEGMENT_PREFIX};
+
+/// Runs the encoder for movies that need converting before they can be
+/// played, sharing one job between everyone watching the same movie in the
+/// same profile.
+pub struct Transcoder {
+    encoder: PathBuf,
+    cache_dir: PathBuf,
+    jobs: Mutex<HashMap<(i32, &'static str), Arc<Job>>>,
+}
+
+fn is_complete(playlist: &Path) -> bool {
+    let mut contents = String::new();
+    match File::open(playlist).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => contents.contains("#EXT-X-ENDLIST"),
+        Err(_) => false,
+    }
+}
+
+impl Transcoder {
+    pub fn new(encoder: PathBuf, cache_dir: PathBuf) -> Transcoder {
+        Transcoder { encoder: encoder, cache_dir: cache_dir, jobs: Mutex::new(HashMap::new()) }
+    }
+
+    /// Reads `CAROLUS_ENCODER` (default `ffmpeg` from the `PATH`) and
+    /// `CAROLUS_TRANSCODE_CACHE` (default a directory under the system
+    /// temp dir).
+    pub fn from_env() -> Transcoder {
+        let encoder = env::var_os("CAROLUS_ENCODER").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("ffmpeg"));
+        let cache_dir = env::var_os("CAROLUS_TRANSCODE_CACHE").map(PathBuf::from)
+            .unwrap_or_else(|| env::temp_dir().join("carolus-transcode"));
+        Transcoder::new(encoder, cache_dir)
+    }
+
+    /// Output is named after the source file's size and modification time
+    /// as well as the movie, so a file that has been replaced or upgraded is
+    /// transcoded again rather than served the old file's output.
+    pub fn output_dir(&self, movie_id: i32, input: &Path, profile: &Profile) -> io::Result<PathBuf> {
+        let metadata = fs::metadata(input)?;
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        Ok(self.cache_dir.join(format!("{}-{}-{:x}-{:x}", movie_id, profile.name, metadata.len(), modified)))
+    }
+
+    /// Removes output of the movie in the profile left from earlier
+    /// versions of its file.
+    fn remove_stale_output(&self, movie_id: i32, profile: &Profile, current: &Path) {
+        let prefix = format!("{}-{}-", movie_id, profile.name);
+        let entries = match fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let stale = path != current && entry.file_name().to_str().map_or(false, |name| name.starts_with(&prefix));
+            if stale {
+                info!("Removing stale transcode {}", path.display());
+                let _ = fs::remove_dir_all(&path);
+            }
+        }
+    }
+
+    /// The running or finished job for the movie, starting the encoder if
+    /// there is neither one nor complete output left on disk from before.
+    pub fn start(&self, movie_id: i32, input: &Path, profile: &'static Profile) -> io::Result<Arc<Job>> {
+        let output = self.output_dir(movie_id, input, profile)?;
+        let mut jobs = self.jobs.lock().unwrap();
+        let key = (movie_id, profile.name);
+        if let Some(job) = jobs.get(&key) {
+            if job.output != output {
+                // The movie's file has changed since this job started
+                job.cancel();
+            } else {
+                match job.state() {
+                    JobState::Running => return Ok(job.clone()),
+                    JobState::Finished if job.playlist_path().exists() => return Ok(job.clone()),
+                    _ => (),
+                }
+            }
+        }
+        let job = if is_complete(&output.join(PLAYLIST_NAME)) {
+            Job::cached(movie_id, profile, output)
+        } else {
+            self.remove_stale_output(movie_id, profile, &output);
+            Job::spawn(&self.encoder, movie_id, profile, input, output)?
+        };
+        jobs.insert(key, job.clone());
+        Ok(job)
+    }
+
+    /// Where the movie's current job in the profile writes its output. The
+    /// playlist is always requested first, which starts or finds the job.
+    pub fn current_output(&self, movie_id: i32, profile: &Profile) -> Option<PathBuf> {
+        self.jobs.lock().unwrap().get(&(movie_id, profile.name)).map(|job| job.output.clone())
+    }
+
+    pub fn cancel(&self, movie_id: i32, profile: &Profile) -> bool {
+        match self.jobs.lock().unwrap().remove(&(movie_id, profile.name)) {
+            Some(job) => {
+                job.cancel();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Points the segment URIs in an encoder playlist at the segment route, as
+/// the playlist itself is served from the play route.
+pub fn rewrite_playlist(playlist: &str, movie_id: i32, profile: &Profile) -> String {
+    playlist.lines().map(|line| {
+        if line.is_empty() || line.starts_with('#') {
+            line.to_string()
+        } else {
+            format!("/api/movies/transcode/{}/{}/{}", movie_id, profile.name, line)
+        }
+    }).collect::<Vec<_>>().join("\n") + "\n"
+}
+
+fn is_segment_name(name: &str) -> bool {
+    name.starts_with(SEGMENT_PREFIX) && name.ends_with(".ts") && !name.contains('/') && !name.contains("..")
+}
+
+#[get("/transcode/<movie_id>/<profile>/<segment>")]
+pub fn transcoded_segment(movie_id: i32, profile: String, segment: String, transcoder: State<Transcoder>) -> io::Result<Option<PartialFile>> {
+    let profile = match Profile::find(&profile) {
+        Some(profile) if is_segment_name(&segment) => profile,
+        _ => return Ok(None),
+    };
+    let path = match transcoder.current_output(movie_id, profile) {
+        Some(output) => output.join(segment),
+        None => return Ok(None),
+    };
+    if !path.is_file() {
+        return Ok(None);
+    }
+    serve_partial(&path).map(Some)
+}
+
+#[delete("/transcode/<movie_id>/<profile>")]
+pub fn cancel_transcode(movie_id: i32, profile: String, transcoder: State<Transcoder>) -> Option<NoContent> {
+    match Profile::find(&profile) {
+        Some(profile) if transcoder.cancel(movie_id, profile) => Some(NoContent),
+        _ => None,
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![transcoded_segment, cancel_transcode]
+}
+
+#[cfg(all(test, unix))]
+fn stub_encoder(dir: &Path, script: &str) -> Transcoder {
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    let encoder = dir.join("encoder");
+    File::create(&encoder).unwrap().write_all(script.as_bytes()).unwrap();
+    fs::set_permissions(&encoder, fs::Permissions::from_mode(0o755)).unwrap();
+    Transcoder::new(encoder, dir.join("cache"))
+}
+
+#[cfg(all(test, unix))]
+fn source_file(dir: &Path, contents: &str) -> PathBuf {
+    use std::io::Write;
+    let path = dir.join("movie.mkv");
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    path
+}
+
+#[cfg(all(test, unix))]
+const WORKING_ENCODER: &'static str = "#!/bin/sh
+for last; do :; done
+dir=$(dirname \"$last\")
+printf 'segment' > \"$dir/segment00000.ts\"
+printf '#EXTM3U\\n#EXTINF:6.0,\\nsegment00000.ts\\n#EXT-X-ENDLIST\\n' > \"$last\"
+";
+
+#[cfg(all(test, unix))]
+fn wait_until_done(job: &Job) -> JobState {
+    use std::thread;
+    use std::time::Duration;
+    for _ in 0..100 {
+        match job.state() {
+            JobState::Running => thread::sleep(Duration::from_millis(50)),
+            state => return state,
+        }
+    }
+    job.state()
+}
+
+#[cfg(unix)]
+#[test]
+fn stub_encoder_output_is_cached() {
+    use std::time::Duration;
+    let dir = ::tempdir::TempDir::new("carolus_transcode").unwrap();
+    let transcoder = stub_encoder(dir.path(), WORKING_ENCODER);
+    let profile = Profile::find("720p").unwrap();
+    let source = source_file(dir.path(), "movie");
+    let job = transcoder.start(1, &source, profile).unwrap();
+    let playlist = job.wait_for_playlist(Duration::from_secs(5)).unwrap();
+    assert_eq!(JobState::Finished, wait_until_done(&job));
+    assert!(is_complete(&playlist));
+    assert!(transcoder.current_output(1, profile).unwrap().join("segment00000.ts").is_file());
+
+    // A fresh transcoder picks the finished output up from disk.
+    let restarted = Transcoder::new(dir.path().join("missing-encoder"), dir.path().join("cache"));
+    let cached = restarted.start(1, &source, profile).unwrap();
+    assert_eq!(JobState::Finished, cached.state());
+}
+
+#[cfg(unix)]
+#[test]
+fn replaced_sources_are_transcoded_again() {
+    use std::time::Duration;
+    let dir = ::tempdir::TempDir::new("carolus_transcode").unwrap();
+    let transcoder = stub_encoder(dir.path(), WORKING_ENCODER);
+    let profile = Profile::find("720p").unwrap();
+    let job = transcoder.start(5, &source_file(dir.path(), "movie"), profile).unwrap();
+    job.wait_for_playlist(Duration::from_secs(5)).unwrap();
+    assert_eq!(JobState::Finished, wait_until_done(&job));
+
+    let upgraded = transcoder.start(5, &source_file(dir.path(), "a better encode"), profile).unwrap();
+    assert!(upgraded.output != job.output);
+    upgraded.wait_for_playlist(Duration::from_secs(5)).unwrap();
+    assert_eq!(JobState::Finished, wait_until_done(&upgraded));
+    assert!(!job.output.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_encoder_discards_output() {
+    use std::time::Duration;
+    let dir = ::tempdir::TempDir::new("carolus_transcode").unwrap();
+    let transcoder = stub_encoder(dir.path(), "#!/bin/sh\nexit 1\n");
+    let profile = Profile::find("480p").unwrap();
+    let job = transcoder.start(2, &source_file(dir.path(), "movie"), profile).unwrap();
+    match wait_until_done(&job) {
+        JobState::Failed(_) => (),
+        other => panic!("{:?}", other),
+    }
+    assert!(job.wait_for_playlist(Duration::from_secs(1)).is_err());
+    assert!(!job.output.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn cancel_stops_the_encoder() {
+    let dir = ::tempdir::TempDir::new("carolus_transcode").unwrap();
+    let transcoder = stub_encoder(dir.path(), "#!/bin/sh\nexec sleep 30\n");
+    let profile = Profile::find("1080p").unwrap();
+    let job = transcoder.start(3, &source_file(dir.path(), "movie"), profile).unwrap();
+    assert_eq!(JobState::Running, job.state());
+    assert!(transcoder.cancel(3, profile));
+    assert_eq!(JobState::Cancelled, job.state());
+    assert!(!job.output.exists());
+    assert!(!transcoder.cancel(3, profile));
+}
+
+#[test]
+fn playlist_segments_point_at_segment_route() {
+    let profile = Profile::find("720p").unwrap();
+    let playlist = "#EXTM3U\n#EXTINF:6.0,\nsegment00000.ts\n";
+    assert_eq!(
+        "#EXTM3U\n#EXTINF:6.0,\n/api/movies/transcode/4/720p/segment00000.ts\n",
+        rewrite_playlist(playlist, 4, profile));
+}
+
+#[test]
+fn only_segment_names_are_served() {
+    assert!(is_segment_name("segment00001.ts"));
+    assert!(!is_segment_name("index.m3u8"));
+    assert!(!is_segment_name("segment..ts"));
+}
@@ -0,0 +1,62 @@
+// Copyright (c) 2017 Simon Dickson
+// 
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+pub const PLAYLIST_NAME: &'static str = "index.m3u8";
+pub const SEGMENT_PREFIX: &'static str = "segment";
+
+/// Target output for the encoder, always H.264/AAC in MPEG-TS HLS segments
+/// as that is what every client we have can direct play.
+#[derive(Debug, PartialEq)]
+pub struct Profile {
+    pub name: &'static str,
+    pub height: u32,
+    pub video_bitrate: &'static str,
+    pub audio_bitrate: &'static str,
+}
+
+pub static PROFILES: &'static [Profile] = &[
+    Profile { name: "1080p", height: 1080, video_bitrate: "8000k", audio_bitrate: "192k" },
+    Profile { name: "720p", height: 720, video_bitrate: "4000k", audio_bitrate: "160k" },
+    Profile { name: "480p", height: 480, video_bitrate: "1500k", audio_bitrate: "128k" },
+];
+
+impl Profile {
+    pub fn find(name: &str) -> Option<&'static Profile> {
+        PROFILES.iter().find(|profile| profile.name == name)
+    }
+
+    /// ffmpeg arguments to encode `input` into an HLS event playlist in
+    /// `output`. The playlist is the last argument, stub encoders used in
+    /// tests rely on that.
+    pub fn encoder_args(&self, input: &Path, output: &Path) -> Vec<OsString> {
+        let mut args : Vec<OsString> = Vec::new();
+        args.push("-nostdin".into());
+        args.push("-y".into());
+        args.push("-i".into());
+        args.push(input.as_os_str().to_owned());
+        for arg in &["-map", "0:v:0", "-map", "0:a:0?", "-c:v", "libx264", "-preset", "veryfast", "-profile:v", "high"] {
+            args.push(arg.into());
+        }
+        args.push("-vf".into());
+        args.push(format!("scale=-2:'min({},ih)'", self.height).into());
+        args.push("-b:v".into());
+        args.push(self.video_bitrate.into());
+        for arg in &["-c:a", "aac", "-ac", "2"] {
+            args.push(arg.into());
+        }
+        args.push("-b:a".into());
+        args.push(self.audio_bitrate.into());
+        for arg in &["-f", "hls", "-hls_time", "6", "-hls_playlist_type", "event", "-hls_segment_filename"] {
+            args.push(arg.into());
+        }
+        args.push(output.join(format!("{}%05d.ts", SEGMENT_PREFIX)).into_os_string());
+        args.push(output.join(PLAYLIST_NAME).into_os_string());
+        args
+    }
+}